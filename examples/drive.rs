@@ -0,0 +1,113 @@
+//! Drive a server connection while handling its requests on the same task
+//!
+//! `accept()` is what reads the client's control and QPACK streams, so it has
+//! to keep being polled while requests are in progress. The `server` example
+//! spawns a task per request for that, this one polls the accept loop and the
+//! requests in flight together with `select!`.
+//!
+//! Start it with `cargo run --example drive`, then send it a request with
+//! `cargo run --example client -- https://localhost:4433/hello`.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use bytes::Bytes;
+use futures::{stream::FuturesUnordered, StreamExt};
+use http::{Request, Response, StatusCode};
+use rustls::{Certificate, PrivateKey};
+use tracing::{error, info};
+
+use h3::{error::ErrorLevel, quic::BidiStream, server::RequestStream};
+use h3_quinn::quinn;
+
+static ALPN: &[u8] = b"h3";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let cert = Certificate(std::fs::read("examples/server.cert")?);
+    let key = PrivateKey(std::fs::read("examples/server.key")?);
+
+    let mut tls_config = rustls::ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert], key)?;
+    tls_config.alpn_protocols = vec![ALPN.into()];
+
+    let listen: SocketAddr = "[::1]:4433".parse()?;
+    let server_config = quinn::ServerConfig::with_crypto(Arc::new(tls_config));
+    let endpoint = quinn::Endpoint::server(server_config, listen)?;
+
+    info!("listening on {}", listen);
+
+    while let Some(new_conn) = endpoint.accept().await {
+        tokio::spawn(async move {
+            match new_conn.await {
+                Ok(conn) => {
+                    if let Err(err) = serve(h3_quinn::Connection::new(conn)).await {
+                        error!("connection failed: {}", err);
+                    }
+                }
+                Err(err) => error!("accepting connection failed: {:?}", err),
+            }
+        });
+    }
+
+    endpoint.wait_idle().await;
+
+    Ok(())
+}
+
+/// Serve all the requests of a connection from a single task
+async fn serve(conn: h3_quinn::Connection) -> Result<(), h3::Error> {
+    let mut h3_conn = h3::server::Connection::new(conn).await?;
+    let mut requests = FuturesUnordered::new();
+
+    loop {
+        tokio::select! {
+            // `accept()` is cancel-safe: when a request completes first, the
+            // next iteration resumes waiting where this one left off. Polling
+            // it is what processes the client's SETTINGS, GOAWAY and QPACK
+            // instructions while the requests are in progress.
+            accepted = h3_conn.accept() => match accepted {
+                Ok(Some((req, stream))) => requests.push(handle_request(req, stream)),
+                // The client is gone, or sent GOAWAY and all its requests are done
+                Ok(None) => break,
+                Err(err) if err.get_error_level() == ErrorLevel::StreamError => {
+                    error!("error on accept: {}", err);
+                }
+                Err(err) => return Err(err),
+            },
+            Some(res) = requests.next(), if !requests.is_empty() => {
+                if let Err(err) = res {
+                    error!("handling request failed: {}", err);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle_request<T>(
+    req: Request<()>,
+    mut stream: RequestStream<T, Bytes>,
+) -> Result<(), h3::Error>
+where
+    T: BidiStream<Bytes>,
+{
+    info!("new request: {} {}", req.method(), req.uri());
+    let body = Bytes::from(format!("you asked for {}\n", req.uri().path()));
+    let resp = Response::builder()
+        .status(StatusCode::OK)
+        .body(body)
+        .unwrap();
+    stream.send_complete(resp).await
+}
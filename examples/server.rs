@@ -185,6 +185,17 @@ where
         }
     };
 
+    let mut file = match to_serve {
+        Some(file) => file,
+        None => {
+            let resp = http::Response::builder()
+                .status(status)
+                .body(Bytes::new())
+                .unwrap();
+            return Ok(stream.send_complete(resp).await?);
+        }
+    };
+
     let resp = http::Response::builder().status(status).body(()).unwrap();
 
     match stream.send_response(resp).await {
@@ -196,14 +207,12 @@ where
         }
     }
 
-    if let Some(mut file) = to_serve {
-        loop {
-            let mut buf = BytesMut::with_capacity(4096 * 10);
-            if file.read_buf(&mut buf).await? == 0 {
-                break;
-            }
-            stream.send_data(buf.freeze()).await?;
+    loop {
+        let mut buf = BytesMut::with_capacity(4096 * 10);
+        if file.read_buf(&mut buf).await? == 0 {
+            break;
         }
+        stream.send_data(buf.freeze()).await?;
     }
 
     Ok(stream.finish().await?)
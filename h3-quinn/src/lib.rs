@@ -7,6 +7,7 @@ use std::{
     convert::TryInto,
     fmt::{self, Display},
     future::Future,
+    net::SocketAddr,
     sync::Arc,
     task::{self, Poll},
 };
@@ -22,7 +23,7 @@ pub use quinn::{
     self, crypto::Session, AcceptBi, AcceptUni, Endpoint, OpenBi, OpenUni, VarInt, WriteError,
 };
 
-use h3::quic::{self, Error, StreamId, WriteBuf};
+use h3::quic::{self, Error, StreamId, TlsInfo, WriteBuf};
 use tokio_util::sync::ReusableBoxFuture;
 
 /// A QUIC connection backed by Quinn
@@ -34,12 +35,15 @@ pub struct Connection {
     opening_bi: Option<BoxStream<'static, <OpenBi<'static> as Future>::Output>>,
     incoming_uni: BoxStream<'static, <AcceptUni<'static> as Future>::Output>,
     opening_uni: Option<BoxStream<'static, <OpenUni<'static> as Future>::Output>>,
+    // last peer address reported, to detect migrations
+    remote_addr: SocketAddr,
 }
 
 impl Connection {
     /// Create a [`Connection`] from a [`quinn::NewConnection`]
     pub fn new(conn: quinn::Connection) -> Self {
         Self {
+            remote_addr: conn.remote_address(),
             conn: conn.clone(),
             incoming_bi: Box::pin(stream::unfold(conn.clone(), |conn| async {
                 Some((conn.accept_bi().await, conn))
@@ -55,7 +59,13 @@ impl Connection {
 
 /// The error type for [`Connection`]
 ///
-/// Wraps reasons a Quinn connection might be lost.
+/// Wraps reasons a Quinn connection might be lost. It converts into an
+/// [`h3::Error`] as the wrapped error does: an idle timeout gives a timeout
+/// error, a close from the peer gives a connection error with its code, and a
+/// close with `H3_NO_ERROR` a closed connection.
+///
+/// This crate enables the `quinn` feature of h3, so quinn's errors can also be
+/// converted directly, e.g. `h3::Error::from(e)` for a [`quinn::ConnectionError`].
 #[derive(Debug)]
 pub struct ConnectionError(quinn::ConnectionError);
 
@@ -67,6 +77,15 @@ impl fmt::Display for ConnectionError {
     }
 }
 
+fn peer_close(err: &quinn::ConnectionError) -> Option<(u64, &[u8])> {
+    match err {
+        quinn::ConnectionError::ApplicationClosed(close) => {
+            Some((close.error_code.into_inner(), &close.reason))
+        }
+        _ => None,
+    }
+}
+
 impl Error for ConnectionError {
     fn is_timeout(&self) -> bool {
         matches!(self.0, quinn::ConnectionError::TimedOut)
@@ -81,6 +100,10 @@ impl Error for ConnectionError {
             _ => None,
         }
     }
+
+    fn peer_close(&self) -> Option<(u64, &[u8])> {
+        peer_close(&self.0)
+    }
 }
 
 impl From<quinn::ConnectionError> for ConnectionError {
@@ -170,6 +193,45 @@ where
             reason,
         );
     }
+
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        Some(self.conn.remote_address())
+    }
+
+    /// Quinn does not signal migrations, so the peer address is compared to
+    /// the last one each time this is polled: a change is reported with the
+    /// next activity on the connection.
+    fn poll_path_change(&mut self, _cx: &mut task::Context<'_>) -> Poll<SocketAddr> {
+        let addr = self.conn.remote_address();
+        if addr == self.remote_addr {
+            return Poll::Pending;
+        }
+        self.remote_addr = addr;
+        Poll::Ready(addr)
+    }
+
+    /// With the default rustls session, `handshake_data` is a
+    /// `quinn::crypto::rustls::HandshakeData` and `peer_identity` a
+    /// `Vec<rustls::Certificate>`.
+    fn tls_info(&self) -> Option<TlsInfo> {
+        // Only rustls sessions have an identity of a known type
+        let peer_certificates = self
+            .conn
+            .peer_identity()
+            .and_then(|id| id.downcast::<Vec<rustls::Certificate>>().ok())
+            .map(|certs| certs.into_iter().map(|cert| Bytes::from(cert.0)).collect());
+        Some(TlsInfo {
+            handshake_data: self.conn.handshake_data(),
+            peer_identity: self.conn.peer_identity(),
+            peer_certificates,
+        })
+    }
+
+    /// Always `None`: Quinn only knows the local IP of a connection, the port
+    /// is the endpoint's, which this adapter does not have access to.
+    fn local_addr(&self) -> Option<SocketAddr> {
+        None
+    }
 }
 
 /// Stream opener backed by a Quinn connection
@@ -263,6 +325,13 @@ where
     fn split(self) -> (Self::SendStream, Self::RecvStream) {
         (self.send, self.recv)
     }
+
+    fn unsplit(
+        send: Self::SendStream,
+        recv: Self::RecvStream,
+    ) -> Result<Self, (Self::SendStream, Self::RecvStream)> {
+        Ok(Self { send, recv })
+    }
 }
 
 impl<B> quic::RecvStream for BidiStream<B>
@@ -282,6 +351,10 @@ where
     fn stop_sending(&mut self, error_code: u64) {
         self.recv.stop_sending(error_code)
     }
+
+    fn recv_id(&self) -> StreamId {
+        self.recv.recv_id()
+    }
 }
 
 impl<B> quic::SendStream<B> for BidiStream<B>
@@ -309,6 +382,14 @@ where
     fn id(&self) -> StreamId {
         self.send.id()
     }
+
+    fn set_priority(&mut self, priority: i32) {
+        self.send.set_priority(priority)
+    }
+
+    fn poll_stopped(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<u64, Self::Error>> {
+        self.send.poll_stopped(cx)
+    }
 }
 
 /// Quinn-backed receive stream
@@ -316,6 +397,8 @@ where
 /// Implements a [`quic::RecvStream`] backed by a [`quinn::RecvStream`].
 pub struct RecvStream {
     stream: Option<quinn::RecvStream>,
+    // The stream is moved into `read_chunk_fut` while reading
+    id: StreamId,
     read_chunk_fut: ReadChunkFuture,
 }
 
@@ -330,6 +413,7 @@ type ReadChunkFuture = ReusableBoxFuture<
 impl RecvStream {
     fn new(stream: quinn::RecvStream) -> Self {
         Self {
+            id: stream.id().0.try_into().expect("invalid stream id"),
             stream: Some(stream),
             // Should only allocate once the first time it's used
             read_chunk_fut: ReusableBoxFuture::new(async { unreachable!() }),
@@ -364,11 +448,19 @@ impl quic::RecvStream for RecvStream {
             .stop(VarInt::from_u64(error_code).expect("invalid error_code"))
             .ok();
     }
+
+    fn recv_id(&self) -> StreamId {
+        self.id
+    }
 }
 
 /// The error type for [`RecvStream`]
 ///
-/// Wraps errors that occur when reading from a receive stream.
+/// Wraps errors that occur when reading from a receive stream. Converted
+/// into an [`h3::Error`], [`quinn::ReadError::Reset`] carries the peer's code,
+/// and is a stream error for the request error codes such as
+/// `H3_REQUEST_CANCELLED`, a connection error otherwise. A lost connection
+/// maps as for [`ConnectionError`].
 #[derive(Debug)]
 pub struct ReadError(quinn::ReadError);
 
@@ -409,6 +501,17 @@ impl Error for ReadError {
             _ => None,
         }
     }
+
+    fn peer_close(&self) -> Option<(u64, &[u8])> {
+        match self.0 {
+            quinn::ReadError::ConnectionLost(ref e) => peer_close(e),
+            _ => None,
+        }
+    }
+
+    fn is_stream_reset(&self) -> bool {
+        matches!(self.0, quinn::ReadError::Reset(_))
+    }
 }
 
 /// Quinn-backed send stream
@@ -500,11 +603,44 @@ where
             .try_into()
             .expect("invalid stream id")
     }
+
+    fn set_priority(&mut self, priority: i32) {
+        // The stream is only missing while a write is in progress
+        if let Some(stream) = self.stream.as_ref() {
+            let _ = stream.set_priority(priority);
+        }
+    }
+
+    fn poll_stopped(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<u64, Self::Error>> {
+        if self.stream.is_none() {
+            // Finish the write holding the stream, it fails if the peer stopped it
+            match ready!(self.poll_ready(cx)) {
+                Err(SendStreamError::Write(WriteError::Stopped(code))) => {
+                    return Poll::Ready(Ok(code.into_inner()))
+                }
+                Err(e) => return Poll::Ready(Err(e)),
+                Ok(()) => (),
+            }
+        }
+        let res = ready!(self.stream.as_mut().unwrap().poll_stopped(cx));
+        Poll::Ready(match res {
+            Ok(code) => Ok(code.into_inner()),
+            Err(quinn::StoppedError::ConnectionLost(e)) => {
+                Err(WriteError::ConnectionLost(e).into())
+            }
+            Err(quinn::StoppedError::UnknownStream) => Err(WriteError::UnknownStream.into()),
+            Err(quinn::StoppedError::ZeroRttRejected) => Err(WriteError::ZeroRttRejected.into()),
+        })
+    }
 }
 
 /// The error type for [`SendStream`]
 ///
 /// Wraps errors that can happen writing to or polling a send stream.
+/// Converted into an [`h3::Error`], [`quinn::WriteError::Stopped`] carries the
+/// peer's code, and is a stream error for the request error codes such as
+/// `H3_REQUEST_CANCELLED`, a connection error otherwise. A lost connection
+/// maps as for [`ConnectionError`].
 #[derive(Debug)]
 pub enum SendStreamError {
     /// Errors when writing, wrapping a [`quinn::WriteError`]
@@ -550,6 +686,17 @@ impl Error for SendStreamError {
             _ => None,
         }
     }
+
+    fn peer_close(&self) -> Option<(u64, &[u8])> {
+        match self {
+            Self::Write(quinn::WriteError::ConnectionLost(e)) => peer_close(e),
+            _ => None,
+        }
+    }
+
+    fn is_stream_reset(&self) -> bool {
+        matches!(self, Self::Write(quinn::WriteError::Stopped(_)))
+    }
 }
 
 impl From<SendStreamError> for Arc<dyn Error> {
@@ -0,0 +1,76 @@
+//! Request throughput of a client and a server over an in-memory connection
+//!
+//! Run with `cargo bench -p h3`. Criterion reports the time and throughput of
+//! a GET request with a small response body, then the number of allocations
+//! it takes is printed, to compare changes to the request path.
+
+mod support;
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use h3::server;
+use http::Response;
+use tokio::runtime::{Builder, Runtime};
+
+/// Counts the allocations made by the benchmark process
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const BODY: &[u8] = b"hello from the benchmark server";
+
+fn runtime() -> Runtime {
+    // A single thread keeps scheduling noise out of the numbers
+    Builder::new_current_thread().build().unwrap()
+}
+
+fn request_throughput(c: &mut Criterion) {
+    let rt = runtime();
+    let mut send_request = rt.block_on(support::connect(
+        server::builder(),
+        |_| Response::new(()),
+        BODY,
+    ));
+    let mut requests = support::requests(1);
+
+    const REQUESTS: usize = 1000;
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    rt.block_on(async {
+        for request in requests.by_ref().take(REQUESTS) {
+            assert_eq!(support::get(&mut send_request, request).await, BODY.len());
+        }
+    });
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!(
+        "request/get: {} allocations per request",
+        allocations / REQUESTS
+    );
+
+    let mut group = c.benchmark_group("request");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("get", |b| {
+        b.iter(|| rt.block_on(support::get(&mut send_request, requests.next().unwrap())))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, request_throughput);
+criterion_main!(benches);
@@ -0,0 +1,56 @@
+//! Cost of `send_response` with and without the encoded response cache
+//!
+//! Run with `cargo bench -p h3 --bench response_cache`. Each request gets a
+//! response with the headers of a typical static file, which only differ by
+//! their `etag` between the paths requested. Requests cycle over 1 path, then
+//! over as many paths as the cache has entries, so every lookup walks the
+//! whole cache before hitting.
+
+mod support;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use h3::server;
+use http::{Request, Response};
+use tokio::runtime::Builder;
+
+const CACHE_SIZE: usize = 16;
+
+fn respond(request: &Request<()>) -> Response<()> {
+    Response::builder()
+        .header("content-type", "text/html; charset=utf-8")
+        .header("cache-control", "public, max-age=3600")
+        .header("etag", format!("\"{}\"", request.uri().path()))
+        .header("last-modified", "Tue, 15 Nov 1994 12:45:26 GMT")
+        .header("server", "h3-bench")
+        .header("vary", "accept-encoding")
+        .body(())
+        .unwrap()
+}
+
+fn send_response(c: &mut Criterion) {
+    let rt = Builder::new_current_thread().build().unwrap();
+    let mut group = c.benchmark_group("send_response");
+    group.throughput(Throughput::Elements(1));
+
+    for paths in [1, CACHE_SIZE] {
+        for cache_size in [0, CACHE_SIZE] {
+            let mut builder = server::builder();
+            builder.response_cache_size(cache_size);
+            let mut send_request = rt.block_on(support::connect(builder, respond, b""));
+            let mut requests = support::requests(paths);
+
+            let name = if cache_size == 0 {
+                "uncached"
+            } else {
+                "cached"
+            };
+            group.bench_function(BenchmarkId::new(name, paths), |b| {
+                b.iter(|| rt.block_on(support::get(&mut send_request, requests.next().unwrap())))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, send_response);
+criterion_main!(benches);
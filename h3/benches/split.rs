@@ -0,0 +1,73 @@
+//! Full-duplex throughput of split request streams
+//!
+//! Run with `cargo bench -p h3 --bench split`. On both ends, one task sends
+//! the body while another receives the peer's, on the two halves of a split
+//! request stream, as a proxy or an echo server would. The runtime has a
+//! thread per half, so contention between them shows up in the numbers.
+
+mod support;
+
+use bytes::{Buf, Bytes};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use h3::server;
+use http::{Request, Response};
+use tokio::runtime::Builder;
+
+const CHUNK: &[u8] = &[0x42; 16 * 1024];
+const CHUNKS: usize = 64;
+
+/// Read a body to its end, returning its length
+macro_rules! drain {
+    ($stream:expr) => {{
+        let mut len = 0;
+        while let Some(chunk) = $stream.recv_data().await.unwrap() {
+            len += chunk.remaining();
+        }
+        len
+    }};
+}
+
+fn split_throughput(c: &mut Criterion) {
+    let rt = Builder::new_multi_thread()
+        .worker_threads(2)
+        .build()
+        .unwrap();
+    let mut send_request = rt.block_on(support::connect_with(
+        server::builder(),
+        |_, stream| async move {
+            let (mut send, mut recv) = stream.split();
+            let receiver = tokio::spawn(async move { drain!(recv) });
+            send.send_response(Response::new(())).await.unwrap();
+            for _ in 0..CHUNKS {
+                send.send_data(Bytes::from_static(CHUNK)).await.unwrap();
+            }
+            send.finish().await.unwrap();
+            assert_eq!(receiver.await.unwrap(), CHUNKS * CHUNK.len());
+        },
+    ));
+
+    let mut group = c.benchmark_group("split");
+    group.throughput(Throughput::Bytes(2 * (CHUNKS * CHUNK.len()) as u64));
+    group.bench_function("duplex", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                let request = Request::post("https://localhost/").body(()).unwrap();
+                let stream = send_request.send_request(request).await.unwrap();
+                let (mut send, mut recv) = stream.split();
+                let sender = tokio::spawn(async move {
+                    for _ in 0..CHUNKS {
+                        send.send_data(Bytes::from_static(CHUNK)).await.unwrap();
+                    }
+                    send.finish().await.unwrap();
+                });
+                recv.recv_response().await.unwrap();
+                assert_eq!(drain!(recv), CHUNKS * CHUNK.len());
+                sender.await.unwrap();
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, split_throughput);
+criterion_main!(benches);
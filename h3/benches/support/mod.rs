@@ -0,0 +1,514 @@
+//! In-memory QUIC transport and request generator shared by the benchmarks
+//!
+//! Streams are pipes of `Bytes` chunks between the two sides of a
+//! [`Connection`], with no flow control, loss or encryption, so the numbers
+//! reflect the time and allocations spent in h3 itself.
+
+// Each benchmark only uses part of it
+#![allow(dead_code)]
+
+use std::{
+    collections::VecDeque,
+    convert::TryFrom,
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use bytes::{Buf, Bytes};
+use futures::future;
+use h3::{
+    client::SendRequest,
+    error::Code,
+    quic::{self, StreamId, WriteBuf},
+    server,
+};
+use http::{Request, Response, StatusCode};
+
+const CLIENT: usize = 0;
+const SERVER: usize = 1;
+
+/// Create a connection, returning its client and server sides
+pub fn pair() -> (Connection, Connection) {
+    let shared = Arc::new(Mutex::new(Shared::default()));
+    (
+        Connection(OpenStreams {
+            side: CLIENT,
+            shared: shared.clone(),
+        }),
+        Connection(OpenStreams {
+            side: SERVER,
+            shared,
+        }),
+    )
+}
+
+/// GET requests cycling over `paths` different paths, `/0`, `/1`, ...
+pub fn requests(paths: usize) -> impl Iterator<Item = Request<()>> {
+    (0..paths).cycle().map(|path| {
+        Request::get(format!("https://localhost/{}", path))
+            .body(())
+            .unwrap()
+    })
+}
+
+/// Connect a client to a server built by `builder`
+///
+/// The server answers each request with the response given by `respond`,
+/// followed by `body` unless it is empty.
+pub async fn connect<F>(
+    builder: server::Builder,
+    respond: F,
+    body: &'static [u8],
+) -> SendRequest<OpenStreams, Bytes>
+where
+    F: Fn(&Request<()>) -> Response<()> + Send + Sync + 'static,
+{
+    let respond = Arc::new(respond);
+    connect_with(builder, move |request, mut stream| {
+        let response = respond(&request);
+        async move {
+            stream.send_response(response).await.unwrap();
+            if !body.is_empty() {
+                stream.send_data(Bytes::from_static(body)).await.unwrap();
+            }
+            stream.finish().await.unwrap();
+        }
+    })
+    .await
+}
+
+/// Connect a client to a server built by `builder`, handling requests with `handle`
+///
+/// Each request is handled in its own task. The server and the client
+/// driver run in tasks spawned on the current runtime.
+pub async fn connect_with<H, F>(
+    builder: server::Builder,
+    handle: H,
+) -> SendRequest<OpenStreams, Bytes>
+where
+    H: Fn(Request<()>, server::RequestStream<BidiStream<Bytes>, Bytes>) -> F + Send + 'static,
+    F: Future<Output = ()> + Send + 'static,
+{
+    let (client, server) = pair();
+    tokio::spawn(async move {
+        let mut conn = builder.build::<_, Bytes>(server).await.unwrap();
+        while let Ok(Some((request, stream))) = conn.accept().await {
+            tokio::spawn(handle(request, stream));
+        }
+    });
+    let (mut driver, send_request) = h3::client::new(client).await.unwrap();
+    tokio::spawn(async move { future::poll_fn(|cx| driver.poll_close(cx)).await });
+    send_request
+}
+
+/// Send a request and read the whole response, returning the body length
+pub async fn get(
+    send_request: &mut SendRequest<OpenStreams, Bytes>,
+    request: Request<()>,
+) -> usize {
+    let mut stream = send_request.send_request(request).await.unwrap();
+    // The server may stop the request stream with H3_NO_ERROR once it has
+    // answered, before the client gets to finish it
+    let _ = stream.finish().await;
+    let response = stream.recv_response().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut len = 0;
+    while let Some(chunk) = stream.recv_data().await.unwrap() {
+        len += chunk.remaining();
+    }
+    len
+}
+
+/// Streams waiting to be accepted, and the number opened, for each side
+#[derive(Default)]
+struct Shared {
+    // indexed by the side accepting them
+    incoming_bidi: [VecDeque<(StreamId, Pipe, Pipe)>; 2],
+    incoming_uni: [VecDeque<(StreamId, Pipe)>; 2],
+    bidi_wakers: [Option<Waker>; 2],
+    uni_wakers: [Option<Waker>; 2],
+    // indexed by the side opening them
+    opened_bidi: [u64; 2],
+    opened_uni: [u64; 2],
+    closed: bool,
+}
+
+fn stream_id(index: u64, uni: bool, side: usize) -> StreamId {
+    StreamId::try_from(index << 2 | (uni as u64) << 1 | side as u64).unwrap()
+}
+
+/// One side of an in-memory connection
+pub struct Connection(OpenStreams);
+
+impl<B: Buf> quic::Connection<B> for Connection {
+    type BidiStream = BidiStream<B>;
+    type SendStream = SendStream<B>;
+    type RecvStream = RecvStream;
+    type OpenStreams = OpenStreams;
+    type Error = Error;
+
+    fn poll_accept_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<Self::RecvStream>, Self::Error>> {
+        let side = self.0.side;
+        let mut shared = self.0.shared.lock().unwrap();
+        match shared.incoming_uni[side].pop_front() {
+            Some((id, pipe)) => Poll::Ready(Ok(Some(RecvStream { id, pipe }))),
+            None if shared.closed => Poll::Ready(Ok(None)),
+            None => {
+                shared.uni_wakers[side] = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn poll_accept_bidi(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<Self::BidiStream>, Self::Error>> {
+        let side = self.0.side;
+        let mut shared = self.0.shared.lock().unwrap();
+        match shared.incoming_bidi[side].pop_front() {
+            Some((id, send, recv)) => Poll::Ready(Ok(Some(BidiStream {
+                send: SendStream::new(id, send),
+                recv: RecvStream { id, pipe: recv },
+            }))),
+            None if shared.closed => Poll::Ready(Ok(None)),
+            None => {
+                shared.bidi_wakers[side] = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn poll_open_bidi(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::BidiStream, Self::Error>> {
+        quic::OpenStreams::poll_open_bidi(&mut self.0, cx)
+    }
+
+    fn poll_open_send(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::SendStream, Self::Error>> {
+        quic::OpenStreams::poll_open_send(&mut self.0, cx)
+    }
+
+    fn opener(&self) -> Self::OpenStreams {
+        OpenStreams {
+            side: self.0.side,
+            shared: self.0.shared.clone(),
+        }
+    }
+
+    fn close(&mut self, code: Code, reason: &[u8]) {
+        quic::OpenStreams::<B>::close(&mut self.0, code, reason)
+    }
+}
+
+/// Opens streams on an in-memory connection
+pub struct OpenStreams {
+    side: usize,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl<B: Buf> quic::OpenStreams<B> for OpenStreams {
+    type BidiStream = BidiStream<B>;
+    type SendStream = SendStream<B>;
+    type RecvStream = RecvStream;
+    type Error = Error;
+
+    fn poll_open_bidi(&mut self, _: &mut Context<'_>) -> Poll<Result<Self::BidiStream, Error>> {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.closed {
+            return Poll::Ready(Err(Error::Closed));
+        }
+        let id = stream_id(shared.opened_bidi[self.side], false, self.side);
+        shared.opened_bidi[self.side] += 1;
+        let (send, recv) = (Pipe::default(), Pipe::default());
+        let peer = 1 - self.side;
+        shared.incoming_bidi[peer].push_back((id, recv.clone(), send.clone()));
+        if let Some(waker) = shared.bidi_wakers[peer].take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(BidiStream {
+            send: SendStream::new(id, send),
+            recv: RecvStream { id, pipe: recv },
+        }))
+    }
+
+    fn poll_open_send(&mut self, _: &mut Context<'_>) -> Poll<Result<Self::SendStream, Error>> {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.closed {
+            return Poll::Ready(Err(Error::Closed));
+        }
+        let id = stream_id(shared.opened_uni[self.side], true, self.side);
+        shared.opened_uni[self.side] += 1;
+        let pipe = Pipe::default();
+        let peer = 1 - self.side;
+        shared.incoming_uni[peer].push_back((id, pipe.clone()));
+        if let Some(waker) = shared.uni_wakers[peer].take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(SendStream::new(id, pipe)))
+    }
+
+    fn close(&mut self, _: Code, _: &[u8]) {
+        let mut guard = self.shared.lock().unwrap();
+        let shared = &mut *guard;
+        shared.closed = true;
+        let wakers = shared.bidi_wakers.iter_mut().chain(&mut shared.uni_wakers);
+        wakers.filter_map(Option::take).for_each(Waker::wake);
+    }
+}
+
+/// Data flowing in one direction of a stream
+#[derive(Clone, Default)]
+struct Pipe(Arc<Mutex<PipeState>>);
+
+#[derive(Default)]
+struct PipeState {
+    chunks: VecDeque<Bytes>,
+    finished: bool,
+    reset: Option<u64>,
+    stopped: Option<u64>,
+    reader: Option<Waker>,
+    writer: Option<Waker>,
+}
+
+impl Pipe {
+    fn wake_reader(state: &mut PipeState) {
+        if let Some(waker) = state.reader.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Sending side of an in-memory stream
+pub struct SendStream<B> {
+    id: StreamId,
+    pipe: Pipe,
+    // finished or reset
+    done: bool,
+    _buf: PhantomData<fn(B)>,
+}
+
+impl<B> SendStream<B> {
+    fn new(id: StreamId, pipe: Pipe) -> Self {
+        Self {
+            id,
+            pipe,
+            done: false,
+            _buf: PhantomData,
+        }
+    }
+}
+
+impl<B: Buf> quic::SendStream<B> for SendStream<B> {
+    type Error = Error;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        match self.pipe.0.lock().unwrap().stopped {
+            Some(code) => Poll::Ready(Err(Error::Stopped(code))),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn send_data<T: Into<WriteBuf<B>>>(&mut self, data: T) -> Result<(), Error> {
+        let mut data = data.into();
+        let mut state = self.pipe.0.lock().unwrap();
+        if let Some(code) = state.stopped {
+            return Err(Error::Stopped(code));
+        }
+        state.chunks.push_back(data.copy_to_bytes(data.remaining()));
+        Pipe::wake_reader(&mut state);
+        Ok(())
+    }
+
+    fn poll_finish(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let mut state = self.pipe.0.lock().unwrap();
+        state.finished = true;
+        Pipe::wake_reader(&mut state);
+        self.done = true;
+        Poll::Ready(Ok(()))
+    }
+
+    fn reset(&mut self, reset_code: u64) {
+        let mut state = self.pipe.0.lock().unwrap();
+        state.reset = Some(reset_code);
+        Pipe::wake_reader(&mut state);
+        self.done = true;
+    }
+
+    fn id(&self) -> StreamId {
+        self.id
+    }
+
+    fn poll_stopped(&mut self, cx: &mut Context<'_>) -> Poll<Result<u64, Error>> {
+        let mut state = self.pipe.0.lock().unwrap();
+        match state.stopped {
+            Some(code) => Poll::Ready(Ok(code)),
+            None => {
+                state.writer = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<B> Drop for SendStream<B> {
+    // Like QUIC implementations, a stream dropped without a reset is finished
+    fn drop(&mut self) {
+        if !self.done {
+            let mut state = self.pipe.0.lock().unwrap();
+            state.finished = true;
+            Pipe::wake_reader(&mut state);
+        }
+    }
+}
+
+/// Receiving side of an in-memory stream
+pub struct RecvStream {
+    id: StreamId,
+    pipe: Pipe,
+}
+
+impl quic::RecvStream for RecvStream {
+    type Buf = Bytes;
+    type Error = Error;
+
+    fn poll_data(&mut self, cx: &mut Context<'_>) -> Poll<Result<Option<Bytes>, Error>> {
+        let mut state = self.pipe.0.lock().unwrap();
+        if let Some(code) = state.reset {
+            return Poll::Ready(Err(Error::Reset(code)));
+        }
+        match state.chunks.pop_front() {
+            Some(chunk) => Poll::Ready(Ok(Some(chunk))),
+            None if state.finished => Poll::Ready(Ok(None)),
+            None => {
+                state.reader = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn stop_sending(&mut self, error_code: u64) {
+        let mut state = self.pipe.0.lock().unwrap();
+        state.stopped = Some(error_code);
+        if let Some(waker) = state.writer.take() {
+            waker.wake();
+        }
+    }
+
+    fn recv_id(&self) -> StreamId {
+        self.id
+    }
+}
+
+/// Both sides of an in-memory stream
+pub struct BidiStream<B> {
+    send: SendStream<B>,
+    recv: RecvStream,
+}
+
+impl<B: Buf> quic::BidiStream<B> for BidiStream<B> {
+    type SendStream = SendStream<B>;
+    type RecvStream = RecvStream;
+
+    fn split(self) -> (Self::SendStream, Self::RecvStream) {
+        (self.send, self.recv)
+    }
+
+    fn unsplit(
+        send: Self::SendStream,
+        recv: Self::RecvStream,
+    ) -> Result<Self, (Self::SendStream, Self::RecvStream)> {
+        Ok(Self { send, recv })
+    }
+}
+
+impl<B: Buf> quic::SendStream<B> for BidiStream<B> {
+    type Error = Error;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.send.poll_ready(cx)
+    }
+
+    fn send_data<T: Into<WriteBuf<B>>>(&mut self, data: T) -> Result<(), Error> {
+        self.send.send_data(data)
+    }
+
+    fn poll_finish(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.send.poll_finish(cx)
+    }
+
+    fn reset(&mut self, reset_code: u64) {
+        self.send.reset(reset_code)
+    }
+
+    fn id(&self) -> StreamId {
+        self.send.id()
+    }
+
+    fn poll_stopped(&mut self, cx: &mut Context<'_>) -> Poll<Result<u64, Error>> {
+        self.send.poll_stopped(cx)
+    }
+}
+
+impl<B: Buf> quic::RecvStream for BidiStream<B> {
+    type Buf = Bytes;
+    type Error = Error;
+
+    fn poll_data(&mut self, cx: &mut Context<'_>) -> Poll<Result<Option<Bytes>, Error>> {
+        self.recv.poll_data(cx)
+    }
+
+    fn stop_sending(&mut self, error_code: u64) {
+        self.recv.stop_sending(error_code)
+    }
+
+    fn recv_id(&self) -> StreamId {
+        self.recv.recv_id()
+    }
+}
+
+/// Errors of the in-memory transport
+#[derive(Debug)]
+pub enum Error {
+    /// The connection was closed
+    Closed,
+    /// The peer reset the stream
+    Reset(u64),
+    /// The peer asked to stop sending on the stream
+    Stopped(u64),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl quic::Error for Error {
+    fn is_timeout(&self) -> bool {
+        false
+    }
+
+    fn err_code(&self) -> Option<u64> {
+        match self {
+            Self::Closed => None,
+            Self::Reset(code) | Self::Stopped(code) => Some(*code),
+        }
+    }
+
+    fn is_stream_reset(&self) -> bool {
+        !matches!(self, Self::Closed)
+    }
+}
@@ -0,0 +1,42 @@
+//! Advertisement of HTTP/3 to clients reaching the server over another protocol
+
+use std::time::Duration;
+
+use http::HeaderValue;
+
+/// Build the `alt-svc` field value advertising HTTP/3 on a UDP port
+///
+/// Servers reached over HTTP/1.1 or HTTP/2 send it so clients can switch to
+/// HTTP/3 for the next requests, which they may do for `max_age`, rounded
+/// down to the second. It can also be sent on every HTTP/3 response with
+/// [`crate::server::Builder::default_response_headers()`]:
+///
+/// ```
+/// # use std::time::Duration;
+/// let mut defaults = http::HeaderMap::new();
+/// defaults.insert(http::header::ALT_SVC, h3::alt_svc(443, Duration::from_secs(86400)));
+/// assert_eq!(defaults[http::header::ALT_SVC], "h3=\":443\"; ma=86400");
+/// ```
+pub fn alt_svc(port: u16, max_age: Duration) -> HeaderValue {
+    // Same host, the `h3` ALPN token (RFC 9114 section 3.1.1) and the max age
+    // in seconds (RFC 7838 section 3.1)
+    HeaderValue::from_str(&format!("h3=\":{}\"; ma={}", port, max_age.as_secs()))
+        .expect("alt-svc value is visible ASCII")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alt_svc_value() {
+        assert_eq!(
+            alt_svc(443, Duration::from_secs(3600)),
+            "h3=\":443\"; ma=3600"
+        );
+        assert_eq!(
+            alt_svc(50781, Duration::from_millis(1500)),
+            "h3=\":50781\"; ma=1"
+        );
+    }
+}
@@ -45,12 +45,14 @@ impl BufList<Bytes> {
         chunk
     }
 
-    pub fn push_bytes<T>(&mut self, buf: &mut T)
+    /// Append the content of `buf`, returning it as pushed
+    pub fn push_bytes<T>(&mut self, buf: &mut T) -> &Bytes
     where
         T: Buf,
     {
         debug_assert!(buf.has_remaining());
-        self.bufs.push_back(buf.copy_to_bytes(buf.remaining()))
+        self.bufs.push_back(buf.copy_to_bytes(buf.remaining()));
+        self.bufs.back().expect("just pushed")
     }
 }
 
@@ -0,0 +1,100 @@
+//! Unbounded channel used by the server to track running requests
+//!
+//! It is backed by `tokio::sync::mpsc` with the default `tokio` feature, and
+//! by `futures-channel` otherwise, so h3 does not depend on tokio when built
+//! with `default-features = false`.
+
+use std::{
+    fmt,
+    task::{Context, Poll},
+};
+
+#[cfg(not(feature = "tokio"))]
+use std::pin::Pin;
+
+#[cfg(not(feature = "tokio"))]
+use futures_util::Stream;
+
+/// Create an unbounded channel
+pub(crate) fn unbounded_channel<T>() -> (UnboundedSender<T>, UnboundedReceiver<T>) {
+    #[cfg(feature = "tokio")]
+    let (send, recv) = tokio::sync::mpsc::unbounded_channel();
+    #[cfg(not(feature = "tokio"))]
+    let (send, recv) = futures_channel::mpsc::unbounded();
+
+    (UnboundedSender(send), UnboundedReceiver(recv))
+}
+
+pub(crate) struct UnboundedSender<T>(
+    #[cfg(feature = "tokio")] tokio::sync::mpsc::UnboundedSender<T>,
+    #[cfg(not(feature = "tokio"))] futures_channel::mpsc::UnboundedSender<T>,
+);
+
+impl<T> Clone for UnboundedSender<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> UnboundedSender<T> {
+    /// Send a value, failing if the receiver has been dropped
+    pub(crate) fn send(&self, value: T) -> Result<(), Closed> {
+        #[cfg(feature = "tokio")]
+        let res = self.0.send(value);
+        #[cfg(not(feature = "tokio"))]
+        let res = self.0.unbounded_send(value);
+
+        res.map_err(|_| Closed)
+    }
+}
+
+pub(crate) struct UnboundedReceiver<T>(
+    #[cfg(feature = "tokio")] tokio::sync::mpsc::UnboundedReceiver<T>,
+    #[cfg(not(feature = "tokio"))] futures_channel::mpsc::UnboundedReceiver<T>,
+);
+
+impl<T> UnboundedReceiver<T> {
+    /// Receive the next value, `None` once all senders have been dropped
+    pub(crate) fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        #[cfg(feature = "tokio")]
+        return self.0.poll_recv(cx);
+        #[cfg(not(feature = "tokio"))]
+        return Pin::new(&mut self.0).poll_next(cx);
+    }
+}
+
+/// The receiving half of the channel has been dropped
+#[derive(Debug)]
+pub(crate) struct Closed;
+
+impl fmt::Display for Closed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("channel closed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future;
+
+    #[tokio::test]
+    async fn send_recv_close() {
+        let (send, mut recv) = unbounded_channel();
+        let send2 = send.clone();
+        send.send(1).unwrap();
+        send2.send(2).unwrap();
+        assert_eq!(future::poll_fn(|cx| recv.poll_recv(cx)).await, Some(1));
+        assert_eq!(future::poll_fn(|cx| recv.poll_recv(cx)).await, Some(2));
+
+        drop((send, send2));
+        assert_eq!(future::poll_fn(|cx| recv.poll_recv(cx)).await, None);
+    }
+
+    #[test]
+    fn send_fails_on_closed_receiver() {
+        let (send, recv) = unbounded_channel::<u32>();
+        drop(recv);
+        assert!(send.send(1).is_err());
+    }
+}
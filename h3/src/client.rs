@@ -3,25 +3,41 @@
 use std::{
     convert::TryFrom,
     marker::PhantomData,
+    net::SocketAddr,
     sync::{atomic::AtomicUsize, Arc},
     task::{Context, Poll, Waker},
 };
 
 use bytes::{Buf, Bytes, BytesMut};
-use futures_util::future;
-use http::{request, HeaderMap, Response};
+use futures_util::{future, ready};
+use http::{
+    header::HOST,
+    request,
+    uri::{self, Authority, Scheme},
+    HeaderMap, Response, Uri,
+};
 use tracing::{info, trace};
 
+pub use crate::proto::push::PushId;
+
 use crate::{
+    config::{Config, GreaseFrames, PeerSettings, Tap},
     connection::{self, ConnectionInner, ConnectionState, SharedStateRef},
-    error::{Code, Error, ErrorLevel},
+    error::{Code, Error, ErrorLevel, ReuniteError},
     frame::FrameStream,
-    proto::{frame::Frame, headers::Header, push::PushId, varint::VarInt},
+    proto::{
+        frame::{Frame, SettingId},
+        headers::{Header, HeaderError},
+    },
     qpack,
-    quic::{self, StreamId},
+    quic::{self, SendStream as _, StreamId},
+    stats::{ConnectionStats, StreamStats},
     stream,
 };
 
+#[cfg(feature = "wire-tap")]
+use crate::config::Direction;
+
 /// Start building a new HTTP/3 client
 pub fn builder() -> Builder {
     Builder::new()
@@ -132,6 +148,14 @@ where
     conn_waker: Option<Waker>,
     _buf: PhantomData<fn(B)>,
     send_grease_frame: bool,
+    // Does every request get a grease frame, not only the first one?
+    grease_every_request: bool,
+    // pseudo-headers used for origin-form request URIs
+    default_authority: Option<Authority>,
+    default_scheme: Option<Scheme>,
+    wait_for_settings: bool,
+    // reports the bytes of the request streams
+    tap: Tap,
 }
 
 impl<T, B> SendRequest<T, B>
@@ -144,6 +168,10 @@ where
         &mut self,
         req: http::Request<()>,
     ) -> Result<RequestStream<T::BidiStream, B>, Error> {
+        if self.wait_for_settings {
+            future::poll_fn(|cx| self.poll_settings(cx)).await?;
+        }
+
         let (peer_max_field_section_size, closing) = {
             let state = self.conn_state.read("send request lock state");
             (state.peer_max_field_section_size, state.closing)
@@ -160,6 +188,7 @@ where
             headers,
             ..
         } = parts;
+        let uri = self.complete_uri(uri, &headers)?;
         let headers = Header::request(method, uri, headers)?;
 
         //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
@@ -193,22 +222,103 @@ where
             return Err(Error::header_too_big(mem_size, peer_max_field_section_size));
         }
 
-        stream::write(&mut stream, Frame::Headers(block.freeze()))
+        let encoded_size = block.len();
+        let headers = self.tap.wrap(Frame::Headers(block.freeze()));
+        stream::write(&mut stream, headers)
             .await
             .map_err(|e| self.maybe_conn_err(e))?;
+        let counters = self.conn_state.counters();
+        counters.requests.add(1);
+        counters.headers_frames_sent.add(1);
 
-        let request_stream = RequestStream {
+        let stream_id = stream.id();
+        let mut stream = FrameStream::new(stream);
+        stream.cancel_on_drop();
+        stream.set_tap(self.tap.clone());
+        let mut request_stream = RequestStream {
             inner: connection::RequestStream::new(
-                FrameStream::new(stream),
+                stream,
+                stream_id,
                 self.max_field_section_size,
                 self.conn_state.clone(),
                 self.send_grease_frame,
             ),
+            response_received: false,
         };
-        // send the grease frame only once
-        self.send_grease_frame = false;
+        request_stream.inner.accept_push_promises();
+        request_stream
+            .inner
+            .record_headers_sent(encoded_size, mem_size);
+        // send the grease frame only once, unless configured otherwise
+        if !self.grease_every_request {
+            self.send_grease_frame = false;
+        }
         Ok(request_stream)
     }
+
+    /// Ready once the server has sent a GOAWAY frame
+    ///
+    /// New requests are then refused, see [`Connection::poll_closing()`].
+    pub fn poll_closing(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.conn_state.poll_goaway(cx)
+    }
+
+    /// Wait for the server's SETTINGS, or for the connection to fail
+    fn poll_settings(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let mut state = self.conn_state.write("send request wait settings");
+        if let Some(ref e) = state.error {
+            return Poll::Ready(Err(e.clone()));
+        }
+        if state.settings_received {
+            return Poll::Ready(Ok(()));
+        }
+        if !state
+            .settings_waiters
+            .iter()
+            .any(|w| w.will_wake(cx.waker()))
+        {
+            state.settings_waiters.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+
+    /// Fill an origin-form URI with the default authority and scheme
+    ///
+    /// The authority is taken from the `Host` header when there is one, so
+    /// the default scheme can still apply.
+    fn complete_uri(&self, uri: Uri, headers: &HeaderMap) -> Result<Uri, HeaderError> {
+        if uri.scheme().is_some() || uri.authority().is_some() {
+            return Ok(uri);
+        }
+
+        let authority = match headers.get(HOST) {
+            None => self.default_authority.clone(),
+            Some(host) if self.default_scheme.is_some() => {
+                let host = host
+                    .to_str()
+                    .map_err(|_| HeaderError::InvalidHeaderValue(format!("{:?}", host)))?;
+                Some(
+                    host.parse::<Authority>()
+                        .map_err(|e| HeaderError::InvalidRequest(e.into()))?,
+                )
+            }
+            Some(_) => None,
+        };
+
+        // A URI cannot have a scheme without an authority
+        let authority = match authority {
+            Some(authority) => authority,
+            None => return Ok(uri),
+        };
+
+        let mut parts = uri::Parts::from(uri);
+        parts.authority = Some(authority);
+        parts.scheme = Some(self.default_scheme.clone().unwrap_or(Scheme::HTTPS));
+        if parts.path_and_query.is_none() {
+            parts.path_and_query = Some(uri::PathAndQuery::from_static("/"));
+        }
+        Uri::from_parts(parts).map_err(|e| HeaderError::InvalidRequest(e.into()))
+    }
 }
 
 impl<T, B> ConnectionState for SendRequest<T, B>
@@ -238,6 +348,11 @@ where
             conn_waker: self.conn_waker.clone(),
             _buf: PhantomData,
             send_grease_frame: self.send_grease_frame,
+            grease_every_request: self.grease_every_request,
+            default_authority: self.default_authority.clone(),
+            default_scheme: self.default_scheme.clone(),
+            wait_for_settings: self.wait_for_settings,
+            tap: self.tap.clone(),
         }
     }
 }
@@ -354,6 +469,8 @@ where
     sent_closing: Option<PushId>,
     // Has a GOAWAY frame been received? If so, this is StreamId the last the remote will accept.
     recv_closing: Option<StreamId>,
+    // maximum size for a header we receive on push streams
+    max_field_section_size: u64,
 }
 
 impl<C, B> Connection<C, B>
@@ -367,6 +484,191 @@ where
         self.inner.shutdown(&mut self.sent_closing, PushId(0)).await
     }
 
+    /// Close the connection immediately with `code` and `reason`
+    ///
+    /// Unlike [`Connection::shutdown()`], requests in flight are aborted. The
+    /// reason is sent to the peer in the QUIC CONNECTION_CLOSE frame and is
+    /// meant for debugging. It is truncated to 1024 bytes, on a character
+    /// boundary. Only the first close reaches the peer, dropping the
+    /// connection afterwards does not send another one.
+    pub fn close(&mut self, code: Code, reason: &str) {
+        self.inner.close(code, reason);
+    }
+
+    /// Send a frame of an extension type on the control stream
+    ///
+    /// This allows prototyping control frames h3 does not implement. The
+    /// peer ignores frames of types it does not know. Types defined by
+    /// HTTP/3, or reserved from HTTP/2, are refused without sending anything.
+    pub async fn send_control_frame(
+        &mut self,
+        frame_type: u64,
+        payload: Bytes,
+    ) -> Result<(), Error> {
+        self.inner.send_control_frame(frame_type, payload).await
+    }
+
+    /// Open a unidirectional stream of an extension type
+    ///
+    /// The `stream_type` varint is written at the start of the stream, which
+    /// is returned ready to be written to. h3 does not frame anything else on
+    /// it: writing the content in the format the extension defines, and
+    /// finishing the stream, is up to the caller. Types defined by HTTP/3 or
+    /// of the grease format are refused, unless
+    /// [`Builder::allow_reserved_stream_types()`] is set.
+    pub async fn open_uni(&mut self, stream_type: u64) -> Result<C::SendStream, Error> {
+        self.inner.open_uni(stream_type).await
+    }
+
+    /// Allow the server to push responses with IDs up to `max_push_id`
+    ///
+    /// This sends a MAX_PUSH_ID frame, the server cannot push before the
+    /// first one. The maximum can be raised later, but not lowered. Push
+    /// promises are then read from the request streams, see
+    /// [`RequestStream::take_push_promises()`], and the pushed responses
+    /// are accepted with [`Connection::accept_push()`].
+    pub async fn allow_push(&mut self, max_push_id: PushId) -> Result<(), Error> {
+        self.inner.send_max_push_id(max_push_id).await
+    }
+
+    /// Reject a promised push the application does not want
+    ///
+    /// This sends a CANCEL_PUSH frame, and stops the push stream with `code`
+    /// if it has been received but not accepted yet, or once it arrives.
+    /// Push streams already accepted are stopped with
+    /// [`RequestStream::stop_sending()`] instead. Fails if no promise has
+    /// been received for `push_id`.
+    pub async fn reject_push(&mut self, push_id: PushId, code: Code) -> Result<(), Error> {
+        self.inner.cancel_push(push_id, code).await
+    }
+
+    /// Accept the next pushed response
+    ///
+    /// See [`Connection::poll_accept_push()`].
+    pub async fn accept_push(
+        &mut self,
+    ) -> Result<Option<(PushId, RequestStream<C::RecvStream, B>)>, Error> {
+        future::poll_fn(|cx| self.poll_accept_push(cx)).await
+    }
+
+    /// Poll for the next pushed response
+    ///
+    /// Push streams are returned in the order they arrive, along with the
+    /// push ID of the promise they fulfill, see
+    /// [`RequestStream::take_push_promises()`]. Each can be read on its own,
+    /// concurrently with the others, like the response to a request. This
+    /// drives the connection like [`Connection::poll_close()`], and returns
+    /// `Ok(None)` once it is closed.
+    #[allow(clippy::type_complexity)]
+    pub fn poll_accept_push(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<(PushId, RequestStream<C::RecvStream, B>)>, Error>> {
+        let closed = self.poll_close(cx);
+        // Push streams are of no use once the connection failed
+        if let Poll::Ready(Err(e)) = closed {
+            return Poll::Ready(Err(e));
+        }
+        // Those received before a graceful close can still be read
+        if let Some((push_id, stream_id, stream)) = self.inner.next_push_stream() {
+            let push_stream = RequestStream {
+                inner: connection::RequestStream::new(
+                    stream,
+                    stream_id,
+                    self.max_field_section_size,
+                    self.inner.shared.clone(),
+                    false,
+                ),
+                response_received: false,
+            };
+            return Poll::Ready(Ok(Some((push_id, push_stream))));
+        }
+        closed.map_ok(|()| None)
+    }
+
+    /// Send a frame on the control stream to keep the connection alive
+    ///
+    /// The frame has a reserved type, which the peer ignores. Like any packet,
+    /// it resets the QUIC idle timeout on both ends and refreshes NAT bindings
+    /// along the path. Calling this periodically is only useful when the QUIC
+    /// keep-alive of the transport is disabled or too slow for the network.
+    pub async fn send_keepalive(&mut self) -> Result<(), Error> {
+        self.inner.send_keepalive().await
+    }
+
+    /// Address of the server, if the QUIC implementation provides it
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.inner.remote_addr()
+    }
+
+    /// Local address of the connection, if the QUIC implementation provides it
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    /// Details negotiated by the TLS handshake, if the QUIC implementation provides them
+    ///
+    /// This allows routing on the SNI, or identifying a server by its certificate.
+    pub fn tls_info(&self) -> Option<quic::TlsInfo> {
+        self.inner.tls_info()
+    }
+
+    /// Cumulated statistics of this connection and its request streams
+    pub fn stats(&self) -> ConnectionStats {
+        self.inner.shared.counters().snapshot()
+    }
+
+    /// SETTINGS received from the server
+    ///
+    /// Returns `None` until the server's control stream has been read, which
+    /// happens while [`Connection::poll_close()`] is polled.
+    pub fn peer_settings(&self) -> Option<&PeerSettings> {
+        self.inner.peer_settings()
+    }
+
+    /// Ready once the server has sent a GOAWAY frame
+    ///
+    /// The connection is then draining: no new request should be sent, but requests already
+    /// in flight can complete. This happens before the connection is closed,
+    /// and a connection can also close without any GOAWAY, in which case this
+    /// stays pending. The GOAWAY is read while [`Connection::poll_close()`] is polled.
+    pub fn poll_closing(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.inner.shared.poll_goaway(cx)
+    }
+
+    /// Stream ID carried by the last GOAWAY frame received from the server
+    ///
+    /// Requests on streams with this ID or greater were not processed by the
+    /// server and can be retried on another connection. `None` until a
+    /// GOAWAY has been received.
+    pub fn peer_goaway_id(&self) -> Option<StreamId> {
+        self.recv_closing
+    }
+
+    /// Did the server enable extended CONNECT?
+    ///
+    /// `false` until the server's SETTINGS have been received.
+    pub fn peer_supports_extended_connect(&self) -> bool {
+        self.peer_settings()
+            .map_or(false, PeerSettings::enable_connect_protocol)
+    }
+
+    /// Did the server enable HTTP datagrams?
+    ///
+    /// `false` until the server's SETTINGS have been received.
+    pub fn peer_supports_datagrams(&self) -> bool {
+        self.peer_settings()
+            .map_or(false, PeerSettings::h3_datagram)
+    }
+
+    /// Did the server enable WebTransport?
+    ///
+    /// `false` until the server's SETTINGS have been received.
+    pub fn peer_supports_webtransport(&self) -> bool {
+        self.peer_settings()
+            .map_or(false, PeerSettings::enable_webtransport)
+    }
+
     /// Wait until the connection is closed
     pub async fn wait_idle(&mut self) -> Result<(), Error> {
         future::poll_fn(|cx| self.poll_close(cx)).await
@@ -374,6 +676,18 @@ where
 
     /// Maintain the connection state until it is closed
     pub fn poll_close(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let res = ready!(self.poll_driver(cx));
+
+        // Nothing will make progress anymore, fail the requests waiting for SETTINGS
+        let mut shared = self.inner.shared.write("poll_close end");
+        if shared.error.is_none() {
+            shared.error = Some(res.clone().err().unwrap_or_else(Error::closed));
+        }
+        shared.wake_settings_waiters();
+        Poll::Ready(res)
+    }
+
+    fn poll_driver(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
         while let Poll::Ready(result) = self.inner.poll_control(cx) {
             match result {
                 //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.4.2
@@ -482,15 +796,19 @@ where
 /// # }
 /// ```
 pub struct Builder {
-    max_field_section_size: u64,
-    send_grease: bool,
+    config: Config,
+    default_authority: Option<String>,
+    default_scheme: Option<String>,
+    wait_for_settings: bool,
 }
 
 impl Builder {
     pub(super) fn new() -> Self {
         Builder {
-            max_field_section_size: VarInt::MAX.0,
-            send_grease: true,
+            config: Config::default(),
+            default_authority: None,
+            default_scheme: None,
+            wait_for_settings: false,
         }
     }
 
@@ -500,11 +818,176 @@ impl Builder {
     ///
     /// [header size constraints]: https://www.rfc-editor.org/rfc/rfc9114.html#name-header-size-constraints
     pub fn max_field_section_size(&mut self, value: u64) -> &mut Self {
-        self.max_field_section_size = value;
+        self.config.max_field_section_size = value;
+        self
+    }
+
+    /// Choose the requests ending with a grease frame
+    ///
+    /// Only the first one sent by each [`SendRequest`] by default.
+    pub fn grease_frames(&mut self, value: GreaseFrames) -> &mut Self {
+        self.config.grease_frames = value;
+        self
+    }
+
+    /// Allow the server to push responses with IDs up to `max_push_id` from the start
+    ///
+    /// The MAX_PUSH_ID frame is sent on the control stream right after the
+    /// SETTINGS, so the server can push without waiting for a call to
+    /// [`Connection::allow_push()`]. None is sent by default, which does not
+    /// allow any push.
+    pub fn initial_max_push_id(&mut self, max_push_id: PushId) -> &mut Self {
+        self.config.initial_max_push_id = Some(max_push_id);
+        self
+    }
+
+    /// Advertise an additional SETTINGS parameter to the server
+    ///
+    /// This allows prototyping HTTP/3 extensions. The server can read it back
+    /// from its peer settings. Identifiers defined by HTTP/3 or of the grease
+    /// format are rejected when building the connection, unless
+    /// [`Builder::allow_reserved_settings()`] is set. Identifiers reserved
+    /// from HTTP/2 are always rejected. Up to 5 settings unknown to h3 can
+    /// be added, as many as an h3 peer keeps.
+    pub fn extra_setting(&mut self, id: u64, value: u64) -> &mut Self {
+        self.config.extra_settings.push((SettingId(id), value));
+        self
+    }
+
+    /// Allow [`Builder::extra_setting()`] to use defined or grease identifiers
+    pub fn allow_reserved_settings(&mut self, value: bool) -> &mut Self {
+        self.config.allow_reserved_settings = value;
+        self
+    }
+
+    /// Allow [`Connection::open_uni()`] to use defined or grease stream types
+    ///
+    /// The peer interprets streams of defined types, such as a second
+    /// control stream, so misusing them is likely to break the connection.
+    pub fn allow_reserved_stream_types(&mut self, value: bool) -> &mut Self {
+        self.config.allow_reserved_stream_types = value;
+        self
+    }
+
+    /// Set the QUIC priority of the control stream
+    ///
+    /// Streams with a higher priority are sent first. The control stream
+    /// carries SETTINGS and GOAWAY, so it defaults to `i32::MAX` to be sent
+    /// ahead of request data under load. No effect if the QUIC implementation
+    /// does not support stream priorities.
+    pub fn control_stream_priority(&mut self, priority: i32) -> &mut Self {
+        self.config.control_stream_priority = priority;
+        self
+    }
+
+    /// Set how many empty DATA frames the server may send in a row in responses
+    ///
+    /// Frames are counted on each response, until a non-empty DATA frame is
+    /// received. Exceeding the limit fails
+    /// [`RequestStream::recv_data()`] with `H3_EXCESSIVE_LOAD`, and the
+    /// connection is closed with that code while [`Connection::poll_close()`]
+    /// is polled. Defaults to 32.
+    pub fn max_empty_data_frames(&mut self, value: usize) -> &mut Self {
+        self.config.max_empty_data_frames = value;
+        self
+    }
+
+    /// Call `hook` when the server resets or stops a request stream
+    ///
+    /// It receives the stream ID and the error code sent by the server, once
+    /// per [`RequestStream`], or per half after [`RequestStream::split()`].
+    /// The hook runs on the task driving the request, so it should be quick.
+    pub fn on_stream_reset<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(StreamId, Code) + Send + Sync + 'static,
+    {
+        self.config.on_stream_reset = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `hook` with the new address of the server when the connection
+    /// migrates to another path
+    ///
+    /// This is only a notification, for logging or security checks: requests
+    /// carry on as if nothing happened. Changes are noticed while
+    /// [`Connection::poll_close()`] is polled, depending on the QUIC
+    /// implementation, see [`quic::Connection::poll_path_change()`].
+    pub fn on_path_change<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(SocketAddr) + Send + Sync + 'static,
+    {
+        self.config.on_path_change = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `tap` with the raw bytes h3 writes to and reads from QUIC streams
+    ///
+    /// This helps diagnosing interoperability issues at the byte level, which
+    /// packet captures cannot show as QUIC is encrypted. Bytes are reported as
+    /// the transport takes or yields them, without telling which stream they
+    /// belong to. The tap is called for every chunk, on the task doing the
+    /// I/O, which slows transfers down: it is meant for debugging. Streams
+    /// returned by [`Connection::open_uni()`] are only reported up to their
+    /// stream type. Requires the `wire-tap` feature.
+    #[cfg(feature = "wire-tap")]
+    pub fn wire_tap<F>(&mut self, tap: F) -> &mut Self
+    where
+        F: Fn(Direction, &[u8]) + Send + Sync + 'static,
+    {
+        self.config.tap = Tap::new(Arc::new(tap));
+        self
+    }
+
+    /// Set how many unidirectional streams the server may have open before their
+    /// stream type is received
+    ///
+    /// Exceeding it closes the connection with `H3_EXCESSIVE_LOAD`. It should
+    /// leave room for the control and QPACK streams. Defaults to 100.
+    pub fn max_pending_uni_streams(&mut self, value: usize) -> &mut Self {
+        self.config.max_pending_uni_streams = value;
+        self
+    }
+
+    /// Set the `:authority` of requests whose URI is in origin-form
+    ///
+    /// Requests like `GET /index.html` are sent to this authority, unless
+    /// they carry a `Host` header. Without it, such requests are rejected.
+    /// An invalid authority makes [`Builder::build()`] fail.
+    pub fn default_authority(&mut self, authority: &str) -> &mut Self {
+        self.default_authority = Some(authority.to_owned());
+        self
+    }
+
+    /// Set the `:scheme` of requests whose URI is in origin-form
+    ///
+    /// It defaults to `https`. An invalid scheme makes [`Builder::build()`] fail.
+    pub fn default_scheme(&mut self, scheme: &str) -> &mut Self {
+        self.default_scheme = Some(scheme.to_owned());
+        self
+    }
+
+    /// Wait for the server's SETTINGS before sending each request
+    ///
+    /// By default requests are sent right away, which saves a round-trip on
+    /// the first request. Until the server's SETTINGS arrive, the client
+    /// assumes the default values, so a header section may exceed a limit the
+    /// server has not advertised yet. Header sections are always encoded
+    /// without the QPACK dynamic table, so nothing else depends on SETTINGS.
+    ///
+    /// When enabled, [`SendRequest::send_request()`] waits until the
+    /// [`Connection`] driver has received the SETTINGS frame, so the driver
+    /// must be polled concurrently.
+    pub fn wait_for_settings(&mut self, value: bool) -> &mut Self {
+        self.wait_for_settings = value;
         self
     }
 
     /// Create a new HTTP/3 client from a `quic` connection
+    ///
+    /// The settings are sent to the server as the connection starts, and
+    /// HTTP/3 does not allow changing them later. The connection keeps its
+    /// own copy: modifying the builder afterwards only affects the
+    /// connections it builds next.
     pub async fn build<C, O, B>(
         &mut self,
         quic: C,
@@ -514,36 +997,72 @@ impl Builder {
         O: quic::OpenStreams<B>,
         B: Buf,
     {
+        self.config.validate().map_err(|e| {
+            Code::H3_INTERNAL_ERROR
+                .with_reason("invalid configuration", ErrorLevel::ConnectionError)
+                .with_cause(e)
+        })?;
+
+        let invalid_default = |e: http::uri::InvalidUri| {
+            Code::H3_INTERNAL_ERROR
+                .with_reason("invalid default pseudo-header", ErrorLevel::ConnectionError)
+                .with_cause(e)
+        };
+        let default_authority = self
+            .default_authority
+            .as_deref()
+            .map(Authority::try_from)
+            .transpose()
+            .map_err(invalid_default)?;
+        let default_scheme = self
+            .default_scheme
+            .as_deref()
+            .map(Scheme::try_from)
+            .transpose()
+            .map_err(invalid_default)?;
+
         let open = quic.opener();
         let conn_state = SharedStateRef::default();
 
         let conn_waker = Some(future::poll_fn(|cx| Poll::Ready(cx.waker().clone())).await);
 
+        let mut inner = ConnectionInner::new(quic, conn_state.clone(), &self.config).await?;
+        inner.accept_push_streams();
+        if let Some(max_push_id) = self.config.initial_max_push_id {
+            inner.send_max_push_id(max_push_id).await?;
+        }
+
         Ok((
             Connection {
-                inner: ConnectionInner::new(
-                    quic,
-                    self.max_field_section_size,
-                    conn_state.clone(),
-                    self.send_grease,
-                )
-                .await?,
+                inner,
                 sent_closing: None,
                 recv_closing: None,
+                max_field_section_size: self.config.max_field_section_size,
             },
             SendRequest {
                 open,
                 conn_state,
                 conn_waker,
-                max_field_section_size: self.max_field_section_size,
+                max_field_section_size: self.config.max_field_section_size,
                 sender_count: Arc::new(AtomicUsize::new(1)),
                 _buf: PhantomData,
-                send_grease_frame: self.send_grease,
+                send_grease_frame: self.config.grease_first_request(),
+                grease_every_request: self.config.grease_frames == GreaseFrames::EveryRequest,
+                default_authority,
+                default_scheme,
+                wait_for_settings: self.wait_for_settings,
+                tap: self.config.tap.clone(),
             },
         ))
     }
 }
 
+/// Halves of a split [`RequestStream`], given back when they cannot be joined
+type Halves<S, B> = ReuniteError<
+    RequestStream<<S as quic::BidiStream<B>>::SendStream, B>,
+    RequestStream<<S as quic::BidiStream<B>>::RecvStream, B>,
+>;
+
 /// Manage request bodies transfer, response and trailers.
 ///
 /// Once a request has been sent via [`send_request()`], a response can be awaited by calling
@@ -599,6 +1118,8 @@ impl Builder {
 /// [`stop_sending()`]: #method.stop_sending
 pub struct RequestStream<S, B> {
     inner: connection::RequestStream<S, B>,
+    // Has a final (non informational) response been received?
+    response_received: bool,
 }
 
 impl<S, B> ConnectionState for RequestStream<S, B> {
@@ -607,31 +1128,67 @@ impl<S, B> ConnectionState for RequestStream<S, B> {
     }
 }
 
+impl<S, B> RequestStream<S, B> {
+    /// Body bytes and DATA frames sent and received so far on this stream
+    ///
+    /// The size of the request headers and trailers sent, before and after
+    /// QPACK encoding, is also reported. After [`RequestStream::split()`],
+    /// each half only counts its own direction.
+    pub fn stats(&self) -> StreamStats {
+        self.inner.stats()
+    }
+
+    /// ID of the QUIC stream carrying the request
+    ///
+    /// Both halves returned by [`RequestStream::split()`] keep the ID of the
+    /// bidirectional stream, which helps correlate them in logs.
+    pub fn id(&self) -> StreamId {
+        self.inner.id()
+    }
+
+    /// Take the push promises received so far on this stream
+    ///
+    /// The server may send PUSH_PROMISE frames anywhere in the response. They
+    /// are collected while reading it, with the promised request decoded.
+    /// A push ID above the one allowed with [`Connection::allow_push()`] is
+    /// a connection error.
+    pub fn take_push_promises(&mut self) -> Vec<(PushId, http::Request<()>)> {
+        self.inner.take_push_promises()
+    }
+}
+
 impl<S, B> RequestStream<S, B>
 where
     S: quic::RecvStream,
 {
     /// Receive the HTTP/3 response
     ///
-    /// This should be called before trying to receive any data with [`recv_data()`].
+    /// This must be called before trying to receive any data with [`recv_data()`],
+    /// and again after each informational (1xx) response, until the final
+    /// one. It fails once the final response has been received.
     ///
     /// [`recv_data()`]: #method.recv_data
     pub async fn recv_response(&mut self) -> Result<Response<()>, Error> {
-        let mut frame = future::poll_fn(|cx| self.inner.stream.poll_next(cx))
-            .await
-            .map_err(|e| self.maybe_conn_err(e))?
-            .ok_or_else(|| {
-                Code::H3_GENERAL_PROTOCOL_ERROR.with_reason(
-                    "Did not receive response headers",
-                    ErrorLevel::ConnectionError,
-                )
-            })?;
+        if self.response_received {
+            return Err(Code::H3_INTERNAL_ERROR
+                .with_reason("response already received", ErrorLevel::StreamError));
+        }
 
-        //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.5
-        //= type=TODO
-        //# A client MUST treat
-        //# receipt of a PUSH_PROMISE frame that contains a larger push ID than
-        //# the client has advertised as a connection error of H3_ID_ERROR.
+        let mut frame = loop {
+            let frame = future::poll_fn(|cx| self.inner.stream.poll_next(cx))
+                .await
+                .map_err(|e| self.inner.stream_err(e))?
+                .ok_or_else(|| {
+                    Code::H3_GENERAL_PROTOCOL_ERROR.with_reason(
+                        "Did not receive response headers",
+                        ErrorLevel::ConnectionError,
+                    )
+                })?;
+            match frame {
+                Frame::PushPromise(promise) => self.inner.recv_push_promise(promise)?,
+                frame => break frame,
+            }
+        };
 
         //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.5
         //= type=TODO
@@ -641,7 +1198,8 @@ where
         //# H3_GENERAL_PROTOCOL_ERROR.
 
         let decoded = if let Frame::Headers(ref mut encoded) = frame {
-            match qpack::decode_stateless(encoded, self.inner.max_field_section_size) {
+            self.inner.conn_state.counters().headers_frames_recv.add(1);
+            match qpack::decode_stateless(encoded, self.inner.max_field_section_size, usize::MAX) {
                 //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
                 //# An HTTP/3 implementation MAY impose a limit on the maximum size of
                 //# the message header it will accept on an individual HTTP message.
@@ -656,6 +1214,9 @@ where
                 Err(e) => return Err(e.into()),
             }
         } else {
+            //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
+            //# Receipt of an invalid sequence of frames MUST be treated as a
+            //# connection error of type H3_FRAME_UNEXPECTED.
             return Err(Code::H3_FRAME_UNEXPECTED.with_reason(
                 "First response frame is not headers",
                 ErrorLevel::ConnectionError,
@@ -669,18 +1230,72 @@ where
         *resp.status_mut() = status;
         *resp.headers_mut() = headers;
         *resp.version_mut() = http::Version::HTTP_3;
+        if !status.is_informational() {
+            self.response_received = true;
+        }
 
         Ok(resp)
     }
 
-    /// Receive some of the request body.
-    // TODO what if called before recv_response ?
+    /// Receive some of the response body.
+    ///
+    /// This fails until the final response has been received with
+    /// [`recv_response()`], so that a DATA frame sent by the server ahead of
+    /// it is rejected by the latter as H3_FRAME_UNEXPECTED. Chunks are never
+    /// empty: empty DATA frames are skipped, and `None` marks the end of the
+    /// body.
+    ///
+    /// [`recv_response()`]: #method.recv_response
     pub async fn recv_data(&mut self) -> Result<Option<impl Buf>, Error> {
+        self.check_response_received()?;
         self.inner.recv_data().await
     }
 
+    /// Poll for some of the body
+    ///
+    /// This is the poll-based counterpart of [`RequestStream::recv_data()`].
+    /// Progress is kept in the stream, so polling can stop at any `Pending`
+    /// and resume later, possibly from another task.
+    pub fn poll_recv_data(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<impl Buf>, Error>> {
+        self.check_response_received()?;
+        self.inner.poll_recv_data(cx)
+    }
+
+    /// Wait for the type and payload length of the next frame, without consuming it
+    ///
+    /// This lets a proxy or an inspection tool decide how to handle the frame
+    /// before reading it. Only the frame header is buffered, the payload is not
+    /// read ahead. Unknown frame types are reported too, although receiving
+    /// skips them. `None` marks the end of the stream. Before
+    /// [`recv_response()`] completes, this is the HEADERS frame of a response.
+    /// It fails while a DATA payload is partially read, and the frame following
+    /// the body has already been read once [`RequestStream::recv_data()`]
+    /// returned `None`.
+    ///
+    /// [`recv_response()`]: #method.recv_response
+    pub async fn peek_frame(&mut self) -> Result<Option<(u64, u64)>, Error> {
+        future::poll_fn(|cx| self.inner.poll_peek_frame(cx)).await
+    }
+
+    /// Poll for the type and payload length of the next frame
+    ///
+    /// This is the poll-based counterpart of [`RequestStream::peek_frame()`].
+    pub fn poll_peek_frame(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<(u64, u64)>, Error>> {
+        self.inner.poll_peek_frame(cx)
+    }
+
     /// Receive an optional set of trailers for the response.
+    ///
+    /// Like [`RequestStream::recv_data()`], this fails until the final
+    /// response has been received.
     pub async fn recv_trailers(&mut self) -> Result<Option<HeaderMap>, Error> {
+        self.check_response_received()?;
         let res = self.inner.recv_trailers().await;
         if let Err(ref e) = res {
             if e.is_header_too_big() {
@@ -690,6 +1305,16 @@ where
         res
     }
 
+    // The body and trailers are only read after the final response, for the
+    // frames preceding it to go through `recv_response()`.
+    fn check_response_received(&self) -> Result<(), Error> {
+        if !self.response_received {
+            return Err(Code::H3_INTERNAL_ERROR
+                .with_reason("final response not received", ErrorLevel::StreamError));
+        }
+        Ok(())
+    }
+
     /// Tell the peer to stop sending into the underlying QUIC stream
     pub fn stop_sending(&mut self, error_code: crate::error::Code) {
         // TODO take by value to prevent any further call as this request is cancelled
@@ -704,15 +1329,37 @@ where
     B: Buf,
 {
     /// Send some data on the request body.
+    ///
+    /// This returns once the QUIC stream has accepted the frame, which is then
+    /// transmitted without waiting for more data or [`RequestStream::finish`].
     pub async fn send_data(&mut self, buf: B) -> Result<(), Error> {
         self.inner.send_data(buf).await
     }
 
+    /// Start sending some data on the body
+    ///
+    /// Together with [`RequestStream::poll_ready()`], this is the poll-based
+    /// counterpart of [`RequestStream::send_data()`]. The frame is written
+    /// as `poll_ready()` is polled, which must return `Ready` before the next
+    /// call, or this fails.
+    pub fn start_send_data(&mut self, buf: B) -> Result<(), Error> {
+        self.inner.start_send_data(buf)
+    }
+
+    /// Poll until the data passed to [`RequestStream::start_send_data()`] is written
+    ///
+    /// It is `Ready` right away when there is nothing to write.
+    pub fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.inner.poll_ready(cx)
+    }
+
     /// Send a set of trailers to end the request.
     ///
     /// Either [`RequestStream::finish`] or
     /// [`RequestStream::send_trailers`] must be called to finalize a
-    /// request.
+    /// request. Trailers can be sent after a partial body, or without any
+    /// body at all, and finish the stream: calling [`RequestStream::finish`]
+    /// afterwards does nothing.
     pub async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), Error> {
         self.inner.send_trailers(trailers).await
     }
@@ -748,6 +1395,43 @@ where
         RequestStream<S::RecvStream, B>,
     ) {
         let (send, recv) = self.inner.split();
-        (RequestStream { inner: send }, RequestStream { inner: recv })
+        (
+            RequestStream {
+                inner: send,
+                response_received: self.response_received,
+            },
+            RequestStream {
+                inner: recv,
+                response_received: self.response_received,
+            },
+        )
+    }
+
+    /// Join the halves returned by [`RequestStream::split()`] back together.
+    ///
+    /// Fails if the halves come from different streams, or if the QUIC
+    /// implementation cannot join them. Both halves are then given back in
+    /// the error.
+    #[allow(clippy::result_large_err)] // as large as `Self`
+    pub fn unsplit(
+        send: RequestStream<S::SendStream, B>,
+        recv: RequestStream<S::RecvStream, B>,
+    ) -> Result<Self, Halves<S, B>> {
+        match connection::RequestStream::unsplit(send.inner, recv.inner) {
+            Ok(inner) => Ok(RequestStream {
+                inner,
+                response_received: recv.response_received,
+            }),
+            Err((send_inner, recv_inner)) => Err(ReuniteError(
+                RequestStream {
+                    inner: send_inner,
+                    ..send
+                },
+                RequestStream {
+                    inner: recv_inner,
+                    ..recv
+                },
+            )),
+        }
     }
 }
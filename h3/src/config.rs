@@ -0,0 +1,487 @@
+//! HTTP/3 connection configuration
+//!
+//! Settings shared by the client and server builders, and the SETTINGS
+//! received from the peer.
+
+use std::{fmt, net::SocketAddr, sync::Arc, time::Duration};
+
+use bytes::Buf;
+use http::HeaderMap;
+
+use crate::{
+    error::Code,
+    proto::{
+        frame::{SettingId, Settings, SettingsError},
+        push::PushId,
+        varint::VarInt,
+    },
+    quic::StreamId,
+    stream::WriteBuf,
+};
+
+/// Called with the stream ID and error code of a request stream reset or
+/// stopped by the peer
+pub(crate) type StreamResetHook = Arc<dyn Fn(StreamId, Code) + Send + Sync>;
+
+/// Called with the new address of the peer when the connection migrates
+pub(crate) type PathChangeHook = Arc<dyn Fn(SocketAddr) + Send + Sync>;
+
+/// Configuration shared by [`crate::client::Builder`] and [`crate::server::Builder`]
+#[derive(Clone)]
+pub(crate) struct Config {
+    /// Maximum size of a header section this endpoint is willing to accept
+    pub(crate) max_field_section_size: u64,
+    /// Maximum size of a response header section the server is willing to
+    /// send, on top of the limit advertised by the client
+    pub(crate) max_response_field_section_size: u64,
+    /// Send grease settings, frames and streams
+    pub(crate) send_grease: bool,
+    /// Request streams on which a grease frame is sent, when `send_grease` is set
+    pub(crate) grease_frames: GreaseFrames,
+    /// Additional SETTINGS parameters advertised to the peer
+    pub(crate) extra_settings: Vec<(SettingId, u64)>,
+    /// Allow extra settings to use defined or reserved identifiers
+    pub(crate) allow_reserved_settings: bool,
+    /// Allow opening unidirectional streams of defined or grease types
+    pub(crate) allow_reserved_stream_types: bool,
+    /// QUIC priority of the control stream
+    pub(crate) control_stream_priority: i32,
+    /// Maximum number of peer unidirectional streams kept while their type is unknown
+    pub(crate) max_pending_uni_streams: usize,
+    /// Maximum number of consecutive empty DATA frames on request streams
+    pub(crate) max_empty_data_frames: usize,
+    /// Maximum number of requests the client may reset before sending their
+    /// headers, within `early_reset_window`
+    pub(crate) max_early_resets: usize,
+    /// Period over which requests reset before their headers are counted
+    pub(crate) early_reset_window: Duration,
+    /// Send GOAWAY when a server accept future is dropped before completing
+    pub(crate) goaway_on_accept_drop: bool,
+    /// Reports request streams reset by the peer
+    pub(crate) on_stream_reset: Option<StreamResetHook>,
+    /// Reports the peer address changes of the connection
+    pub(crate) on_path_change: Option<PathChangeHook>,
+    /// Number of encoded response header blocks kept for reuse, 0 to disable
+    pub(crate) response_cache_size: usize,
+    /// Maximum number of fields in a request header section
+    pub(crate) max_header_fields: usize,
+    /// Attach the encoded request header block to the request extensions
+    pub(crate) capture_raw_headers: bool,
+    /// Reject response trailers not declared in the `Trailer` header
+    pub(crate) enforce_declared_trailers: bool,
+    /// Fields added to every final response which does not set them
+    pub(crate) default_response_headers: HeaderMap,
+    /// MAX_PUSH_ID sent right after the client SETTINGS, if any
+    pub(crate) initial_max_push_id: Option<PushId>,
+    /// Reports the raw bytes of the streams
+    pub(crate) tap: Tap,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_field_section_size: VarInt::MAX.0,
+            max_response_field_section_size: VarInt::MAX.0,
+            send_grease: true,
+            grease_frames: GreaseFrames::Once,
+            extra_settings: Vec::new(),
+            allow_reserved_settings: false,
+            allow_reserved_stream_types: false,
+            control_stream_priority: i32::MAX,
+            max_pending_uni_streams: 100,
+            max_empty_data_frames: 32,
+            max_early_resets: 100,
+            early_reset_window: Duration::from_secs(10),
+            goaway_on_accept_drop: false,
+            on_stream_reset: None,
+            on_path_change: None,
+            response_cache_size: 0,
+            max_header_fields: usize::MAX,
+            capture_raw_headers: false,
+            enforce_declared_trailers: false,
+            default_response_headers: HeaderMap::new(),
+            initial_max_push_id: None,
+            tap: Tap::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Check the configuration is consistent before building a connection
+    ///
+    /// Extra settings using a defined or grease identifier are only accepted
+    /// when `allow_reserved_settings` is set, for users who intentionally
+    /// want to exercise them.
+    pub(crate) fn validate(&self) -> Result<(), SettingsError> {
+        if VarInt::from_u64(self.max_field_section_size).is_err() {
+            return Err(SettingsError::InvalidSettingValue(
+                SettingId::MAX_HEADER_LIST_SIZE,
+                self.max_field_section_size,
+            ));
+        }
+
+        for (i, &(id, value)) in self.extra_settings.iter().enumerate() {
+            //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.4.1
+            //# Setting identifiers that were defined in [HTTP/2] where there is no
+            //# corresponding HTTP/3 setting have also been reserved
+            //# (Section 11.2.2).  These reserved settings MUST NOT be sent, and
+            //# their receipt MUST be treated as a connection error of type
+            //# H3_SETTINGS_ERROR.
+            if id.is_forbidden() || VarInt::from_u64(id.0).is_err() {
+                return Err(SettingsError::InvalidSettingId(id.0));
+            }
+            // Defined and grease identifiers have a meaning h3 does not control,
+            // the user has to opt in to send them. Extensions are only enabled
+            // by the user, so they can always be sent.
+            let reserved = (id.is_supported() && !id.is_extension()) || id.is_grease();
+            if !self.allow_reserved_settings && reserved {
+                return Err(SettingsError::InvalidSettingId(id.0));
+            }
+            if VarInt::from_u64(value).is_err() {
+                return Err(SettingsError::InvalidSettingValue(id, value));
+            }
+
+            //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.4
+            //# The same setting identifier MUST NOT occur more than once in the
+            //# SETTINGS frame.
+            if id == SettingId::MAX_HEADER_LIST_SIZE
+                || self.extra_settings[..i].iter().any(|(prev, _)| *prev == id)
+            {
+                return Err(SettingsError::Repeated(id));
+            }
+        }
+
+        // The peer would drop unknown settings above this
+        let unknown = self
+            .extra_settings
+            .iter()
+            .filter(|(id, _)| !id.is_supported())
+            .count();
+        // Keep room for the grease setting
+        if unknown > Settings::UNKNOWN_CAPACITY
+            || self.extra_settings.len() + 2 > Settings::CAPACITY
+        {
+            return Err(SettingsError::Exceeded);
+        }
+
+        Ok(())
+    }
+
+    /// Build the SETTINGS frame sent on the control stream, without grease
+    ///
+    /// The configuration must have been checked with [`Config::validate()`].
+    pub(crate) fn settings(&self) -> Result<Settings, SettingsError> {
+        let mut settings = Settings::default();
+        settings.insert(SettingId::MAX_HEADER_LIST_SIZE, self.max_field_section_size)?;
+        for &(id, value) in self.extra_settings.iter() {
+            settings.insert(id, value)?;
+        }
+        Ok(settings)
+    }
+}
+
+/// Request streams carrying a grease frame
+///
+/// Grease frames have a reserved type which the peer must ignore, they are
+/// sent right before finishing the stream. Nothing is sent when grease is
+/// disabled altogether.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GreaseFrames {
+    /// No request stream
+    Never,
+    /// The first request stream of the connection, the default
+    Once,
+    /// Every request stream
+    EveryRequest,
+}
+
+impl Config {
+    /// Whether the first request stream gets a grease frame
+    pub(crate) fn grease_first_request(&self) -> bool {
+        self.send_grease && self.grease_frames != GreaseFrames::Never
+    }
+}
+
+/// Direction of the bytes reported to a wire tap
+#[cfg(feature = "wire-tap")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Written to a QUIC stream
+    Sent,
+    /// Read from a QUIC stream
+    Received,
+}
+
+/// Called with the raw bytes written to or read from QUIC streams
+#[cfg(feature = "wire-tap")]
+pub(crate) type WireTap = Arc<dyn Fn(Direction, &[u8]) + Send + Sync>;
+
+/// Reports the raw bytes of the streams h3 reads and writes
+///
+/// Without the `wire-tap` feature, this has no field and every method is a
+/// no-op, so the tap costs nothing.
+#[derive(Clone, Default)]
+pub(crate) struct Tap {
+    #[cfg(feature = "wire-tap")]
+    tap: Option<WireTap>,
+}
+
+impl Tap {
+    #[cfg(feature = "wire-tap")]
+    pub(crate) fn new(tap: WireTap) -> Self {
+        Self { tap: Some(tap) }
+    }
+
+    #[inline]
+    pub(crate) fn is_set(&self) -> bool {
+        #[cfg(feature = "wire-tap")]
+        return self.tap.is_some();
+        #[cfg(not(feature = "wire-tap"))]
+        false
+    }
+
+    #[inline]
+    pub(crate) fn sent(&self, _bytes: &[u8]) {
+        #[cfg(feature = "wire-tap")]
+        if let Some(ref tap) = self.tap {
+            tap(Direction::Sent, _bytes);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn received(&self, _bytes: &[u8]) {
+        #[cfg(feature = "wire-tap")]
+        if let Some(ref tap) = self.tap {
+            tap(Direction::Received, _bytes);
+        }
+    }
+
+    /// Prepare data to be written, its bytes are reported as the transport takes them
+    pub(crate) fn wrap<B, D>(&self, data: D) -> WriteBuf<B>
+    where
+        B: Buf,
+        D: Into<WriteBuf<B>>,
+    {
+        let mut buf = data.into();
+        if self.is_set() {
+            buf.set_tap(self.clone());
+        }
+        buf
+    }
+}
+
+/// SETTINGS received from the peer
+///
+/// Holds every parameter of the peer's SETTINGS frame, including identifiers
+/// unknown to h3, so extensions can be prototyped on top of it. Grease
+/// identifiers are not retained.
+#[derive(Clone)]
+pub struct PeerSettings {
+    inner: Settings,
+}
+
+impl PeerSettings {
+    pub(crate) fn new(inner: Settings) -> Self {
+        Self { inner }
+    }
+
+    /// Get the value of the setting with identifier `id`, if the peer sent it
+    pub fn get(&self, id: u64) -> Option<u64> {
+        self.inner.get(SettingId(id))
+    }
+
+    /// The peer's SETTINGS_MAX_FIELD_SECTION_SIZE, if it sent one
+    pub fn max_field_section_size(&self) -> Option<u64> {
+        self.inner.get(SettingId::MAX_HEADER_LIST_SIZE)
+    }
+
+    /// Did the peer enable extended CONNECT (SETTINGS_ENABLE_CONNECT_PROTOCOL)?
+    pub fn enable_connect_protocol(&self) -> bool {
+        self.inner.get(SettingId::ENABLE_CONNECT_PROTOCOL) == Some(1)
+    }
+
+    /// Did the peer enable HTTP datagrams (SETTINGS_H3_DATAGRAM)?
+    pub fn h3_datagram(&self) -> bool {
+        self.inner.get(SettingId::H3_DATAGRAM) == Some(1)
+    }
+
+    /// Did the peer enable WebTransport (SETTINGS_ENABLE_WEBTRANSPORT)?
+    pub fn enable_webtransport(&self) -> bool {
+        self.inner.get(SettingId::ENABLE_WEBTRANSPORT) == Some(1)
+    }
+
+    /// Iterate over all `(identifier, value)` pairs sent by the peer
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.inner.iter().map(|(id, value)| (id.0, *value))
+    }
+}
+
+impl fmt::Debug for PeerSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.iter().map(|(id, value)| (format!("{:#x}", id), value)))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::frame::Frame;
+    use assert_matches::assert_matches;
+    use bytes::Bytes;
+    use std::io::Cursor;
+
+    fn config_with(id: u64, allow_reserved: bool) -> Config {
+        Config {
+            extra_settings: vec![(SettingId(id), 1)],
+            allow_reserved_settings: allow_reserved,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn extra_setting_is_sent() {
+        let config = config_with(0x4242, false);
+        assert_matches!(config.validate(), Ok(()));
+        assert_eq!(config.settings().unwrap().get(SettingId(0x4242)), Some(1));
+    }
+
+    #[test]
+    fn extra_setting_reserved_rejected() {
+        assert_matches!(
+            config_with(0x1f * 3 + 0x21, false).validate(),
+            Err(SettingsError::InvalidSettingId(_))
+        );
+        assert_matches!(
+            config_with(SettingId::QPACK_MAX_TABLE_CAPACITY.0, false).validate(),
+            Err(SettingsError::InvalidSettingId(_))
+        );
+    }
+
+    #[test]
+    fn extra_setting_reserved_allowed() {
+        assert_matches!(config_with(0x1f * 3 + 0x21, true).validate(), Ok(()));
+        assert_matches!(
+            config_with(SettingId::QPACK_MAX_TABLE_CAPACITY.0, true).validate(),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn extra_setting_never_forbidden_or_repeated() {
+        assert_matches!(
+            config_with(0x2, true).validate(),
+            Err(SettingsError::InvalidSettingId(0x2))
+        );
+        assert_matches!(
+            config_with(SettingId::MAX_HEADER_LIST_SIZE.0, true).validate(),
+            Err(SettingsError::Repeated(SettingId::MAX_HEADER_LIST_SIZE))
+        );
+
+        let mut config = config_with(0x4242, false);
+        config.extra_settings.push((SettingId(0x4242), 2));
+        assert_matches!(
+            config.validate(),
+            Err(SettingsError::Repeated(SettingId(0x4242)))
+        );
+    }
+
+    #[test]
+    fn values_out_of_varint_bounds() {
+        let config = Config {
+            max_field_section_size: u64::MAX,
+            ..Config::default()
+        };
+        assert_matches!(
+            config.validate(),
+            Err(SettingsError::InvalidSettingValue(
+                SettingId::MAX_HEADER_LIST_SIZE,
+                u64::MAX
+            ))
+        );
+
+        let mut config = config_with(0x4242, false);
+        config.extra_settings[0].1 = u64::MAX;
+        assert_matches!(
+            config.validate(),
+            Err(SettingsError::InvalidSettingValue(_, u64::MAX))
+        );
+        assert_matches!(
+            config_with(u64::MAX, true).validate(),
+            Err(SettingsError::InvalidSettingId(u64::MAX))
+        );
+    }
+
+    fn config_with_unknown(count: usize) -> Config {
+        Config {
+            extra_settings: (0..count as u64)
+                .map(|i| (SettingId(0x4242 + i), i))
+                .collect(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn too_many_extra_settings() {
+        let config = config_with_unknown(Settings::UNKNOWN_CAPACITY + 1);
+        assert_matches!(config.validate(), Err(SettingsError::Exceeded));
+    }
+
+    #[test]
+    fn extra_settings_round_trip_at_limit() {
+        round_trip(config_with_unknown(Settings::UNKNOWN_CAPACITY));
+    }
+
+    #[test]
+    fn extension_settings_round_trip_with_unknown_full() {
+        let mut config = config_with_unknown(Settings::UNKNOWN_CAPACITY);
+        config.extra_settings.extend(&[
+            (SettingId::ENABLE_CONNECT_PROTOCOL, 1),
+            (SettingId::H3_DATAGRAM, 1),
+            (SettingId::ENABLE_WEBTRANSPORT, 1),
+        ]);
+        round_trip(config);
+    }
+
+    fn round_trip(config: Config) {
+        assert_matches!(config.validate(), Ok(()));
+        let mut settings = config.settings().unwrap();
+        settings.insert(SettingId::grease(), 0).unwrap();
+
+        let mut buf = Vec::new();
+        Frame::<Bytes>::Settings(settings).encode_with_payload(&mut buf);
+        let decoded = match Frame::decode(&mut Cursor::new(&buf)) {
+            Ok(Frame::Settings(settings)) => PeerSettings::new(settings),
+            frame => panic!("unexpected {:?}", frame),
+        };
+        assert_eq!(
+            decoded.get(SettingId::MAX_HEADER_LIST_SIZE.0),
+            Some(config.max_field_section_size)
+        );
+        for &(id, value) in &config.extra_settings {
+            assert_eq!(decoded.get(id.0), Some(value));
+        }
+    }
+
+    #[test]
+    fn peer_capabilities() {
+        let mut settings = Settings::default();
+        settings
+            .insert(SettingId::ENABLE_CONNECT_PROTOCOL, 1)
+            .unwrap();
+        settings.insert(SettingId::H3_DATAGRAM, 0).unwrap();
+        let peer = PeerSettings::new(settings);
+        assert!(peer.enable_connect_protocol());
+        assert!(!peer.h3_datagram());
+        assert!(!peer.enable_webtransport());
+
+        let mut settings = Settings::default();
+        settings.insert(SettingId::H3_DATAGRAM, 1).unwrap();
+        settings.insert(SettingId::ENABLE_WEBTRANSPORT, 1).unwrap();
+        let peer = PeerSettings::new(settings);
+        assert!(!peer.enable_connect_protocol());
+        assert!(peer.h3_datagram());
+        assert!(peer.enable_webtransport());
+    }
+}
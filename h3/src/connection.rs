@@ -1,25 +1,30 @@
 use std::{
+    collections::{HashMap, HashSet, VecDeque},
     convert::TryFrom,
-    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
-    task::{Context, Poll},
+    net::SocketAddr,
+    sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    task::{Context, Poll, Waker},
 };
 
 use bytes::{Buf, Bytes, BytesMut};
 use futures_util::{future, ready};
-use http::HeaderMap;
+use http::{HeaderMap, Request};
 use tracing::warn;
 
 use crate::{
-    error::{Code, Error},
+    config::{Config, GreaseFrames, PathChangeHook, PeerSettings, StreamResetHook, Tap},
+    error::{CloseOrigin, Code, Error},
     frame::FrameStream,
     proto::{
-        frame::{Frame, PayloadLen, SettingId, Settings},
+        frame::{Frame, FrameType, PayloadLen, PushPromise, SettingId, StreamKind},
         headers::Header,
-        stream::StreamType,
+        push::PushId,
+        stream::{StreamId, StreamType},
         varint::VarInt,
     },
     qpack,
     quic::{self, SendStream as _},
+    stats::{ConnectionCounters, StreamStats},
     stream::{self, AcceptRecvStream, AcceptedRecvStream},
 };
 
@@ -31,11 +36,66 @@ pub struct SharedState {
     pub error: Option<Error>,
     // Has a GOAWAY frame been sent or received?
     pub closing: bool,
+    // Has the peer's SETTINGS frame been received?
+    pub settings_received: bool,
+    // Tasks waiting for the peer's SETTINGS frame, or for the connection to fail
+    pub settings_waiters: Vec<Waker>,
+    // Has a GOAWAY frame been received?
+    pub goaway_received: bool,
+    // Tasks waiting for the peer's GOAWAY frame
+    pub goaway_waiters: Vec<Waker>,
+    // maximum number of consecutive empty DATA frames we receive on a request stream
+    pub max_empty_data_frames: usize,
+    // connection error found on a request stream, for the driver to close with
+    pub close_request: Option<(Code, &'static str)>,
+    // task driving the connection, woken to act on `close_request`
+    pub driver_waker: Option<Waker>,
+    // highest push ID allowed with a MAX_PUSH_ID frame, none until one is sent
+    pub max_push_id: Option<PushId>,
+    // push IDs of the PUSH_PROMISE frames received
+    pub promised_pushes: HashSet<PushId>,
+    // reports request streams reset by the peer
+    pub on_stream_reset: Option<StreamResetHook>,
+}
+
+impl SharedState {
+    /// Have the driver close the connection, from a request stream
+    pub fn request_close(&mut self, code: Code, reason: &'static str) {
+        self.close_request.get_or_insert((code, reason));
+        if let Some(waker) = self.driver_waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Wake the tasks waiting for the peer's SETTINGS
+    pub fn wake_settings_waiters(&mut self) {
+        for waker in self.settings_waiters.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Longest reason sent in a CONNECTION_CLOSE frame, in bytes
+///
+/// The frame has to fit in a single packet, which can be as small as 1200
+/// bytes.
+pub(crate) const MAX_CLOSE_REASON_LEN: usize = 1024;
+
+/// Cut `reason` to at most `MAX_CLOSE_REASON_LEN` bytes, on a char boundary
+fn truncate_close_reason(reason: &str) -> &str {
+    if reason.len() <= MAX_CLOSE_REASON_LEN {
+        return reason;
+    }
+    let mut end = MAX_CLOSE_REASON_LEN;
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    &reason[..end]
 }
 
 #[derive(Clone)]
 #[doc(hidden)]
-pub struct SharedStateRef(Arc<RwLock<SharedState>>);
+pub struct SharedStateRef(Arc<RwLock<SharedState>>, Arc<ConnectionCounters>);
 
 impl SharedStateRef {
     pub fn read(&self, panic_msg: &'static str) -> RwLockReadGuard<SharedState> {
@@ -45,15 +105,50 @@ impl SharedStateRef {
     pub fn write(&self, panic_msg: &'static str) -> RwLockWriteGuard<SharedState> {
         self.0.write().expect(panic_msg)
     }
+
+    /// Statistics counters of the connection, readable without locking
+    pub(crate) fn counters(&self) -> &ConnectionCounters {
+        &self.1
+    }
+
+    /// Ready once a GOAWAY frame has been received from the peer
+    pub(crate) fn poll_goaway(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.write("poll goaway");
+        if state.goaway_received {
+            return Poll::Ready(());
+        }
+        if !state.goaway_waiters.iter().any(|w| w.will_wake(cx.waker())) {
+            state.goaway_waiters.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+
+    /// Do both references point to the state of the same connection?
+    pub(crate) fn same_connection(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
 }
 
 impl Default for SharedStateRef {
     fn default() -> Self {
-        Self(Arc::new(RwLock::new(SharedState {
-            peer_max_field_section_size: VarInt::MAX.0,
-            error: None,
-            closing: false,
-        })))
+        Self(
+            Arc::new(RwLock::new(SharedState {
+                peer_max_field_section_size: VarInt::MAX.0,
+                error: None,
+                closing: false,
+                settings_received: false,
+                settings_waiters: Vec::new(),
+                goaway_received: false,
+                goaway_waiters: Vec::new(),
+                max_empty_data_frames: usize::MAX,
+                close_request: None,
+                driver_waker: None,
+                max_push_id: None,
+                promised_pushes: HashSet::new(),
+                on_stream_reset: None,
+            })),
+            Arc::default(),
+        )
     }
 }
 
@@ -69,6 +164,9 @@ pub trait ConnectionState {
     }
 }
 
+/// Push streams received, with their push and stream IDs
+type PushStreams<S, B> = VecDeque<(PushId, StreamId, FrameStream<S, B>)>;
+
 pub struct ConnectionInner<C, B>
 where
     C: quic::Connection<B>,
@@ -81,8 +179,24 @@ where
     decoder_recv: Option<AcceptedRecvStream<C::RecvStream, B>>,
     encoder_recv: Option<AcceptedRecvStream<C::RecvStream, B>>,
     pending_recv_streams: Vec<AcceptRecvStream<C::RecvStream>>,
-    got_peer_settings: bool,
+    max_pending_uni_streams: usize,
+    peer_settings: Option<PeerSettings>,
+    // Has the QUIC connection been closed by us?
+    closed: bool,
     pub(super) send_grease_frame: bool,
+    // Does every request stream get a grease frame, not only the first one?
+    pub(super) grease_every_request: bool,
+    allow_reserved_stream_types: bool,
+    // push streams received and not taken yet, `None` unless they are accepted
+    push_streams: Option<PushStreams<C::RecvStream, B>>,
+    // push IDs of all the push streams received
+    push_ids_received: HashSet<PushId>,
+    // pushes cancelled by us, with the code stopping their push stream
+    rejected_pushes: HashMap<PushId, Code>,
+    // reports the bytes of every stream
+    pub(super) tap: Tap,
+    // reports the connection migrating to another path
+    on_path_change: Option<PathChangeHook>,
 }
 
 impl<C, B> ConnectionInner<C, B>
@@ -90,12 +204,16 @@ where
     C: quic::Connection<B>,
     B: Buf,
 {
-    pub async fn new(
-        mut conn: C,
-        max_field_section_size: u64,
-        shared: SharedStateRef,
-        grease: bool,
-    ) -> Result<Self, Error> {
+    pub async fn new(mut conn: C, shared: SharedStateRef, config: &Config) -> Result<Self, Error> {
+        {
+            let mut shared = shared.write("connection config");
+            shared.max_empty_data_frames = config.max_empty_data_frames;
+            shared.on_stream_reset = config.on_stream_reset.clone();
+        }
+        let mut settings = config
+            .settings()
+            .map_err(|e| Code::H3_INTERNAL_ERROR.with_cause(e))?;
+
         //= https://www.rfc-editor.org/rfc/rfc9114#section-6.2
         //# Endpoints SHOULD create the HTTP control stream as well as the
         //# unidirectional streams required by mandatory extensions (such as the
@@ -104,13 +222,10 @@ where
         let mut control_send = future::poll_fn(|cx| conn.poll_open_send(cx))
             .await
             .map_err(|e| Code::H3_STREAM_CREATION_ERROR.with_transport(e))?;
+        // SETTINGS and GOAWAY must not wait behind request data
+        control_send.set_priority(config.control_stream_priority);
 
-        let mut settings = Settings::default();
-        settings
-            .insert(SettingId::MAX_HEADER_LIST_SIZE, max_field_section_size)
-            .map_err(|e| Code::H3_INTERNAL_ERROR.with_cause(e))?;
-
-        if grease {
+        if config.send_grease {
             //  Grease Settings (https://www.rfc-editor.org/rfc/rfc9114.html#name-defined-settings-parameters)
             //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.4.1
             //# Setting identifiers of the format 0x1f * N + 0x21 for non-negative
@@ -158,7 +273,9 @@ where
         //# as soon as the transport is ready to send data.
         stream::write(
             &mut control_send,
-            (StreamType::CONTROL, Frame::Settings(settings)),
+            config
+                .tap
+                .wrap((StreamType::CONTROL, Frame::Settings(settings))),
         )
         .await?;
 
@@ -175,11 +292,20 @@ where
             decoder_recv: None,
             encoder_recv: None,
             pending_recv_streams: Vec::with_capacity(3),
-            got_peer_settings: false,
-            send_grease_frame: grease,
+            max_pending_uni_streams: config.max_pending_uni_streams,
+            peer_settings: None,
+            closed: false,
+            send_grease_frame: config.grease_first_request(),
+            grease_every_request: config.grease_frames == GreaseFrames::EveryRequest,
+            allow_reserved_stream_types: config.allow_reserved_stream_types,
+            push_streams: None,
+            push_ids_received: HashSet::new(),
+            rejected_pushes: HashMap::new(),
+            tap: config.tap.clone(),
+            on_path_change: config.on_path_change.clone(),
         };
         // start a grease stream
-        if grease {
+        if config.send_grease {
             //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.8
             //= type=implication
             //# Frame types of the format 0x1f * N + 0x21 for non-negative integer
@@ -198,13 +324,44 @@ where
         sent_closing: &mut Option<T>,
         max_id: T,
     ) -> Result<(), Error>
+    where
+        T: From<VarInt> + PartialOrd<T> + Copy,
+        VarInt: From<T>,
+    {
+        if self.send_goaway(sent_closing, max_id)? {
+            future::poll_fn(|cx| self.control_send.poll_ready(cx)).await?;
+        }
+        Ok(())
+    }
+
+    /// Send GOAWAY like [`ConnectionInner::shutdown()`], without waiting for it
+    ///
+    /// For when awaiting is not possible. The frame is handed to the transport
+    /// right away, it is only written later if the transport is blocked and the
+    /// control stream is written to again.
+    pub fn shutdown_now<T>(&mut self, sent_closing: &mut Option<T>, max_id: T) -> Result<(), Error>
+    where
+        T: From<VarInt> + PartialOrd<T> + Copy,
+        VarInt: From<T>,
+    {
+        if self.send_goaway(sent_closing, max_id)? {
+            let mut cx = Context::from_waker(futures_util::task::noop_waker_ref());
+            if let Poll::Ready(res) = self.control_send.poll_ready(&mut cx) {
+                res?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Queue GOAWAY on the control stream, returns `false` if it was not needed
+    fn send_goaway<T>(&mut self, sent_closing: &mut Option<T>, max_id: T) -> Result<bool, Error>
     where
         T: From<VarInt> + PartialOrd<T> + Copy,
         VarInt: From<T>,
     {
         if let Some(sent_id) = sent_closing {
             if *sent_id <= max_id {
-                return Ok(());
+                return Ok(false);
             }
         }
 
@@ -217,7 +374,121 @@ where
         //# (Section 5.2) so that both endpoints can reliably determine whether
         //# previously sent frames have been processed and gracefully complete or
         //# terminate any necessary remaining tasks.
-        stream::write(&mut self.control_send, Frame::Goaway(max_id.into())).await
+        self.control_send
+            .send_data(self.tap.wrap(Frame::Goaway(max_id.into())))?;
+        Ok(true)
+    }
+
+    /// Keep the push streams received, which only clients may do
+    pub fn accept_push_streams(&mut self) {
+        self.push_streams = Some(VecDeque::new());
+    }
+
+    /// Take the oldest push stream received
+    pub fn next_push_stream(
+        &mut self,
+    ) -> Option<(PushId, StreamId, FrameStream<C::RecvStream, B>)> {
+        self.push_streams.as_mut()?.pop_front()
+    }
+
+    /// Allow the peer to push with IDs up to `max_push_id`
+    pub async fn send_max_push_id(&mut self, max_push_id: PushId) -> Result<(), Error> {
+        {
+            let mut state = self.shared.write("send_max_push_id");
+            match state.max_push_id {
+                Some(id) if id == max_push_id => return Ok(()),
+                // The peer would treat a smaller value as a connection error
+                Some(id) if id > max_push_id => {
+                    return Err(Code::H3_INTERNAL_ERROR.with_reason(
+                        format!("cannot lower MAX_PUSH_ID below {}", id.0),
+                        crate::error::ErrorLevel::StreamError,
+                    ))
+                }
+                // Promises may arrive as soon as the frame is sent
+                _ => state.max_push_id = Some(max_push_id),
+            }
+        }
+        stream::write(
+            &mut self.control_send,
+            self.tap.wrap(Frame::MaxPushId(max_push_id)),
+        )
+        .await
+    }
+
+    /// Cancel a promised push, stopping its push stream with `code`
+    pub async fn cancel_push(&mut self, push_id: PushId, code: Code) -> Result<(), Error> {
+        // The peer would treat an ID it has not promised as a connection error
+        if !self
+            .shared
+            .read("cancel_push")
+            .promised_pushes
+            .contains(&push_id)
+        {
+            return Err(Code::H3_INTERNAL_ERROR.with_reason(
+                format!("{} has not been promised", push_id),
+                crate::error::ErrorLevel::StreamError,
+            ));
+        }
+        if self.rejected_pushes.insert(push_id, code).is_some() {
+            return Ok(());
+        }
+
+        // A push stream not accepted yet is stopped right away, later ones on arrival
+        if let Some(push_streams) = self.push_streams.as_mut() {
+            if let Some(pos) = push_streams.iter().position(|(id, _, _)| *id == push_id) {
+                if let Some((_, _, mut stream)) = push_streams.remove(pos) {
+                    stream.stop_sending(code);
+                }
+            }
+        }
+
+        stream::write(
+            &mut self.control_send,
+            self.tap.wrap(Frame::CancelPush(push_id)),
+        )
+        .await
+    }
+
+    /// Send a reserved frame on the control stream, which the peer ignores
+    pub async fn send_keepalive(&mut self) -> Result<(), Error> {
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.8
+        //= type=implication
+        //# These frames have no semantics, and
+        //# they MAY be sent on any stream where frames are allowed to be sent.
+        stream::write(&mut self.control_send, self.tap.wrap(Frame::Grease)).await
+    }
+
+    /// Send a frame of an extension type on the control stream
+    ///
+    /// SETTINGS is always the first frame, as it is sent when the connection
+    /// is created.
+    pub async fn send_control_frame(&mut self, ty: u64, payload: Bytes) -> Result<(), Error> {
+        let ty = FrameType::extension(ty).ok_or_else(|| {
+            Code::H3_INTERNAL_ERROR.with_reason(
+                format!("frame type {:#x} is not an extension", ty),
+                crate::error::ErrorLevel::StreamError,
+            )
+        })?;
+        stream::write(
+            &mut self.control_send,
+            self.tap.wrap(Frame::Extension(ty, payload)),
+        )
+        .await
+    }
+
+    /// Open a unidirectional stream and write `ty` as its stream type
+    pub async fn open_uni(&mut self, ty: u64) -> Result<C::SendStream, Error> {
+        let ty = StreamType::extension(ty, self.allow_reserved_stream_types).ok_or_else(|| {
+            Code::H3_INTERNAL_ERROR.with_reason(
+                format!("stream type {:#x} is not an extension", ty),
+                crate::error::ErrorLevel::StreamError,
+            )
+        })?;
+        let mut stream = future::poll_fn(|cx| self.conn.poll_open_send(cx))
+            .await
+            .map_err(|e| Code::H3_STREAM_CREATION_ERROR.with_transport(e))?;
+        stream::write(&mut stream, self.tap.wrap(ty)).await?;
+        Ok(stream)
     }
 
     pub fn poll_accept_request(
@@ -243,9 +514,21 @@ where
 
         loop {
             match self.conn.poll_accept_recv(cx)? {
-                Poll::Ready(Some(stream)) => self
-                    .pending_recv_streams
-                    .push(AcceptRecvStream::new(stream)),
+                Poll::Ready(Some(stream)) => {
+                    //= https://www.rfc-editor.org/rfc/rfc9114#section-10.5
+                    //# An endpoint MAY
+                    //# treat activity that is suspicious as a connection error of type
+                    //# H3_EXCESSIVE_LOAD, but false positives will result in disrupting
+                    //# valid connections and requests.
+                    if self.pending_recv_streams.len() >= self.max_pending_uni_streams {
+                        return Poll::Ready(Err(self.close(
+                            Code::H3_EXCESSIVE_LOAD,
+                            "too many unidirectional streams without a type",
+                        )));
+                    }
+                    self.pending_recv_streams
+                        .push(AcceptRecvStream::new(stream, self.tap.clone()))
+                }
                 Poll::Ready(None) => {
                     return Poll::Ready(Err(Code::H3_GENERAL_PROTOCOL_ERROR.with_reason(
                         "Connection closed unexpected",
@@ -302,6 +585,30 @@ where
                         ));
                     }
                 }
+                AcceptedRecvStream::Push(push_id, stream_id, mut stream)
+                    if self.push_streams.is_some() =>
+                {
+                    let push_id = PushId(push_id);
+                    let max_push_id = self.shared.read("accept push stream").max_push_id;
+                    if !matches!(max_push_id, Some(max) if push_id <= max) {
+                        return Poll::Ready(Err(self.close(
+                            Code::H3_ID_ERROR,
+                            format!("{} is above the maximum push ID", push_id),
+                        )));
+                    }
+                    // Each push is fulfilled by a single stream
+                    if !self.push_ids_received.insert(push_id) {
+                        return Poll::Ready(Err(self.close(
+                            Code::H3_ID_ERROR,
+                            format!("{} received on two push streams", push_id),
+                        )));
+                    }
+                    if let Some(code) = self.rejected_pushes.get(&push_id) {
+                        stream.stop_sending(*code);
+                    } else if let Some(push_streams) = self.push_streams.as_mut() {
+                        push_streams.push_back((push_id, stream_id, stream));
+                    }
+                }
 
                 //= https://www.rfc-editor.org/rfc/rfc9114#section-6.2.3
                 //= type=implication
@@ -319,6 +626,24 @@ where
             return Poll::Ready(Err(e.clone()));
         }
 
+        // Request streams have no access to the QUIC connection, they leave
+        // closing it to the driver
+        let mut shared = self.shared.write("poll_control close request");
+        if let Some((code, reason)) = shared.close_request.take() {
+            drop(shared);
+            return Poll::Ready(Err(self.close(code, reason)));
+        }
+        if !matches!(shared.driver_waker, Some(ref w) if w.will_wake(cx.waker())) {
+            shared.driver_waker = Some(cx.waker().clone());
+        }
+        drop(shared);
+
+        if let Some(ref hook) = self.on_path_change {
+            while let Poll::Ready(addr) = self.conn.poll_path_change(cx) {
+                hook(addr);
+            }
+        }
+
         loop {
             match self.poll_accept_recv(cx) {
                 Poll::Ready(Ok(_)) => continue,
@@ -341,23 +666,27 @@ where
             //# error of type H3_CLOSED_CRITICAL_STREAM.
             None => Err(self.close(Code::H3_CLOSED_CRITICAL_STREAM, "control stream closed")),
             Some(frame) => {
-                match frame {
-                    Frame::Settings(settings) if !self.got_peer_settings => {
-                        //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.4
-                        //= type=TODO
-                        //# A receiver MAY treat the presence of duplicate
-                        //# setting identifiers as a connection error of type H3_SETTINGS_ERROR.
-
-                        //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.4.1
-                        //= type=TODO
-                        //# Setting identifiers that were defined in [HTTP/2] where there is no
-                        //# corresponding HTTP/3 setting have also been reserved
-                        //# (Section 11.2.2).  These reserved settings MUST NOT be sent, and
-                        //# their receipt MUST be treated as a connection error of type
-                        //# H3_SETTINGS_ERROR.
+                self.shared.counters().control_frames_recv.add(1);
+                if !frame.is_allowed_on(StreamKind::Control) {
+                    //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
+                    //# Receipt of an invalid sequence of frames MUST be treated as a
+                    //# connection error of type H3_FRAME_UNEXPECTED.
 
-                        self.got_peer_settings = true;
+                    //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.1
+                    //# If
+                    //# a DATA frame is received on a control stream, the recipient MUST
+                    //# respond with a connection error of type H3_FRAME_UNEXPECTED.
 
+                    //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.2
+                    //# If a HEADERS frame is received on a control stream, the recipient
+                    //# MUST respond with a connection error of type H3_FRAME_UNEXPECTED.
+                    return Poll::Ready(Err(self.close(
+                        Code::H3_FRAME_UNEXPECTED,
+                        format!("on control stream: {:?}", frame),
+                    )));
+                }
+                match frame {
+                    Frame::Settings(settings) if self.peer_settings.is_none() => {
                         //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.4
                         //= type=implication
                         //# An implementation MUST ignore any parameter with an identifier it
@@ -367,16 +696,19 @@ where
                         //= type=implication
                         //# Endpoints MUST NOT consider such settings to have
                         //# any meaning upon receipt.
-                        self.shared
-                            .write("connection settings write")
-                            .peer_max_field_section_size = settings
+                        let mut shared = self.shared.write("connection settings write");
+                        shared.peer_max_field_section_size = settings
                             .get(SettingId::MAX_HEADER_LIST_SIZE)
                             .unwrap_or(VarInt::MAX.0);
+                        shared.settings_received = true;
+                        shared.wake_settings_waiters();
+                        drop(shared);
+                        self.peer_settings = Some(PeerSettings::new(settings.clone()));
                         Ok(Frame::Settings(settings))
                     }
                     f @ Frame::Goaway(_) => Ok(f),
                     f @ Frame::CancelPush(_) | f @ Frame::MaxPushId(_) => {
-                        if self.got_peer_settings {
+                        if self.peer_settings.is_some() {
                             //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.3
                             //= type=TODO
                             //# If a CANCEL_PUSH frame is received that
@@ -397,23 +729,6 @@ where
                         }
                     }
 
-                    //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
-                    //# Receipt of an invalid sequence of frames MUST be treated as a
-                    //# connection error of type H3_FRAME_UNEXPECTED.
-
-                    //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.1
-                    //= type=implication
-                    //# DATA frames MUST be associated with an HTTP request or response.
-
-                    //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.1
-                    //# If
-                    //# a DATA frame is received on a control stream, the recipient MUST
-                    //# respond with a connection error of type H3_FRAME_UNEXPECTED.
-
-                    //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.2
-                    //# If a HEADERS frame is received on a control stream, the recipient
-                    //# MUST respond with a connection error of type H3_FRAME_UNEXPECTED.
-
                     //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.4
                     //# If an endpoint receives a second SETTINGS
                     //# frame on the control stream, the endpoint MUST respond with a
@@ -428,6 +743,23 @@ where
         Poll::Ready(res)
     }
 
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.conn.remote_addr()
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.conn.local_addr()
+    }
+
+    pub fn tls_info(&self) -> Option<quic::TlsInfo> {
+        self.conn.tls_info()
+    }
+
+    /// SETTINGS received from the peer, once its control stream has been read
+    pub fn peer_settings(&self) -> Option<&PeerSettings> {
+        self.peer_settings.as_ref()
+    }
+
     pub(crate) fn process_goaway<T>(
         &mut self,
         recv_closing: &mut Option<T>,
@@ -463,8 +795,11 @@ where
                 }
             }
             *recv_closing = Some(id.into());
-            if !self.shared.read("connection goaway read").closing {
-                self.shared.write("connection goaway overwrite").closing = true;
+            let mut shared = self.shared.write("connection goaway");
+            shared.closing = true;
+            shared.goaway_received = true;
+            for waker in shared.goaway_waiters.drain(..) {
+                waker.wake();
             }
             Ok(())
         }
@@ -472,11 +807,28 @@ where
 
     /// Closes a Connection with code and reason.
     /// It returns an [`Error`] which can be returned.
+    ///
+    /// Reasons longer than `MAX_CLOSE_REASON_LEN` bytes are truncated.
     pub fn close<T: AsRef<str>>(&mut self, code: Code, reason: T) -> Error {
-        self.shared.write("connection close err").error =
-            Some(code.with_reason(reason.as_ref(), crate::error::ErrorLevel::ConnectionError));
-        self.conn.close(code, reason.as_ref().as_bytes());
-        code.with_reason(reason.as_ref(), crate::error::ErrorLevel::ConnectionError)
+        let reason = truncate_close_reason(reason.as_ref());
+        // Only the first close reaches the wire, so the peer does not get
+        // contradictory codes, e.g. when dropping a connection closed on error
+        let err = code.with_reason(reason, crate::error::ErrorLevel::ConnectionError);
+        if self.closed {
+            return err;
+        }
+
+        let err = err.with_close_origin(CloseOrigin::Local {
+            code,
+            reason: (!reason.is_empty()).then(|| reason.into()),
+        });
+        let mut shared = self.shared.write("connection close err");
+        shared.error = Some(err.clone());
+        shared.wake_settings_waiters();
+        drop(shared);
+        self.conn.close(code, reason.as_bytes());
+        self.closed = true;
+        err
     }
 
     /// starts an grease stream
@@ -500,7 +852,8 @@ where
         //# types be ignored.  These streams have no semantics, and they can be
         //# sent when application-layer padding is desired.  They MAY also be
         //# sent on connections where no data is currently being transferred.
-        match stream::write(&mut grease_stream, (StreamType::grease(), Frame::Grease)).await {
+        let grease = self.tap.wrap((StreamType::grease(), Frame::Grease));
+        match stream::write(&mut grease_stream, grease).await {
             Ok(()) => (),
             Err(err) => {
                 warn!("write data on grease stream failed with {}", err);
@@ -526,28 +879,217 @@ where
     }
 }
 
+/// Cancellation of a request stream by the peer, shared by its halves and handles
+#[derive(Debug, Default)]
+pub(crate) struct StreamCancel {
+    // The peer's code once known, and the tasks waiting for it
+    state: Mutex<(Option<Code>, Vec<Waker>)>,
+}
+
+impl StreamCancel {
+    /// Record the peer's code and wake the waiting tasks, the first time
+    fn cancel(&self, code: Code) {
+        let mut state = self.state.lock().expect("stream cancel");
+        if state.0.is_none() {
+            state.0 = Some(code);
+            for waker in state.1.drain(..) {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Ready with the peer's code once the stream noticed the cancellation
+    pub(crate) fn poll(&self, cx: &mut Context<'_>) -> Poll<Code> {
+        let mut state = self.state.lock().expect("stream cancel");
+        if let Some(code) = state.0 {
+            return Poll::Ready(code);
+        }
+        if !state.1.iter().any(|w| w.will_wake(cx.waker())) {
+            state.1.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+/// Send and receive halves of a split [`RequestStream`]
+pub(crate) type Halves<S, B> = (
+    RequestStream<<S as quic::BidiStream<B>>::SendStream, B>,
+    RequestStream<<S as quic::BidiStream<B>>::RecvStream, B>,
+);
+
 pub struct RequestStream<S, B> {
     pub(super) stream: FrameStream<S, B>,
+    stream_id: StreamId,
+    // Has a reset from the peer been reported to the `on_stream_reset` hook?
+    reset_reported: bool,
+    // Notifies the cancellation handles once a reset from the peer is seen
+    cancel: Arc<StreamCancel>,
     pub(super) trailers: Option<Bytes>,
     pub(super) conn_state: SharedStateRef,
     pub(super) max_field_section_size: u64,
+    // local limit on the header sections we send, on top of the peer's
+    pub(super) max_send_field_section_size: u64,
     send_grease_frame: bool,
+    // Has the sending side been finished, by `finish()` or `send_trailers()`?
+    send_finished: bool,
+    // maximum cumulated size of the body we receive
+    recv_body_limit: Option<u64>,
+    // push promises received and not taken yet, `None` unless they are accepted
+    push_promises: Option<Vec<(PushId, Request<()>)>>,
+    // empty DATA frames received since the last non-empty one, and the limit
+    empty_data_frames: usize,
+    max_empty_data_frames: usize,
+    stats: StreamStats,
 }
 
 impl<S, B> RequestStream<S, B> {
     pub fn new(
         stream: FrameStream<S, B>,
+        stream_id: StreamId,
         max_field_section_size: u64,
         conn_state: SharedStateRef,
         grease: bool,
     ) -> Self {
+        let max_empty_data_frames = conn_state.read("request stream").max_empty_data_frames;
         Self {
             stream,
+            stream_id,
+            reset_reported: false,
+            cancel: Arc::default(),
             conn_state,
             max_field_section_size,
+            max_send_field_section_size: VarInt::MAX.0,
             trailers: None,
             send_grease_frame: grease,
+            send_finished: false,
+            recv_body_limit: None,
+            push_promises: None,
+            empty_data_frames: 0,
+            max_empty_data_frames,
+            stats: StreamStats::default(),
+        }
+    }
+
+    /// Refuse to send header sections larger than `max`, even if the peer accepts them
+    pub(crate) fn limit_sent_field_section_size(&mut self, max: u64) {
+        self.max_send_field_section_size = max;
+    }
+
+    /// Maximum size of the header sections sent, as limited by both endpoints
+    pub(crate) fn max_sent_field_section_size(&self) -> u64 {
+        self.conn_state
+            .read("max_sent_field_section_size")
+            .peer_max_field_section_size
+            .min(self.max_send_field_section_size)
+    }
+
+    /// Accept PUSH_PROMISE frames, which only clients may receive
+    pub(crate) fn accept_push_promises(&mut self) {
+        self.push_promises = Some(Vec::new());
+    }
+
+    /// Take the push promises received so far
+    pub(crate) fn take_push_promises(&mut self) -> Vec<(PushId, Request<()>)> {
+        self.push_promises
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Check and keep a PUSH_PROMISE frame received on the request stream
+    pub(crate) fn recv_push_promise(&mut self, promise: PushPromise) -> Result<(), Error> {
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.5
+        //# A server MUST treat the
+        //# receipt of a PUSH_PROMISE frame as a connection error of type
+        //# H3_FRAME_UNEXPECTED.
+        if self.push_promises.is_none() {
+            return Err(Code::H3_FRAME_UNEXPECTED.into());
         }
+
+        let (push_id, mut encoded) = promise.into_parts();
+
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.5
+        //# A client MUST treat
+        //# receipt of a PUSH_PROMISE frame that contains a larger push ID than
+        //# the client has advertised as a connection error of H3_ID_ERROR.
+        match self.conn_state.read("recv_push_promise").max_push_id {
+            Some(max) if push_id <= max => (),
+            _ => {
+                return Err(Code::H3_ID_ERROR.with_reason(
+                    format!("{} is above the maximum push ID", push_id),
+                    crate::error::ErrorLevel::ConnectionError,
+                ))
+            }
+        }
+
+        let fields =
+            match qpack::decode_stateless(&mut encoded, self.max_field_section_size, usize::MAX) {
+                Err(qpack::DecoderError::HeaderTooLong(cancel_size)) => {
+                    return Err(Error::header_too_big(
+                        cancel_size,
+                        self.max_field_section_size,
+                    ))
+                }
+                Ok(decoded) => decoded.fields,
+                Err(e) => return Err(e.into()),
+            };
+        let (method, uri, headers) = Header::try_from(fields)?.into_request_parts()?;
+        let mut request = Request::new(());
+        *request.method_mut() = method;
+        *request.uri_mut() = uri;
+        *request.headers_mut() = headers;
+        *request.version_mut() = http::Version::HTTP_3;
+
+        self.conn_state
+            .write("recv_push_promise")
+            .promised_pushes
+            .insert(push_id);
+        if let Some(promises) = self.push_promises.as_mut() {
+            promises.push((push_id, request));
+        }
+        Ok(())
+    }
+
+    /// Limit the cumulated size of the body received on this stream
+    pub fn limit_body(&mut self, max_size: u64) {
+        self.recv_body_limit = Some(max_size);
+    }
+
+    /// Body transfer statistics of this stream
+    pub fn stats(&self) -> StreamStats {
+        self.stats
+    }
+
+    /// Account for a header section sent on this stream
+    pub(crate) fn record_headers_sent(&mut self, encoded_size: usize, size: u64) {
+        self.stats.headers_encoded_sent += encoded_size as u64;
+        self.stats.headers_size_sent += size;
+    }
+
+    /// ID of the QUIC stream carrying the request
+    pub fn id(&self) -> StreamId {
+        self.stream_id
+    }
+
+    /// Shared state notified when the peer resets or stops the stream
+    pub(crate) fn cancel(&self) -> Arc<StreamCancel> {
+        self.cancel.clone()
+    }
+
+    /// Convert an error from the stream, reporting the first reset by the peer
+    pub(crate) fn stream_err<E: Into<Error>>(&mut self, err: E) -> Error {
+        let err = self.maybe_conn_err(err);
+        if let Some(code) = err.stream_reset() {
+            self.cancel.cancel(code);
+            if !self.reset_reported {
+                self.reset_reported = true;
+                let hook = self.conn_state.read("stream reset").on_stream_reset.clone();
+                if let Some(hook) = hook {
+                    hook(self.stream_id, code);
+                }
+            }
+        }
+        err
     }
 }
 
@@ -563,16 +1105,49 @@ where
 {
     /// Receive some of the request body.
     pub async fn recv_data(&mut self) -> Result<Option<impl Buf>, Error> {
-        if !self.stream.has_data() {
-            let frame = future::poll_fn(|cx| self.stream.poll_next(cx))
-                .await
-                .map_err(|e| self.maybe_conn_err(e))?;
+        future::poll_fn(|cx| self.poll_recv_data(cx)).await
+    }
+
+    /// Poll for some of the request body
+    ///
+    /// Progress is kept in the stream, so polling can stop at any `Pending`
+    /// and resume later.
+    pub fn poll_recv_data(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<impl Buf>, Error>> {
+        while !self.stream.has_data() {
+            let frame = ready!(self.stream.poll_next(cx)).map_err(|e| self.stream_err(e))?;
             match frame {
-                Some(Frame::Data { .. }) => (),
+                Some(Frame::Data(PayloadLen(len))) => {
+                    self.stats.data_frames_recv += 1;
+                    self.conn_state.counters().data_frames_recv.add(1);
+                    if len > 0 {
+                        self.empty_data_frames = 0;
+                        break;
+                    }
+
+                    //= https://www.rfc-editor.org/rfc/rfc9114#section-10.5
+                    //# An endpoint MAY
+                    //# treat activity that is suspicious as a connection error of type
+                    //# H3_EXCESSIVE_LOAD, but false positives will result in disrupting
+                    //# valid connections and requests.
+                    self.empty_data_frames += 1;
+                    if self.empty_data_frames > self.max_empty_data_frames {
+                        let reason = "too many empty DATA frames";
+                        self.conn_state
+                            .write("empty data frames")
+                            .request_close(Code::H3_EXCESSIVE_LOAD, reason);
+                        return Poll::Ready(Err(Code::H3_EXCESSIVE_LOAD
+                            .with_reason(reason, crate::error::ErrorLevel::ConnectionError)));
+                    }
+                }
                 Some(Frame::Headers(encoded)) => {
+                    self.conn_state.counters().headers_frames_recv.add(1);
                     self.trailers = Some(encoded);
-                    return Ok(None);
+                    return Poll::Ready(Ok(None));
                 }
+                Some(Frame::PushPromise(promise)) => self.recv_push_promise(promise)?,
 
                 //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
                 //# Receipt of an invalid sequence of frames MUST be treated as a
@@ -596,15 +1171,43 @@ where
                 //# The MAX_PUSH_ID frame is always sent on the control stream.  Receipt
                 //# of a MAX_PUSH_ID frame on any other stream MUST be treated as a
                 //# connection error of type H3_FRAME_UNEXPECTED.
-                Some(_) => return Err(Code::H3_FRAME_UNEXPECTED.into()),
-                None => return Ok(None),
+                Some(_) => return Poll::Ready(Err(Code::H3_FRAME_UNEXPECTED.into())),
+                None => return Poll::Ready(Ok(None)),
             }
         }
 
-        let data = future::poll_fn(|cx| self.stream.poll_data(cx))
-            .await
-            .map_err(|e| self.maybe_conn_err(e))?;
-        Ok(data)
+        let data = ready!(self.stream.poll_data(cx)).map_err(|e| self.stream_err(e))?;
+
+        if let Some(data) = data.as_ref() {
+            let len = data.remaining() as u64;
+            self.stats.bytes_recv += len;
+            self.conn_state.counters().bytes_recv.add(len);
+        }
+        if let Some(limit) = self.recv_body_limit {
+            if self.stats.bytes_recv > limit {
+                //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
+                //# The error code H3_NO_ERROR SHOULD be used when requesting that
+                //# the client stop sending on the request stream.
+                self.stream.stop_sending(Code::H3_NO_ERROR);
+                return Poll::Ready(Err(Error::body_too_big(limit)));
+            }
+        }
+        Poll::Ready(Ok(data))
+    }
+
+    /// Poll for the type and payload length of the next frame, without consuming it
+    pub fn poll_peek_frame(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<(u64, u64)>, Error>> {
+        if self.stream.has_data() {
+            return Poll::Ready(Err(Code::H3_INTERNAL_ERROR.with_reason(
+                "peeking a frame while a DATA payload is read",
+                crate::error::ErrorLevel::StreamError,
+            )));
+        }
+        let header = ready!(self.stream.poll_peek(cx)).map_err(|e| self.stream_err(e))?;
+        Poll::Ready(Ok(header.map(|(ty, len)| (ty.value(), len))))
     }
 
     /// Receive trailers
@@ -612,53 +1215,61 @@ where
         let mut trailers = if let Some(encoded) = self.trailers.take() {
             encoded
         } else {
-            let frame = future::poll_fn(|cx| self.stream.poll_next(cx))
-                .await
-                .map_err(|e| self.maybe_conn_err(e))?;
-            match frame {
-                Some(Frame::Headers(encoded)) => encoded,
+            loop {
+                let frame = future::poll_fn(|cx| self.stream.poll_next(cx))
+                    .await
+                    .map_err(|e| self.stream_err(e))?;
+                match frame {
+                    Some(Frame::Headers(encoded)) => {
+                        self.conn_state.counters().headers_frames_recv.add(1);
+                        break encoded;
+                    }
+                    Some(Frame::PushPromise(promise)) => self.recv_push_promise(promise)?,
 
-                //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
-                //# Receipt of an invalid sequence of frames MUST be treated as a
-                //# connection error of type H3_FRAME_UNEXPECTED.
+                    //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
+                    //# Receipt of an invalid sequence of frames MUST be treated as a
+                    //# connection error of type H3_FRAME_UNEXPECTED.
 
-                //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.3
-                //# Receiving a
-                //# CANCEL_PUSH frame on a stream other than the control stream MUST be
-                //# treated as a connection error of type H3_FRAME_UNEXPECTED.
+                    //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.3
+                    //# Receiving a
+                    //# CANCEL_PUSH frame on a stream other than the control stream MUST be
+                    //# treated as a connection error of type H3_FRAME_UNEXPECTED.
 
-                //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.4
-                //# If an endpoint receives a SETTINGS frame on a different
-                //# stream, the endpoint MUST respond with a connection error of type
-                //# H3_FRAME_UNEXPECTED.
+                    //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.4
+                    //# If an endpoint receives a SETTINGS frame on a different
+                    //# stream, the endpoint MUST respond with a connection error of type
+                    //# H3_FRAME_UNEXPECTED.
 
-                //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.6
-                //# A client MUST treat a GOAWAY frame on a stream other than
-                //# the control stream as a connection error of type H3_FRAME_UNEXPECTED.
+                    //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.6
+                    //# A client MUST treat a GOAWAY frame on a stream other than
+                    //# the control stream as a connection error of type H3_FRAME_UNEXPECTED.
 
-                //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.7
-                //# The MAX_PUSH_ID frame is always sent on the control stream.  Receipt
-                //# of a MAX_PUSH_ID frame on any other stream MUST be treated as a
-                //# connection error of type H3_FRAME_UNEXPECTED.
-                Some(_) => return Err(Code::H3_FRAME_UNEXPECTED.into()),
-                None => return Ok(None),
+                    //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.7
+                    //# The MAX_PUSH_ID frame is always sent on the control stream.  Receipt
+                    //# of a MAX_PUSH_ID frame on any other stream MUST be treated as a
+                    //# connection error of type H3_FRAME_UNEXPECTED.
+                    Some(_) => return Err(Code::H3_FRAME_UNEXPECTED.into()),
+                    None => return Ok(None),
+                }
             }
         };
 
-        if !self.stream.is_eos() {
+        while !self.stream.is_eos() {
             // Get the trailing frame
             let trailing_frame = future::poll_fn(|cx| self.stream.poll_next(cx))
                 .await
-                .map_err(|e| self.maybe_conn_err(e))?;
+                .map_err(|e| self.stream_err(e))?;
 
-            if trailing_frame.is_some() {
+            match trailing_frame {
+                Some(Frame::PushPromise(promise)) => self.recv_push_promise(promise)?,
                 // if it's not unknown or reserved, fail.
-                return Err(Code::H3_FRAME_UNEXPECTED.into());
+                Some(_) => return Err(Code::H3_FRAME_UNEXPECTED.into()),
+                None => break,
             }
         }
 
         let qpack::Decoded { fields, .. } =
-            match qpack::decode_stateless(&mut trailers, self.max_field_section_size) {
+            match qpack::decode_stateless(&mut trailers, self.max_field_section_size, usize::MAX) {
                 //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
                 //# An HTTP/3 implementation MAY impose a limit on the maximum size of
                 //# the message header it will accept on an individual HTTP message.
@@ -687,15 +1298,35 @@ where
 {
     /// Send some data on the response body.
     pub async fn send_data(&mut self, buf: B) -> Result<(), Error> {
-        let frame = Frame::Data(buf);
+        self.start_send_data(buf)?;
+        future::poll_fn(|cx| self.poll_ready(cx)).await
+    }
 
-        stream::write(&mut self.stream, frame)
-            .await
-            .map_err(|e| self.maybe_conn_err(e))?;
+    /// Hand a DATA frame to the stream, to be written by `poll_ready()`
+    ///
+    /// Fails if the previous frame has not been written yet.
+    pub fn start_send_data(&mut self, buf: B) -> Result<(), Error> {
+        let len = buf.remaining() as u64;
+        self.stream
+            .send_data(Frame::Data(buf))
+            .map_err(|e| self.stream_err(e))?;
+        self.stats.bytes_sent += len;
+        self.stats.data_frames_sent += 1;
+        let counters = self.conn_state.counters();
+        counters.bytes_sent.add(len);
+        counters.data_frames_sent.add(1);
         Ok(())
     }
 
-    /// Send a set of trailers to end the request.
+    /// Poll until the stream has taken the frame being written, if any
+    pub fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.stream.poll_ready(cx).map_err(|e| self.stream_err(e))
+    }
+
+    /// Send a set of trailers and finish the stream
+    ///
+    /// Works whether a body has been sent or not. Pseudo-header fields cannot
+    /// be represented in a `HeaderMap`, so they never end up in trailers.
     pub async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), Error> {
         //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2
         //= type=TODO
@@ -703,11 +1334,8 @@ where
         //# converted to lowercase prior to their encoding.
         let mut block = BytesMut::new();
 
-        let mem_size = qpack::encode_stateless(&mut block, Header::trailer(trailers))?;
-        let max_mem_size = self
-            .conn_state
-            .read("send_trailers shared state read")
-            .peer_max_field_section_size;
+        let mem_size = qpack::encode_stateless(&mut block, Header::trailer(trailers)?)?;
+        let max_mem_size = self.max_sent_field_section_size();
 
         //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
         //# An implementation that
@@ -717,11 +1345,18 @@ where
         if mem_size > max_mem_size {
             return Err(Error::header_too_big(mem_size, max_mem_size));
         }
+        let encoded_size = block.len();
         stream::write(&mut self.stream, Frame::Headers(block.freeze()))
             .await
-            .map_err(|e| self.maybe_conn_err(e))?;
+            .map_err(|e| self.stream_err(e))?;
+        self.conn_state.counters().headers_frames_sent.add(1);
+        self.record_headers_sent(encoded_size, mem_size);
 
-        Ok(())
+        // Nothing can follow the trailer section in a message
+        self.send_finished = true;
+        future::poll_fn(|cx| self.stream.poll_finish(cx))
+            .await
+            .map_err(|e| self.stream_err(e))
     }
 
     /// Stops an stream with an error code
@@ -729,20 +1364,33 @@ where
         self.stream.reset(code.into());
     }
 
+    /// Poll until the peer stops the stream, returning its code
+    pub fn poll_stopped(&mut self, cx: &mut Context<'_>) -> Poll<Result<Code, Error>> {
+        let code = ready!(self.stream.poll_stopped(cx)).map_err(|e| self.stream_err(e))?;
+        let code = Code::from_u64(code);
+        self.cancel.cancel(code);
+        Poll::Ready(Ok(code))
+    }
+
     pub async fn finish(&mut self) -> Result<(), Error> {
+        // Already done by `send_trailers()`
+        if self.send_finished {
+            return Ok(());
+        }
         if self.send_grease_frame {
-            // send a grease frame once per Connection
+            // the connection decides which request streams get one
             stream::write(&mut self.stream, Frame::Grease)
                 .await
-                .map_err(|e| self.maybe_conn_err(e))?;
+                .map_err(|e| self.stream_err(e))?;
             self.send_grease_frame = false;
         }
         future::poll_fn(|cx| self.stream.poll_ready(cx))
             .await
-            .map_err(|e| self.maybe_conn_err(e))?;
+            .map_err(|e| self.stream_err(e))?;
+        self.send_finished = true;
         future::poll_fn(|cx| self.stream.poll_finish(cx))
             .await
-            .map_err(|e| self.maybe_conn_err(e))
+            .map_err(|e| self.stream_err(e))
     }
 }
 
@@ -762,18 +1410,102 @@ where
         (
             RequestStream {
                 stream: send,
+                stream_id: self.stream_id,
+                reset_reported: self.reset_reported,
+                cancel: self.cancel.clone(),
                 trailers: None,
                 conn_state: self.conn_state.clone(),
                 max_field_section_size: 0,
+                max_send_field_section_size: self.max_send_field_section_size,
                 send_grease_frame: self.send_grease_frame,
+                send_finished: self.send_finished,
+                recv_body_limit: None,
+                push_promises: None,
+                empty_data_frames: 0,
+                max_empty_data_frames: self.max_empty_data_frames,
+                stats: StreamStats {
+                    bytes_sent: self.stats.bytes_sent,
+                    data_frames_sent: self.stats.data_frames_sent,
+                    headers_encoded_sent: self.stats.headers_encoded_sent,
+                    headers_size_sent: self.stats.headers_size_sent,
+                    ..StreamStats::default()
+                },
             },
             RequestStream {
                 stream: recv,
+                stream_id: self.stream_id,
+                reset_reported: self.reset_reported,
+                cancel: self.cancel,
                 trailers: self.trailers,
                 conn_state: self.conn_state,
                 max_field_section_size: self.max_field_section_size,
+                max_send_field_section_size: VarInt::MAX.0,
                 send_grease_frame: self.send_grease_frame,
+                send_finished: self.send_finished,
+                recv_body_limit: self.recv_body_limit,
+                push_promises: self.push_promises,
+                empty_data_frames: self.empty_data_frames,
+                max_empty_data_frames: self.max_empty_data_frames,
+                stats: StreamStats {
+                    bytes_recv: self.stats.bytes_recv,
+                    data_frames_recv: self.stats.data_frames_recv,
+                    ..StreamStats::default()
+                },
             },
         )
     }
+
+    /// Join the halves returned by `split()`
+    ///
+    /// The halves are given back if they do not come from the same stream,
+    /// or if the transport cannot join them.
+    #[allow(clippy::result_large_err)] // as large as `Self`
+    pub(crate) fn unsplit(
+        send: RequestStream<S::SendStream, B>,
+        recv: RequestStream<S::RecvStream, B>,
+    ) -> Result<Self, Halves<S, B>> {
+        if send.stream_id != recv.stream_id || !send.conn_state.same_connection(&recv.conn_state) {
+            return Err((send, recv));
+        }
+        let stream = match FrameStream::unsplit(send.stream, recv.stream) {
+            Ok(stream) => stream,
+            Err((send_stream, recv_stream)) => {
+                return Err((
+                    RequestStream {
+                        stream: send_stream,
+                        ..send
+                    },
+                    RequestStream {
+                        stream: recv_stream,
+                        ..recv
+                    },
+                ))
+            }
+        };
+
+        Ok(RequestStream {
+            stream_id: recv.stream_id,
+            reset_reported: send.reset_reported || recv.reset_reported,
+            cancel: recv.cancel,
+            trailers: recv.trailers,
+            max_field_section_size: recv.max_field_section_size,
+            max_send_field_section_size: send.max_send_field_section_size,
+            send_grease_frame: send.send_grease_frame,
+            send_finished: send.send_finished,
+            recv_body_limit: recv.recv_body_limit,
+            push_promises: recv.push_promises,
+            empty_data_frames: recv.empty_data_frames,
+            max_empty_data_frames: recv.max_empty_data_frames,
+            stats: StreamStats {
+                bytes_sent: send.stats.bytes_sent,
+                data_frames_sent: send.stats.data_frames_sent,
+                bytes_recv: recv.stats.bytes_recv,
+                data_frames_recv: recv.stats.data_frames_recv,
+                headers_encoded_sent: send.stats.headers_encoded_sent,
+                headers_size_sent: send.stats.headers_size_sent,
+            },
+            stream,
+            conn_state: recv.conn_state,
+        })
+    }
 }
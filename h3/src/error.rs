@@ -29,6 +29,34 @@ impl Code {
     pub fn value(&self) -> u64 {
         self.code
     }
+
+    /// Code with the numerical value `code`
+    ///
+    /// Values without a constant below are valid too: they can be reserved
+    /// codes, or codes defined by extensions.
+    pub const fn from_u64(code: u64) -> Code {
+        Code { code }
+    }
+
+    /// Is this code of the reserved 0x1f * N + 0x21 format?
+    pub(crate) fn is_grease(&self) -> bool {
+        proto::varint::is_grease(self.code)
+    }
+
+    /// Is this code only about a single request, leaving the connection usable?
+    ///
+    /// See <https://www.rfc-editor.org/rfc/rfc9114#section-8.1>
+    pub(crate) fn is_request_error(&self) -> bool {
+        matches!(
+            *self,
+            Code::H3_REQUEST_REJECTED
+                | Code::H3_REQUEST_CANCELLED
+                | Code::H3_REQUEST_INCOMPLETE
+                | Code::H3_MESSAGE_ERROR
+                | Code::H3_CONNECT_ERROR
+                | Code::H3_VERSION_FALLBACK
+        )
+    }
 }
 
 impl PartialEq<u64> for Code {
@@ -41,8 +69,55 @@ impl PartialEq<u64> for Code {
 pub(crate) struct ErrorImpl {
     pub(crate) kind: Kind,
     cause: Option<Arc<Cause>>,
+    close_origin: Option<CloseOrigin>,
+    // Code of the peer's RESET_STREAM or STOP_SENDING, if this results from one
+    stream_reset: Option<Code>,
+}
+
+/// Which endpoint closed the connection, and how
+///
+/// See [`Error::close_origin()`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CloseOrigin {
+    /// Closed by this endpoint, usually after a protocol error from the peer
+    Local {
+        /// Error code sent to the peer
+        code: Code,
+        /// Reason sent to the peer, if not empty
+        reason: Option<Box<str>>,
+    },
+    /// Closed by the peer with a QUIC CONNECTION_CLOSE frame
+    Peer {
+        /// Error code received from the peer
+        code: Code,
+        /// Reason received from the peer, if not empty
+        reason: Option<Box<str>>,
+    },
+}
+
+/// Halves of a request stream which could not be joined back
+///
+/// Returned by `unsplit()` on the client and server request streams when the
+/// halves come from different streams, or when the QUIC implementation
+/// cannot join them. Both halves are given back, in the order they were
+/// passed, so nothing is reset.
+pub struct ReuniteError<S, R>(pub S, pub R);
+
+impl<S, R> fmt::Debug for ReuniteError<S, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ReuniteError(..)")
+    }
+}
+
+impl<S, R> fmt::Display for ReuniteError<S, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("request stream halves cannot be joined")
+    }
 }
 
+impl<S, R> std::error::Error for ReuniteError<S, R> {}
+
 /// Some errors affect the whole connection, others only one Request or Stream.
 /// See [errors](https://www.rfc-editor.org/rfc/rfc9114.html#errors) for mor details.
 #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
@@ -70,6 +145,13 @@ pub enum Kind {
         actual_size: u64,
         max_size: u64,
     },
+    // Received body is beyond the limit set on the request stream
+    #[non_exhaustive]
+    BodyTooBig {
+        max_size: u64,
+    },
+    // Response body sent before the response headers
+    ResponseNotSent,
     // Error from QUIC layer
     #[non_exhaustive]
     Transport(Arc<TransportError>),
@@ -96,16 +178,46 @@ macro_rules! codes {
         )+
         }
 
-        impl fmt::Debug for Code {
-            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        impl Code {
+            /// Name of the constant for this code, if there is one
+            fn name(&self) -> Option<&'static str> {
                 match self.code {
                 $(
-                    $num => f.write_str(stringify!($name)),
+                    $num => Some(stringify!($name)),
                 )+
-                    other => write!(f, "{:#x}", other),
+                    _ => None,
                 }
             }
         }
+
+        #[cfg(test)]
+        const ALL_CODES: &[Code] = &[$(Code::$name),+];
+    }
+}
+
+impl fmt::Debug for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{:#x}", self.code),
+        }
+    }
+}
+
+/// Formats known codes as their name followed by their value, like
+/// `H3_NO_ERROR (0x100)`, and others as `reserved (0x21)` or `unknown (0x7)`
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{} ({:#x})", name, self.code),
+            //= https://www.rfc-editor.org/rfc/rfc9114#section-8.1
+            //= type=implication
+            //# Error codes of the format 0x1f * N + 0x21 for non-negative integer
+            //# values of N are reserved to exercise the requirement that unknown
+            //# error codes be treated as equivalent to H3_NO_ERROR (Section 9).
+            None if self.is_grease() => write!(f, "reserved ({:#x})", self.code),
+            None => write!(f, "unknown ({:#x})", self.code),
+        }
     }
 }
 
@@ -215,10 +327,34 @@ impl From<Code> for u64 {
 impl Error {
     fn new(kind: Kind) -> Self {
         Error {
-            inner: Box::new(ErrorImpl { kind, cause: None }),
+            inner: Box::new(ErrorImpl {
+                kind,
+                cause: None,
+                close_origin: None,
+                stream_reset: None,
+            }),
         }
     }
 
+    /// Which endpoint closed the connection, if this error results from it
+    ///
+    /// Errors raised after the connection has been closed carry the code and
+    /// reason the closing endpoint sent, which helps telling a protocol error
+    /// detected locally from one reported by the peer.
+    pub fn close_origin(&self) -> Option<&CloseOrigin> {
+        self.inner.close_origin.as_ref()
+    }
+
+    pub(crate) fn with_close_origin(mut self, origin: CloseOrigin) -> Self {
+        self.inner.close_origin = Some(origin);
+        self
+    }
+
+    /// Error code sent by the peer, if it reset or stopped the stream
+    pub(crate) fn stream_reset(&self) -> Option<Code> {
+        self.inner.stream_reset
+    }
+
     /// Returns the error code from the error if available
     pub fn try_get_code(&self) -> Option<Code> {
         match self.inner.kind {
@@ -236,6 +372,7 @@ impl Error {
                 reason: _,
                 level,
             } => level,
+            Kind::BodyTooBig { .. } | Kind::ResponseNotSent => ErrorLevel::StreamError,
             // return Connection error on other kinds
             _ => ErrorLevel::ConnectionError,
         }
@@ -248,6 +385,14 @@ impl Error {
         })
     }
 
+    pub(crate) fn body_too_big(max_size: u64) -> Self {
+        Error::new(Kind::BodyTooBig { max_size })
+    }
+
+    pub(crate) fn response_not_sent() -> Self {
+        Error::new(Kind::ResponseNotSent)
+    }
+
     pub(crate) fn with_cause<E: Into<Cause>>(mut self, cause: E) -> Self {
         self.inner.cause = Some(Arc::new(cause.into()));
         self
@@ -272,6 +417,29 @@ impl Error {
         matches!(&self.inner.kind, Kind::HeaderTooBig { .. })
     }
 
+    /// Returns true if the received body exceeded the limit set on the stream
+    ///
+    /// A server would typically respond with `413 Content Too Large`.
+    pub fn is_body_too_big(&self) -> bool {
+        matches!(&self.inner.kind, Kind::BodyTooBig { .. })
+    }
+
+    /// Returns true if body data was sent before the response headers
+    ///
+    /// Nothing is written to the stream in this case, a response can still
+    /// be sent.
+    pub fn is_response_not_sent(&self) -> bool {
+        matches!(&self.inner.kind, Kind::ResponseNotSent)
+    }
+
+    /// Returns true if the connection was closed by the QUIC idle timeout
+    ///
+    /// This tells an inactive connection apart from one closed or reset by
+    /// either endpoint.
+    pub fn is_timeout(&self) -> bool {
+        matches!(&self.inner.kind, Kind::Timeout)
+    }
+
     #[cfg(test)]
     #[doc(hidden)]
     pub fn kind(&self) -> Kind {
@@ -312,6 +480,12 @@ impl fmt::Debug for Error {
                 builder.field("header_size", &actual_size);
                 builder.field("max_size", &max_size);
             }
+            Kind::BodyTooBig { max_size } => {
+                builder.field("body_max_size", &max_size);
+            }
+            Kind::ResponseNotSent => {
+                builder.field("response_not_sent", &true);
+            }
         }
 
         if let Some(ref cause) = self.inner.cause {
@@ -328,7 +502,7 @@ impl fmt::Display for Error {
             Kind::Closed => write!(f, "connection is closed")?,
             Kind::Closing => write!(f, "connection is gracefully closing")?,
             Kind::Transport(ref e) => write!(f, "quic transport error: {}", e)?,
-            Kind::Timeout => write!(f, "timeout",)?,
+            Kind::Timeout => write!(f, "connection idle timeout")?,
             Kind::Application {
                 code, ref reason, ..
             } => {
@@ -346,6 +520,10 @@ impl fmt::Display for Error {
                 "issued header size {} o is beyond peer's limit {} o",
                 actual_size, max_size
             )?,
+            Kind::BodyTooBig { max_size } => {
+                write!(f, "received body is beyond the limit {} o", max_size)?
+            }
+            Kind::ResponseNotSent => write!(f, "body data sent before the response")?,
         };
         if let Some(ref cause) = self.inner.cause {
             write!(f, "cause: {}", cause)?
@@ -378,12 +556,21 @@ impl From<qpack::EncoderError> for Error {
 
 impl From<qpack::DecoderError> for Error {
     fn from(e: qpack::DecoderError) -> Self {
-        match e {
-            qpack::DecoderError::InvalidStaticIndex(_) => {
-                Self::from(Code::QPACK_DECOMPRESSION_FAILED).with_cause(e)
-            }
-            _ => Self::from(Code::QPACK_DECODER_STREAM_ERROR).with_cause(e),
-        }
+        // Only field sections are decoded, encoder stream instructions are
+        // never processed as the dynamic table capacity is always 0.
+
+        //= https://www.rfc-editor.org/rfc/rfc9204#section-6
+        //# QPACK_DECOMPRESSION_FAILED (0x0200):  The decoder failed to interpret
+        //# an encoded field section and is not able to continue decoding that
+        //# field section.
+
+        //= https://www.rfc-editor.org/rfc/rfc9204#section-2.2.3
+        //# If the decoder encounters a reference in a field line representation
+        //# to a dynamic table entry that has already been evicted or that has an
+        //# absolute index greater than or equal to the declared Required Insert
+        //# Count (Section 4.5.1), it MUST treat this as a connection error of
+        //# type QPACK_DECOMPRESSION_FAILED.
+        Self::from(Code::QPACK_DECOMPRESSION_FAILED).with_cause(e)
     }
 }
 
@@ -451,15 +638,49 @@ where
             return Error::new(Kind::Timeout);
         }
 
-        match quic_error.err_code() {
-            Some(c) if Code::H3_NO_ERROR == c => Error::new(Kind::Closed),
-            Some(c) => Error::new(Kind::Application {
-                code: Code { code: c },
+        if let Some((code, reason)) = quic_error.peer_close() {
+            let code = Code { code };
+            let reason: Option<Box<str>> =
+                (!reason.is_empty()).then(|| String::from_utf8_lossy(reason).into());
+            let err = if code == Code::H3_NO_ERROR {
+                Error::new(Kind::Closed)
+            } else {
+                Error::new(Kind::Application {
+                    code,
+                    reason: reason.clone(),
+                    level: ErrorLevel::ConnectionError,
+                })
+            };
+            return err.with_close_origin(CloseOrigin::Peer { code, reason });
+        }
+
+        let code = match quic_error.err_code() {
+            Some(code) => Code { code },
+            None => return Error::new(Kind::Transport(Arc::new(quic_error))),
+        };
+        let stream_reset = quic_error.is_stream_reset();
+        let mut err = if code == Code::H3_NO_ERROR {
+            Error::new(Kind::Closed)
+        } else {
+            // A peer resetting or stopping a stream with a request error code
+            // only gave up on this stream, the connection is still usable. Any
+            // other code on a reset stays a connection error, as it was before
+            // resets were told apart.
+            let level = if stream_reset && code.is_request_error() {
+                ErrorLevel::StreamError
+            } else {
+                ErrorLevel::ConnectionError
+            };
+            Error::new(Kind::Application {
+                code,
                 reason: None,
-                level: ErrorLevel::ConnectionError,
-            }),
-            None => Error::new(Kind::Transport(Arc::new(quic_error))),
+                level,
+            })
+        };
+        if stream_reset {
+            err.inner.stream_reset = Some(code);
         }
+        err
     }
 }
 
@@ -471,11 +692,45 @@ impl From<proto::stream::InvalidStreamId> for Error {
 
 #[cfg(test)]
 mod tests {
-    use super::Error;
+    use super::{Code, Error, ALL_CODES};
     use std::mem;
 
     #[test]
     fn test_size_of() {
         assert_eq!(mem::size_of::<Error>(), mem::size_of::<usize>());
     }
+
+    #[test]
+    fn code_round_trip() {
+        assert_eq!(ALL_CODES.len(), 20);
+        for &code in ALL_CODES {
+            assert_eq!(Code::from_u64(code.value()), code);
+            assert_eq!(Code::from_u64(u64::from(code)), code);
+            assert_eq!(
+                code.to_string(),
+                format!("{:?} ({:#x})", code, code.value())
+            );
+        }
+    }
+
+    #[test]
+    fn code_display() {
+        assert_eq!(Code::H3_NO_ERROR.to_string(), "H3_NO_ERROR (0x100)");
+        assert_eq!(
+            Code::QPACK_DECOMPRESSION_FAILED.to_string(),
+            "QPACK_DECOMPRESSION_FAILED (0x200)"
+        );
+        assert_eq!(Code::from_u64(0x21).to_string(), "reserved (0x21)");
+        assert_eq!(
+            Code::from_u64(0x1f * 1337 + 0x21).to_string(),
+            "reserved (0xa208)"
+        );
+        assert_eq!(Code::from_u64(0x22).to_string(), "unknown (0x22)");
+        assert_eq!(
+            Code::from_u64(0x3994bd84).to_string(),
+            "unknown (0x3994bd84)"
+        );
+        // Debug stays compact
+        assert_eq!(format!("{:?}", Code::from_u64(0x21)), "0x21");
+    }
 }
@@ -8,40 +8,71 @@ use tracing::trace;
 
 use crate::{
     buf::BufList,
-    error::TransportError,
+    config::Tap,
+    error::{Code, TransportError},
     proto::{
-        frame::{self, Frame, PayloadLen},
+        coding::BufExt,
+        frame::{self, Frame, FrameType, PayloadLen},
         stream::StreamId,
     },
     quic::{BidiStream, RecvStream, SendStream},
     stream::WriteBuf,
 };
 
+/// Send and receive halves of a split [`FrameStream`]
+pub(crate) type Halves<S, B> = (
+    FrameStream<<S as BidiStream<B>>::SendStream, B>,
+    FrameStream<<S as BidiStream<B>>::RecvStream, B>,
+);
+
 pub struct FrameStream<S, B> {
-    stream: S,
+    // Only `None` once moved out by `split()`
+    stream: Option<S>,
     bufs: BufList<Bytes>,
     decoder: FrameDecoder,
     remaining_data: usize,
     /// Set to true when `stream` reaches the end.
     is_eos: bool,
+    /// Resets the sending side when dropped before being finished.
+    cancel_on_drop: Option<fn(&mut S)>,
+    /// Reports the bytes read and written
+    tap: Tap,
     _phantom_buffer: PhantomData<B>,
 }
 
 impl<S, B> FrameStream<S, B> {
     pub fn new(stream: S) -> Self {
-        Self::with_bufs(stream, BufList::new())
+        Self::with_bufs(stream, BufList::new(), Tap::default())
     }
 
-    pub(crate) fn with_bufs(stream: S, bufs: BufList<Bytes>) -> Self {
+    pub(crate) fn with_bufs(stream: S, bufs: BufList<Bytes>, tap: Tap) -> Self {
         Self {
-            stream,
+            stream: Some(stream),
             bufs,
             decoder: FrameDecoder::default(),
             remaining_data: 0,
             is_eos: false,
+            cancel_on_drop: None,
+            tap,
             _phantom_buffer: PhantomData,
         }
     }
+
+    pub(crate) fn set_tap(&mut self, tap: Tap) {
+        self.tap = tap;
+    }
+
+    fn stream(&mut self) -> &mut S {
+        self.stream.as_mut().expect("stream already split")
+    }
+}
+
+impl<S, B> Drop for FrameStream<S, B> {
+    fn drop(&mut self) {
+        if let (Some(cancel), Some(stream)) = (self.cancel_on_drop.take(), self.stream.as_mut()) {
+            cancel(stream);
+        }
+    }
 }
 
 impl<S, B> FrameStream<S, B>
@@ -84,6 +115,39 @@ where
         }
     }
 
+    /// Type and payload length of the next frame, without consuming it
+    ///
+    /// Only the frame header is buffered, so a large payload is not read ahead
+    /// of the caller. Unknown frame types are reported as well, even though
+    /// `poll_next()` skips them.
+    pub fn poll_peek(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<(FrameType, u64)>, FrameStreamError>> {
+        assert!(
+            self.remaining_data == 0,
+            "There is still data to read, please call poll_data() until it returns None."
+        );
+
+        loop {
+            let header = {
+                let mut cur = self.bufs.cursor();
+                FrameType::decode(&mut cur).and_then(|ty| Ok((ty, cur.get_var()?)))
+            };
+            if let Ok(header) = header {
+                return Poll::Ready(Ok(Some(header)));
+            }
+
+            return match ready!(self.try_recv(cx))? {
+                false => continue,
+                true if self.bufs.has_remaining() => {
+                    Poll::Ready(Err(FrameStreamError::UnexpectedEnd))
+                }
+                true => Poll::Ready(Ok(None)),
+            };
+        }
+    }
+
     pub fn poll_data(
         &mut self,
         cx: &mut Context<'_>,
@@ -92,7 +156,12 @@ where
             return Poll::Ready(Ok(None));
         };
 
-        let end = ready!(self.try_recv(cx))?;
+        // Payload received along with the frame header must not wait for more
+        let end = match self.try_recv(cx) {
+            Poll::Ready(end) => end?,
+            Poll::Pending if self.bufs.has_remaining() => false,
+            Poll::Pending => return Poll::Pending,
+        };
         let data = self.bufs.take_chunk(self.remaining_data);
 
         match (data, end) {
@@ -111,7 +180,7 @@ where
     }
 
     pub(crate) fn stop_sending(&mut self, error_code: crate::error::Code) {
-        self.stream.stop_sending(error_code.into());
+        self.stream().stop_sending(error_code.into());
     }
 
     pub(crate) fn has_data(&self) -> bool {
@@ -126,16 +195,20 @@ where
         if self.is_eos {
             return Poll::Ready(Ok(true));
         }
-        match self.stream.poll_data(cx) {
-            Poll::Ready(Err(e)) => Poll::Ready(Err(FrameStreamError::Quic(e.into()))),
-            Poll::Pending => Poll::Pending,
-            Poll::Ready(Ok(None)) => {
-                self.is_eos = true;
-                Poll::Ready(Ok(true))
-            }
-            Poll::Ready(Ok(Some(mut d))) => {
-                self.bufs.push_bytes(&mut d);
-                Poll::Ready(Ok(false))
+        loop {
+            match ready!(self.stream().poll_data(cx)) {
+                Err(e) => return Poll::Ready(Err(FrameStreamError::Quic(e.into()))),
+                Ok(None) => {
+                    self.is_eos = true;
+                    return Poll::Ready(Ok(true));
+                }
+                // An empty buffer would end up as an empty body chunk, which
+                // callers could mistake for the end of the body
+                Ok(Some(d)) if !d.has_remaining() => (),
+                Ok(Some(mut d)) => {
+                    self.tap.received(self.bufs.push_bytes(&mut d));
+                    return Poll::Ready(Ok(false));
+                }
             }
         }
     }
@@ -149,24 +222,60 @@ where
     type Error = <T as SendStream<B>>::Error;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.stream.poll_ready(cx)
+        self.stream().poll_ready(cx)
     }
 
     fn send_data<D: Into<WriteBuf<B>>>(&mut self, data: D) -> Result<(), Self::Error> {
-        self.stream.send_data(data)
+        let data = self.tap.wrap(data);
+        self.stream().send_data(data)
     }
 
     fn poll_finish(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.stream.poll_finish(cx)
+        let res = ready!(self.stream().poll_finish(cx));
+        if res.is_ok() {
+            self.cancel_on_drop = None;
+        }
+        Poll::Ready(res)
     }
 
     fn reset(&mut self, reset_code: u64) {
-        self.stream.reset(reset_code)
+        self.cancel_on_drop = None;
+        self.stream().reset(reset_code)
     }
 
     fn id(&self) -> StreamId {
-        self.stream.id()
+        self.stream.as_ref().expect("stream already split").id()
     }
+
+    fn set_priority(&mut self, priority: i32) {
+        self.stream().set_priority(priority)
+    }
+
+    fn poll_stopped(&mut self, cx: &mut Context<'_>) -> Poll<Result<u64, Self::Error>> {
+        self.stream().poll_stopped(cx)
+    }
+}
+
+impl<S, B> FrameStream<S, B>
+where
+    S: SendStream<B>,
+    B: Buf,
+{
+    //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1.1
+    //# Implementations SHOULD cancel requests by abruptly terminating any
+    //# directions of a stream that are still open.  To do so, an
+    //# implementation resets the sending parts of streams and aborts reading
+    //# on the receiving parts of streams; see Section 2.4 of
+    //# [QUIC-TRANSPORT].
+    /// Reset the stream with `H3_REQUEST_CANCELLED` if it is dropped before
+    /// being finished or reset.
+    pub(crate) fn cancel_on_drop(&mut self) {
+        self.cancel_on_drop = Some(cancel::<S, B>);
+    }
+}
+
+fn cancel<S: SendStream<B>, B: Buf>(stream: &mut S) {
+    stream.reset(Code::H3_REQUEST_CANCELLED.into());
 }
 
 impl<S, B> FrameStream<S, B>
@@ -174,27 +283,69 @@ where
     S: BidiStream<B>,
     B: Buf,
 {
-    pub(crate) fn split(self) -> (FrameStream<S::SendStream, B>, FrameStream<S::RecvStream, B>) {
-        let (send, recv) = self.stream.split();
+    pub(crate) fn split(
+        mut self,
+    ) -> (FrameStream<S::SendStream, B>, FrameStream<S::RecvStream, B>) {
+        let (send, recv) = self.stream.take().expect("stream already split").split();
         (
             FrameStream {
-                stream: send,
+                stream: Some(send),
                 bufs: BufList::new(),
                 decoder: FrameDecoder::default(),
                 remaining_data: 0,
                 is_eos: false,
+                cancel_on_drop: self
+                    .cancel_on_drop
+                    .take()
+                    .map(|_| cancel::<S::SendStream, B> as fn(&mut S::SendStream)),
+                tap: self.tap.clone(),
                 _phantom_buffer: PhantomData,
             },
             FrameStream {
-                stream: recv,
-                bufs: self.bufs,
-                decoder: self.decoder,
+                stream: Some(recv),
+                bufs: std::mem::replace(&mut self.bufs, BufList::new()),
+                decoder: std::mem::take(&mut self.decoder),
                 remaining_data: self.remaining_data,
                 is_eos: self.is_eos,
+                cancel_on_drop: None,
+                tap: std::mem::take(&mut self.tap),
                 _phantom_buffer: PhantomData,
             },
         )
     }
+
+    /// Join the halves returned by `split()`, or give them back if the
+    /// transport cannot
+    #[allow(clippy::result_large_err)] // as large as `Self`
+    pub(crate) fn unsplit(
+        mut send: FrameStream<S::SendStream, B>,
+        mut recv: FrameStream<S::RecvStream, B>,
+    ) -> Result<Self, Halves<S, B>> {
+        let stream = match S::unsplit(
+            send.stream.take().expect("stream already split"),
+            recv.stream.take().expect("stream already split"),
+        ) {
+            Ok(stream) => stream,
+            Err((send_stream, recv_stream)) => {
+                send.stream = Some(send_stream);
+                recv.stream = Some(recv_stream);
+                return Err((send, recv));
+            }
+        };
+        Ok(FrameStream {
+            stream: Some(stream),
+            bufs: std::mem::replace(&mut recv.bufs, BufList::new()),
+            decoder: std::mem::take(&mut recv.decoder),
+            remaining_data: recv.remaining_data,
+            is_eos: recv.is_eos,
+            cancel_on_drop: send
+                .cancel_on_drop
+                .take()
+                .map(|_| cancel::<S, B> as fn(&mut S)),
+            tap: std::mem::take(&mut send.tap),
+            _phantom_buffer: PhantomData,
+        })
+    }
 }
 
 #[derive(Default)]
@@ -363,6 +514,32 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn poll_headers_one_byte_at_a_time() {
+        let mut recv = FakeRecv::default();
+        let mut buf = BytesMut::with_capacity(512);
+
+        // Long enough for the payload length to take 2 bytes
+        let block = Bytes::from(vec![0x42; 300]);
+        Frame::headers(block.clone()).encode_with_payload(&mut buf);
+        Frame::Data(&b"body"[..]).encode_with_payload(&mut buf);
+        let mut buf = buf.freeze();
+        while buf.has_remaining() {
+            recv.chunk(buf.split_to(1));
+        }
+
+        let mut stream: FrameStream<_, ()> = FrameStream::new(recv);
+
+        assert_poll_matches!(
+            |cx| stream.poll_next(cx),
+            Ok(Some(Frame::Headers(b))) if b == block
+        );
+        assert_poll_matches!(
+            |cx| stream.poll_next(cx),
+            Ok(Some(Frame::Data(PayloadLen(4))))
+        );
+    }
+
     #[tokio::test]
     async fn poll_full_request() {
         let mut recv = FakeRecv::default();
@@ -541,6 +718,76 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn poll_data_skips_empty_chunks() {
+        let mut recv = FakeRecv::default();
+        let mut buf = BytesMut::with_capacity(64);
+
+        FrameType::DATA.encode(&mut buf);
+        VarInt::from(4u32).encode(&mut buf);
+        buf.put_slice(&b"bo"[..]);
+        recv.chunk(buf.freeze())
+            .chunk(Bytes::new())
+            .chunk(Bytes::from_static(b"dy"));
+
+        let mut stream: FrameStream<_, ()> = FrameStream::new(recv);
+
+        assert_poll_matches!(
+            |cx| stream.poll_next(cx),
+            Ok(Some(Frame::Data(PayloadLen(4))))
+        );
+        assert_poll_matches!(
+            |cx| to_bytes(stream.poll_data(cx)),
+            Ok(Some(b)) if &*b == b"bo"
+        );
+        assert_poll_matches!(
+            |cx| to_bytes(stream.poll_data(cx)),
+            Ok(Some(b)) if &*b == b"dy"
+        );
+    }
+
+    #[tokio::test]
+    async fn poll_peek_data_header() {
+        let mut recv = FakeRecv::default();
+        let mut buf = BytesMut::with_capacity(64);
+
+        FrameType::DATA.encode(&mut buf);
+        VarInt::from(4u32).encode(&mut buf);
+        buf.put_slice(&b"body"[..]);
+        let mut buf = buf.freeze();
+        // Cut the header between type and length
+        recv.chunk(buf.split_to(1)).chunk(buf);
+
+        let mut stream: FrameStream<_, ()> = FrameStream::new(recv);
+
+        assert_poll_matches!(|cx| stream.poll_peek(cx), Ok(Some((FrameType::DATA, 4))));
+        assert_poll_matches!(|cx| stream.poll_peek(cx), Ok(Some((FrameType::DATA, 4))));
+        assert_poll_matches!(
+            |cx| stream.poll_next(cx),
+            Ok(Some(Frame::Data(PayloadLen(4))))
+        );
+        assert_poll_matches!(
+            |cx| to_bytes(stream.poll_data(cx)),
+            Ok(Some(b)) if &*b == b"body"
+        );
+        assert_poll_matches!(|cx| stream.poll_peek(cx), Ok(None));
+    }
+
+    #[tokio::test]
+    async fn poll_peek_unexpected_end() {
+        let mut recv = FakeRecv::default();
+        let mut buf = BytesMut::with_capacity(64);
+        FrameType::HEADERS.encode(&mut buf);
+        recv.chunk(buf.freeze());
+
+        let mut stream: FrameStream<_, ()> = FrameStream::new(recv);
+
+        assert_poll_matches!(
+            |cx| stream.poll_peek(cx),
+            Err(FrameStreamError::UnexpectedEnd)
+        );
+    }
+
     // Helpers
 
     #[derive(Default)]
@@ -569,6 +816,10 @@ mod tests {
         fn stop_sending(&mut self, _: u64) {
             unimplemented!()
         }
+
+        fn recv_id(&self) -> StreamId {
+            unimplemented!()
+        }
     }
 
     #[derive(Debug)]
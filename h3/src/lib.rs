@@ -3,18 +3,25 @@
 #![allow(clippy::derive_partial_eq_without_eq)]
 
 pub mod client;
+pub mod config;
 pub mod error;
 pub mod quic;
 pub mod server;
+pub mod stats;
 
+pub use alt_svc::alt_svc;
 pub use error::Error;
 
+mod alt_svc;
 mod buf;
+mod channel;
 mod connection;
 mod frame;
 mod proto;
 #[allow(dead_code)]
-mod qpack;
+pub mod qpack;
+#[cfg(feature = "quinn")]
+mod quinn_error;
 mod stream;
 
 #[cfg(test)]
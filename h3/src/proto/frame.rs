@@ -6,7 +6,7 @@ use super::{
     coding::Encode,
     push::{InvalidPushId, PushId},
     stream::InvalidStreamId,
-    varint::{BufExt, BufMutExt, UnexpectedEnd, VarInt},
+    varint::{self, BufExt, BufMutExt, UnexpectedEnd, VarInt},
 };
 
 #[derive(Debug, PartialEq)]
@@ -47,6 +47,8 @@ pub enum Frame<B> {
     Goaway(VarInt),
     MaxPushId(PushId),
     Grease,
+    // Frame of a type h3 does not implement, only sent on behalf of the user
+    Extension(FrameType, Bytes),
 }
 
 /// Represents the available data len for a `Data` frame on a RecvStream
@@ -132,6 +134,10 @@ where
                 buf.write_var(6);
                 buf.put_slice(b"grease");
             }
+            Frame::Extension(ty, payload) => {
+                ty.encode(buf);
+                buf.write_var(payload.len() as u64);
+            }
         }
     }
 }
@@ -143,7 +149,7 @@ where
     pub fn payload(&self) -> Option<&dyn Buf> {
         match self {
             Frame::Data(f) => Some(f),
-            Frame::Headers(f) => Some(f),
+            Frame::Headers(f) | Frame::Extension(_, f) => Some(f),
             Frame::PushPromise(f) => Some(&f.encoded),
             _ => None,
         }
@@ -152,7 +158,7 @@ where
     pub fn payload_mut(&mut self) -> Option<&mut dyn Buf> {
         match self {
             Frame::Data(f) => Some(f),
-            Frame::Headers(f) => Some(f),
+            Frame::Headers(f) | Frame::Extension(_, f) => Some(f),
             Frame::PushPromise(f) => Some(&mut f.encoded),
             _ => None,
         }
@@ -172,12 +178,42 @@ where
                     b.advance(pos)
                 }
             }
-            Frame::Headers(b) => buf.put_slice(b),
+            Frame::Headers(b) | Frame::Extension(_, b) => buf.put_slice(b),
             _ => (),
         }
     }
 }
 
+/// Streams carrying HTTP/3 frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamKind {
+    Request,
+    Control,
+    Push,
+}
+
+impl<B> Frame<B> {
+    /// Check if this frame may be received on a stream of kind `stream`
+    ///
+    /// Receiving a frame on another kind of stream is a connection error of
+    /// type H3_FRAME_UNEXPECTED. This only covers the stream type, the order
+    /// of frames and the direction of PUSH_PROMISE are checked by the reader.
+    pub fn is_allowed_on(&self, stream: StreamKind) -> bool {
+        match self {
+            //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.1
+            //= type=implication
+            //# DATA frames MUST be associated with an HTTP request or response.
+            Frame::Data(_) | Frame::Headers(_) => stream != StreamKind::Control,
+            Frame::PushPromise(_) => stream == StreamKind::Request,
+            Frame::CancelPush(_) | Frame::Settings(_) | Frame::Goaway(_) | Frame::MaxPushId(_) => {
+                stream == StreamKind::Control
+            }
+            // The type of extension frames is unknown to the receiver, which ignores them
+            Frame::Grease | Frame::Extension(..) => true,
+        }
+    }
+}
+
 impl fmt::Debug for Frame<PayloadLen> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -189,6 +225,9 @@ impl fmt::Debug for Frame<PayloadLen> {
             Frame::Goaway(id) => write!(f, "GoAway({})", id),
             Frame::MaxPushId(id) => write!(f, "MaxPushId({})", id),
             Frame::Grease => write!(f, "Grease()"),
+            Frame::Extension(ty, payload) => {
+                write!(f, "Extension({:#x}, {} bytes)", ty.0, payload.len())
+            }
         }
     }
 }
@@ -207,6 +246,9 @@ where
             Frame::Goaway(id) => write!(f, "GoAway({})", id),
             Frame::MaxPushId(id) => write!(f, "MaxPushId({})", id),
             Frame::Grease => write!(f, "Grease()"),
+            Frame::Extension(ty, payload) => {
+                write!(f, "Extension({:#x}, {} bytes)", ty.0, payload.len())
+            }
         }
     }
 }
@@ -226,6 +268,7 @@ impl<T, U> PartialEq<Frame<T>> for Frame<U> {
             Frame::Goaway(x) => matches!(other, Frame::Goaway(y) if x == y),
             Frame::MaxPushId(x) => matches!(other, Frame::MaxPushId(y) if x == y),
             Frame::Grease => matches!(other, Frame::Grease),
+            Frame::Extension(t, x) => matches!(other, Frame::Extension(u, y) if t == u && x == y),
         }
     }
 }
@@ -235,6 +278,13 @@ impl Frame<Bytes> {
     pub fn headers<T: Into<Bytes>>(block: T) -> Self {
         Frame::Headers(block.into())
     }
+
+    pub fn push_promise<T: Into<Bytes>>(id: PushId, block: T) -> Self {
+        Frame::PushPromise(PushPromise {
+            id: id.0,
+            encoded: block.into(),
+        })
+    }
 }
 
 macro_rules! frame_types {
@@ -265,15 +315,38 @@ impl FrameType {
     pub fn grease() -> Self {
         FrameType(fastrand::u64(0..0x210842108421083) * 0x1f + 0x21)
     }
+
+    /// Returns the type of an extension frame, `None` if the type is defined
+    /// by HTTP/3, reserved from HTTP/2, or too big for a varint
+    pub fn extension(ty: u64) -> Option<Self> {
+        match FrameType(ty) {
+            FrameType::DATA
+            | FrameType::HEADERS
+            | FrameType::H2_PRIORITY
+            | FrameType::CANCEL_PUSH
+            | FrameType::SETTINGS
+            | FrameType::PUSH_PROMISE
+            | FrameType::H2_PING
+            | FrameType::GOAWAY
+            | FrameType::H2_WINDOW_UPDATE
+            | FrameType::H2_CONTINUATION
+            | FrameType::MAX_PUSH_ID => None,
+            _ if ty > VarInt::MAX.into_inner() => None,
+            ty => Some(ty),
+        }
+    }
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct FrameType(u64);
 
 impl FrameType {
-    fn decode<B: Buf>(buf: &mut B) -> Result<Self, UnexpectedEnd> {
+    pub(crate) fn decode<B: Buf>(buf: &mut B) -> Result<Self, UnexpectedEnd> {
         Ok(FrameType(buf.get_var()?))
     }
+    pub(crate) fn value(&self) -> u64 {
+        self.0
+    }
     pub fn encode<B: BufMut>(&self, buf: &mut B) {
         buf.write_var(self.0);
     }
@@ -315,6 +388,11 @@ impl FrameHeader for PushPromise {
 }
 
 impl PushPromise {
+    /// Split into the push ID and the encoded header block of the promised request
+    pub fn into_parts(self) -> (PushId, Bytes) {
+        (PushId(self.id), self.encoded)
+    }
+
     fn decode<B: Buf>(buf: &mut B) -> Result<Self, UnexpectedEnd> {
         Ok(PushPromise {
             id: buf.get_var()?,
@@ -345,17 +423,32 @@ impl SettingId {
         SettingId(fastrand::u64(0..0x210842108421083) * 0x1f + 0x21)
     }
 
-    fn is_supported(self) -> bool {
+    pub(crate) fn is_supported(self) -> bool {
         matches!(
             self,
             SettingId::MAX_HEADER_LIST_SIZE
                 | SettingId::QPACK_MAX_TABLE_CAPACITY
                 | SettingId::QPACK_MAX_BLOCKED_STREAMS,
+        ) || self.is_extension()
+    }
+
+    /// Returns if a Settings Identifier enables an extension the user advertises
+    pub(crate) fn is_extension(self) -> bool {
+        matches!(
+            self,
+            SettingId::ENABLE_CONNECT_PROTOCOL
+                | SettingId::H3_DATAGRAM
+                | SettingId::ENABLE_WEBTRANSPORT,
         )
     }
 
+    /// Returns if a Settings Identifier is of the reserved 0x1f * N + 0x21 format
+    pub(crate) fn is_grease(self) -> bool {
+        varint::is_grease(self.0)
+    }
+
     /// Returns if a Settings Identifier is forbidden
-    fn is_forbidden(&self) -> bool {
+    pub(crate) fn is_forbidden(&self) -> bool {
         //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.4.1
         //# Setting identifiers that were defined in [HTTP/2] where there is no
         //# corresponding HTTP/3 setting have also been reserved
@@ -389,11 +482,19 @@ setting_identifiers! {
     QPACK_MAX_TABLE_CAPACITY = 0x1,
     QPACK_MAX_BLOCKED_STREAMS = 0x7,
     MAX_HEADER_LIST_SIZE = 0x6,
+    // https://www.rfc-editor.org/rfc/rfc9220#section-5
+    ENABLE_CONNECT_PROTOCOL = 0x8,
+    // https://www.rfc-editor.org/rfc/rfc9297#section-5.1
+    H3_DATAGRAM = 0x33,
+    // https://datatracker.ietf.org/doc/html/draft-ietf-webtrans-http3-02#section-8.2
+    ENABLE_WEBTRANSPORT = 0x2b603742,
 }
 
-const SETTINGS_LEN: usize = 4;
+const SETTINGS_LEN: usize = 11;
+// Unknown settings can't use up the room needed by supported ones
+const UNKNOWN_SETTINGS_LEN: usize = SETTINGS_LEN - 6;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Settings {
     entries: [(SettingId, u64); SETTINGS_LEN],
     len: usize,
@@ -419,6 +520,9 @@ impl FrameHeader for Settings {
 
 impl Settings {
     pub const MAX_ENCODED_SIZE: usize = SETTINGS_LEN * 2 * VarInt::MAX_SIZE;
+    pub const CAPACITY: usize = SETTINGS_LEN;
+    /// How many settings h3 does not support are kept, sent or received
+    pub const UNKNOWN_CAPACITY: usize = UNKNOWN_SETTINGS_LEN;
 
     pub fn insert(&mut self, id: SettingId, value: u64) -> Result<(), SettingsError> {
         if self.len >= self.entries.len() {
@@ -438,7 +542,7 @@ impl Settings {
     }
 
     pub fn get(&self, id: SettingId) -> Option<u64> {
-        for (entry_id, value) in self.entries.iter() {
+        for (entry_id, value) in self.entries[..self.len].iter() {
             if id == *entry_id {
                 return Some(*value);
             }
@@ -446,6 +550,14 @@ impl Settings {
         None
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = &(SettingId, u64)> {
+        self.entries[..self.len].iter()
+    }
+
+    fn unknown_len(&self) -> usize {
+        self.iter().filter(|(id, _)| !id.is_supported()).count()
+    }
+
     pub(super) fn encode<T: BufMut>(&self, buf: &mut T) {
         self.encode_header(buf);
         for (id, val) in self.entries[..self.len].iter() {
@@ -476,13 +588,20 @@ impl Settings {
             }
 
             if identifier.is_supported() {
-                //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.4.1
-                //# Setting identifiers that were defined in [HTTP/2] where there is no
-                //# corresponding HTTP/3 setting have also been reserved
-                //# (Section 11.2.2).  These reserved settings MUST NOT be sent, and
-                //# their receipt MUST be treated as a connection error of type
-                //# H3_SETTINGS_ERROR.
+                //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.4
+                //# A receiver MAY treat the presence of duplicate
+                //# setting identifiers as a connection error of type H3_SETTINGS_ERROR.
                 settings.insert(identifier, value)?;
+            } else if identifier.is_grease() {
+                trace!("ignore grease setting 0x{:x}", identifier.0);
+            } else if settings.unknown_len() < UNKNOWN_SETTINGS_LEN {
+                // Unknown settings are kept so the application can inspect them, but they
+                // must not make the frame fail: repeated ones keep their first value.
+                if let Err(e) = settings.insert(identifier, value) {
+                    trace!("ignore unknown setting 0x{:x}: {}", identifier.0, e);
+                }
+            } else {
+                trace!("ignore unknown setting 0x{:x}: too many", identifier.0);
             }
         }
         Ok(settings)
@@ -547,6 +666,38 @@ mod tests {
     use assert_matches::assert_matches;
     use std::io::Cursor;
 
+    #[test]
+    fn frames_allowed_per_stream() {
+        use StreamKind::*;
+        let table: [(Frame<Bytes>, [bool; 3]); 8] = [
+            (Frame::Data(Bytes::new()), [true, false, true]),
+            (Frame::Headers(Bytes::new()), [true, false, true]),
+            (
+                Frame::PushPromise(PushPromise {
+                    id: 0,
+                    encoded: Bytes::new(),
+                }),
+                [true, false, false],
+            ),
+            (Frame::CancelPush(PushId(0)), [false, true, false]),
+            (Frame::Settings(Settings::default()), [false, true, false]),
+            (Frame::Goaway(VarInt(0)), [false, true, false]),
+            (Frame::MaxPushId(PushId(0)), [false, true, false]),
+            (Frame::Grease, [true, true, true]),
+        ];
+        for (frame, allowed) in table.iter() {
+            for (stream, allowed) in [Request, Control, Push].iter().zip(allowed) {
+                assert_eq!(
+                    frame.is_allowed_on(*stream),
+                    *allowed,
+                    "{:?} on {:?}",
+                    frame,
+                    stream
+                );
+            }
+        }
+    }
+
     #[test]
     fn unknown_frame_type() {
         let mut buf = Cursor::new(&[22, 4, 0, 255, 128, 0, 3, 1, 2]);
@@ -554,6 +705,22 @@ mod tests {
         assert_matches!(Frame::decode(&mut buf), Ok(Frame::CancelPush(PushId(2))));
     }
 
+    #[test]
+    fn extension_frame() {
+        assert!(FrameType::extension(FrameType::SETTINGS.0).is_none());
+        assert!(FrameType::extension(FrameType::H2_PING.0).is_none());
+        assert!(FrameType::extension(1 << 62).is_none());
+
+        let ty = FrameType::extension(22).unwrap();
+        let mut buf = Vec::new();
+        Frame::<Bytes>::Extension(ty, Bytes::from_static(&[1, 2])).encode_with_payload(&mut buf);
+        assert_eq!(buf, [22, 2, 1, 2]);
+        assert_matches!(
+            Frame::decode(&mut Cursor::new(&buf)),
+            Err(FrameError::UnknownFrame(22))
+        );
+    }
+
     #[test]
     fn len_unexpected_end() {
         let mut buf = Cursor::new(&[0, 255]);
@@ -585,34 +752,92 @@ mod tests {
         assert_eq!(check_frame, decoded);
     }
 
+    fn settings(entries: &[(SettingId, u64)]) -> Settings {
+        let mut settings = Settings::default();
+        for (id, value) in entries {
+            settings.insert(*id, *value).unwrap();
+        }
+        settings
+    }
+
     #[test]
     fn settings_frame() {
         codec_frame_check(
-            Frame::Settings(Settings {
-                entries: [
-                    (SettingId::MAX_HEADER_LIST_SIZE, 0xfad1),
-                    (SettingId::QPACK_MAX_TABLE_CAPACITY, 0xfad2),
-                    (SettingId::QPACK_MAX_BLOCKED_STREAMS, 0xfad3),
-                    (SettingId(95), 0),
-                ],
-                len: 4,
-            }),
+            Frame::Settings(settings(&[
+                (SettingId::MAX_HEADER_LIST_SIZE, 0xfad1),
+                (SettingId::QPACK_MAX_TABLE_CAPACITY, 0xfad2),
+                (SettingId::QPACK_MAX_BLOCKED_STREAMS, 0xfad3),
+                (SettingId(95), 0),
+            ])),
             &[
                 4, 18, 6, 128, 0, 250, 209, 1, 128, 0, 250, 210, 7, 128, 0, 250, 211, 64, 95, 0,
             ],
-            Frame::Settings(Settings {
-                entries: [
-                    (SettingId::MAX_HEADER_LIST_SIZE, 0xfad1),
-                    (SettingId::QPACK_MAX_TABLE_CAPACITY, 0xfad2),
-                    (SettingId::QPACK_MAX_BLOCKED_STREAMS, 0xfad3),
-                    // check without the Grease setting because this is ignored
-                    (SettingId(0), 0),
-                ],
-                len: 3,
-            }),
+            Frame::Settings(settings(&[
+                (SettingId::MAX_HEADER_LIST_SIZE, 0xfad1),
+                (SettingId::QPACK_MAX_TABLE_CAPACITY, 0xfad2),
+                (SettingId::QPACK_MAX_BLOCKED_STREAMS, 0xfad3),
+                // check without the Grease setting because this is ignored
+            ])),
+        );
+    }
+
+    #[test]
+    fn settings_grease_ignored() {
+        let mut raw = vec![];
+        for (id, value) in [(0x21, 1), (0x1f * 42 + 0x21, 2), (0x21, 3), (0x6, 4)] {
+            VarInt::from_u64(id).unwrap().encode(&mut raw);
+            VarInt::from_u64(value).unwrap().encode(&mut raw);
+        }
+        let settings = Settings::decode(&mut Cursor::new(&raw)).unwrap();
+        assert_eq!(settings.iter().count(), 1);
+        assert_eq!(settings.get(SettingId::MAX_HEADER_LIST_SIZE), Some(4));
+    }
+
+    #[test]
+    fn settings_unknown_repeated_ignored() {
+        let raw = [0x3f, 1, 0x3f, 2];
+        let settings = Settings::decode(&mut Cursor::new(&raw)).unwrap();
+        assert_eq!(settings.get(SettingId(0x3f)), Some(1));
+    }
+
+    #[test]
+    fn settings_known_repeated_rejected() {
+        let raw = [0x6, 1, 0x1, 0, 0x6, 2];
+        assert_matches!(
+            Settings::decode(&mut Cursor::new(&raw)),
+            Err(SettingsError::Repeated(SettingId::MAX_HEADER_LIST_SIZE))
         );
     }
 
+    #[test]
+    fn settings_unknown_does_not_crowd_out_known() {
+        let mut raw = vec![];
+        for id in 0x30..0x30 + SETTINGS_LEN as u8 {
+            raw.extend(&[id, 0]);
+        }
+        raw.extend(&[0x6, 1, 0x1, 2, 0x7, 3]);
+        let settings = Settings::decode(&mut Cursor::new(&raw)).unwrap();
+        assert_eq!(settings.get(SettingId::MAX_HEADER_LIST_SIZE), Some(1));
+        assert_eq!(settings.get(SettingId::QPACK_MAX_TABLE_CAPACITY), Some(2));
+        assert_eq!(settings.get(SettingId::QPACK_MAX_BLOCKED_STREAMS), Some(3));
+        assert_eq!(settings.unknown_len(), UNKNOWN_SETTINGS_LEN);
+    }
+
+    #[test]
+    fn settings_extensions_kept_when_unknown_full() {
+        let mut raw = vec![];
+        for id in 0x0a..0x0a + UNKNOWN_SETTINGS_LEN as u8 {
+            raw.extend(&[id, 0]);
+        }
+        raw.extend(&[0x8, 1, 0x33, 1]);
+        raw.extend(&[0xab, 0x60, 0x37, 0x42, 1]);
+        let settings = Settings::decode(&mut Cursor::new(&raw)).unwrap();
+        assert_eq!(settings.unknown_len(), UNKNOWN_SETTINGS_LEN);
+        assert_eq!(settings.get(SettingId::ENABLE_CONNECT_PROTOCOL), Some(1));
+        assert_eq!(settings.get(SettingId::H3_DATAGRAM), Some(1));
+        assert_eq!(settings.get(SettingId::ENABLE_WEBTRANSPORT), Some(1));
+    }
+
     #[test]
     fn settings_frame_emtpy() {
         codec_frame_check(
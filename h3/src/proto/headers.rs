@@ -23,6 +23,7 @@ pub struct Header {
 #[allow(clippy::len_without_is_empty)]
 impl Header {
     pub fn request(method: Method, uri: Uri, fields: HeaderMap) -> Result<Self, HeaderError> {
+        check_connection_specific(&fields)?;
         match (uri.authority(), fields.get("host")) {
             (None, None) => Err(HeaderError::MissingAuthority),
             (Some(a), Some(h)) if a.as_str() != h => Err(HeaderError::ContradictedAuthority),
@@ -33,21 +34,24 @@ impl Header {
         }
     }
 
-    pub fn response(status: StatusCode, fields: HeaderMap) -> Self {
-        Self {
+    pub fn response(status: StatusCode, fields: HeaderMap) -> Result<Self, HeaderError> {
+        check_status(status)?;
+        check_connection_specific(&fields)?;
+        Ok(Self {
             pseudo: Pseudo::response(status),
             fields,
-        }
+        })
     }
 
-    pub fn trailer(fields: HeaderMap) -> Self {
-        Self {
+    pub fn trailer(fields: HeaderMap) -> Result<Self, HeaderError> {
+        check_connection_specific(&fields)?;
+        Ok(Self {
             //= https://www.rfc-editor.org/rfc/rfc9114#section-4.3
             //# Pseudo-header fields MUST NOT appear in trailer
             //# sections.
             pseudo: Pseudo::default(),
             fields,
-        }
+        })
     }
 
     pub fn into_request_parts(self) -> Result<(Method, Uri, HeaderMap), HeaderError> {
@@ -103,10 +107,9 @@ impl Header {
         //# carries the HTTP status code; see Section 15 of [HTTP].  This pseudo-
         //# header field MUST be included in all responses; otherwise, the
         //# response is malformed (see Section 4.1.2).
-        Ok((
-            self.pseudo.status.ok_or(HeaderError::MissingStatus)?,
-            self.fields,
-        ))
+        let status = self.pseudo.status.ok_or(HeaderError::MissingStatus)?;
+        check_status(status)?;
+        Ok((status, self.fields))
     }
 
     pub fn into_fields(self) -> HeaderMap {
@@ -282,6 +285,51 @@ impl Field {
     }
 }
 
+/// Status codes are three digits, but only 100 to 599 are defined
+///
+/// `StatusCode` accepts up to 999, the other values fail to parse.
+fn check_status(status: StatusCode) -> Result<(), HeaderError> {
+    //= https://www.rfc-editor.org/rfc/rfc9114#section-4.5
+    //# HTTP/3 does not support the HTTP Upgrade mechanism (Section 7.8 of
+    //# [HTTP]) or the 101 (Switching Protocols) informational status code
+    //# (Section 15.2.2 of [HTTP]).
+    if status == StatusCode::SWITCHING_PROTOCOLS || status.as_u16() > 599 {
+        return Err(HeaderError::InvalidStatus(status));
+    }
+    Ok(())
+}
+
+/// Refuse to send fields which only make sense for a single HTTP/1.1 connection
+///
+/// The characters of names and values are already checked by the `http`
+/// types, so these are the only fields the peer would reject as malformed.
+pub(crate) fn check_connection_specific(fields: &HeaderMap) -> Result<(), HeaderError> {
+    //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2
+    //# An endpoint MUST NOT generate an HTTP/3 field section containing
+    //# connection-specific fields; any message containing connection-
+    //# specific fields MUST be treated as malformed.
+    for name in [
+        header::CONNECTION,
+        HeaderName::from_static("keep-alive"),
+        HeaderName::from_static("proxy-connection"),
+        header::TRANSFER_ENCODING,
+        header::UPGRADE,
+    ] {
+        if fields.contains_key(&name) {
+            return Err(HeaderError::ConnectionSpecific(name));
+        }
+    }
+
+    //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2
+    //# The only exception to this is the TE header field, which MAY be
+    //# present in an HTTP/3 request header; when it is, it MUST NOT contain
+    //# any value other than "trailers".
+    if fields.get_all(header::TE).iter().any(|te| te != "trailers") {
+        return Err(HeaderError::ConnectionSpecific(header::TE));
+    }
+    Ok(())
+}
+
 fn try_value<N, V, R>(name: N, value: V) -> Result<R, HeaderError>
 where
     N: AsRef<[u8]>,
@@ -398,6 +446,8 @@ pub enum HeaderError {
     MissingStatus,
     MissingAuthority,
     ContradictedAuthority,
+    ConnectionSpecific(HeaderName),
+    InvalidStatus(StatusCode),
 }
 
 impl HeaderError {
@@ -435,6 +485,16 @@ impl fmt::Display for HeaderError {
             HeaderError::ContradictedAuthority => {
                 write!(f, "uri and authority field are in contradiction")
             }
+            HeaderError::ConnectionSpecific(name) => {
+                write!(
+                    f,
+                    "connection-specific field not allowed in HTTP/3: {}",
+                    name
+                )
+            }
+            HeaderError::InvalidStatus(status) => {
+                write!(f, "invalid response status: {}", status.as_u16())
+            }
         }
     }
 }
@@ -444,6 +504,70 @@ mod tests {
     use super::*;
     use assert_matches::assert_matches;
 
+    #[test]
+    fn invalid_status() {
+        for status in ["0", "99", "1000", "20", "abc"] {
+            assert_matches!(
+                Header::try_from(vec![(":status", status).into()]),
+                Err(HeaderError::InvalidHeaderValue(_))
+            );
+        }
+        for status in ["101", "600", "999"] {
+            let header = Header::try_from(vec![(":status", status).into()]).unwrap();
+            assert_matches!(
+                header.into_response_parts(),
+                Err(HeaderError::InvalidStatus(_))
+            );
+        }
+        let header = Header::try_from(vec![(":status", "100").into()]).unwrap();
+        assert_matches!(header.into_response_parts(), Ok((StatusCode::CONTINUE, _)));
+
+        let switching = StatusCode::SWITCHING_PROTOCOLS;
+        assert!(Header::response(switching, HeaderMap::new()).is_err());
+        let out_of_range = StatusCode::from_u16(600).unwrap();
+        assert!(Header::response(out_of_range, HeaderMap::new()).is_err());
+    }
+
+    #[test]
+    fn connection_specific_fields() {
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2
+        //= type=test
+        //# An endpoint MUST NOT generate an HTTP/3 field section containing
+        //# connection-specific fields; any message containing connection-
+        //# specific fields MUST be treated as malformed.
+        let mut fields = HeaderMap::new();
+        fields.insert(header::CONNECTION, HeaderValue::from_static("close"));
+        assert_matches!(
+            Header::response(StatusCode::OK, fields),
+            Err(HeaderError::ConnectionSpecific(name)) if name == header::CONNECTION
+        );
+
+        let mut fields = HeaderMap::new();
+        fields.insert(
+            header::TRANSFER_ENCODING,
+            HeaderValue::from_static("chunked"),
+        );
+        assert_matches!(
+            Header::trailer(fields),
+            Err(HeaderError::ConnectionSpecific(_))
+        );
+
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2
+        //= type=test
+        //# The only exception to this is the TE header field, which MAY be
+        //# present in an HTTP/3 request header; when it is, it MUST NOT contain
+        //# any value other than "trailers".
+        let uri = Uri::from_static("https://example.com/");
+        let mut fields = HeaderMap::new();
+        fields.insert(header::TE, HeaderValue::from_static("trailers"));
+        assert!(Header::request(Method::GET, uri.clone(), fields.clone()).is_ok());
+        fields.append(header::TE, HeaderValue::from_static("gzip"));
+        assert_matches!(
+            Header::request(Method::GET, uri, fields),
+            Err(HeaderError::ConnectionSpecific(name)) if name == header::TE
+        );
+    }
+
     #[test]
     fn request_has_no_authority_nor_host() {
         //= https://www.rfc-editor.org/rfc/rfc9114#section-4.3.1
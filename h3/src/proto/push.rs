@@ -3,6 +3,7 @@ use std::fmt::{self, Display};
 
 use super::varint::VarInt;
 
+/// Identifier of a server push
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct PushId(pub(crate) u64);
 
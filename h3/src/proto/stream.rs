@@ -7,7 +7,7 @@ use std::{
 
 use super::{
     coding::{BufExt, BufMutExt, Decode, Encode, UnexpectedEnd},
-    varint::VarInt,
+    varint::{self, VarInt},
 };
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -39,6 +39,28 @@ impl StreamType {
     pub fn grease() -> Self {
         StreamType(fastrand::u64(0..0x210842108421083) * 0x1f + 0x21)
     }
+
+    /// Is this a stream type of the 0x1f * N + 0x21 format, reserved for grease?
+    pub fn is_grease(&self) -> bool {
+        varint::is_grease(self.0)
+    }
+
+    /// Returns the type of an extension stream, `None` if the type is defined
+    /// by HTTP/3, reserved for grease, or too big for a varint
+    ///
+    /// With `allow_reserved`, only the size is checked.
+    pub fn extension(ty: u64, allow_reserved: bool) -> Option<Self> {
+        let ty = StreamType(ty);
+        match ty {
+            _ if ty.0 > VarInt::MAX.into_inner() => None,
+            _ if allow_reserved => Some(ty),
+            StreamType::CONTROL | StreamType::PUSH | StreamType::ENCODER | StreamType::DECODER => {
+                None
+            }
+            _ if ty.is_grease() => None,
+            ty => Some(ty),
+        }
+    }
 }
 
 impl Decode for StreamType {
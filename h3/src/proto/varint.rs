@@ -190,6 +190,27 @@ impl<T: BufMut> BufMutExt for T {
         VarInt::from_u64(x).unwrap().encode(self);
     }
 }
+/// Is this value of the 0x1f * N + 0x21 format, reserved for grease?
+///
+/// Stream types, frame types, setting identifiers and error codes share it.
+pub(crate) fn is_grease(value: u64) -> bool {
+    value >= 0x21 && (value - 0x21) % 0x1f == 0
+}
+
 /// Error returned when constructing a `VarInt` from a value >= 2^62
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct VarIntBoundsExceeded(pub(crate) u64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grease_values() {
+        assert!(is_grease(0x21));
+        assert!(is_grease(0x1f * 1337 + 0x21));
+        assert!(!is_grease(0x20));
+        assert!(!is_grease(0x22));
+        assert!(!is_grease(0x1f * 2));
+    }
+}
@@ -36,6 +36,7 @@ pub enum Error {
     BadBaseIndex(isize),
     UnexpectedEnd,
     HeaderTooLong(u64),
+    TooManyFields(usize),
 }
 
 impl std::error::Error for Error {}
@@ -53,6 +54,7 @@ impl std::fmt::Display for Error {
             Error::BadBaseIndex(i) => write!(f, "out of bounds base index: {}", i),
             Error::UnexpectedEnd => write!(f, "unexpected end"),
             Error::HeaderTooLong(_) => write!(f, "header too long"),
+            Error::TooManyFields(n) => write!(f, "more than {} fields", n),
         }
     }
 }
@@ -209,7 +211,11 @@ impl Decoder {
 
 // Decode field lines received on Request or Push stream.
 // https://www.rfc-editor.org/rfc/rfc9204.html#name-field-line-representations
-pub fn decode_stateless<T: Buf>(buf: &mut T, max_size: u64) -> Result<Decoded, Error> {
+pub fn decode_stateless<T: Buf>(
+    buf: &mut T,
+    max_size: u64,
+    max_fields: usize,
+) -> Result<Decoded, Error> {
     let (required_ref, _base) = HeaderPrefix::decode(buf)?.get(0, 0)?;
 
     if required_ref > 0 {
@@ -243,6 +249,9 @@ pub fn decode_stateless<T: Buf>(buf: &mut T, max_size: u64) -> Result<Decoded, E
         if mem_size > max_size {
             return Err(Error::HeaderTooLong(mem_size));
         }
+        if fields.len() == max_fields {
+            return Err(Error::TooManyFields(max_fields));
+        }
         fields.push(field);
     }
 
@@ -331,6 +340,61 @@ mod tests {
     use super::*;
     use crate::qpack::tests::helpers::{build_table_with_size, TABLE_SIZE};
 
+    #[test]
+    fn error_code_mapping() {
+        use crate::error::{Code, ErrorLevel};
+
+        let errors = [
+            Error::InvalidInteger(prefix_int::Error::Overflow),
+            Error::InvalidString(prefix_string::Error::UnexpectedEnd),
+            Error::InvalidIndex(vas::Error::Index(0)),
+            Error::DynamicTable(DynamicTableError::BadIndex(0)),
+            Error::InvalidStaticIndex(99),
+            Error::UnknownPrefix(0),
+            Error::MissingRefs(0),
+            Error::BadBaseIndex(-1),
+            Error::UnexpectedEnd,
+            Error::HeaderTooLong(0),
+            Error::TooManyFields(0),
+        ];
+        for e in errors {
+            let err = crate::Error::from(e);
+            assert_eq!(err.try_get_code(), Some(Code::QPACK_DECOMPRESSION_FAILED));
+            assert_eq!(err.get_error_level(), ErrorLevel::ConnectionError);
+        }
+    }
+
+    #[test]
+    fn decode_stateless_empty_block() {
+        let mut buf = bytes::Bytes::new();
+        assert!(decode_stateless(&mut buf, u64::MAX, usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_too_many_fields() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("a", "1".parse().unwrap());
+        headers.insert("b", "2".parse().unwrap());
+        let mut buf = bytes::BytesMut::new();
+        crate::qpack::encode_stateless(
+            &mut buf,
+            crate::proto::headers::Header::trailer(headers).unwrap(),
+        )
+        .unwrap();
+        let block = buf.freeze();
+        assert_eq!(
+            decode_stateless(&mut block.clone(), u64::MAX, 1),
+            Err(Error::TooManyFields(1))
+        );
+        assert_eq!(
+            decode_stateless(&mut block.clone(), u64::MAX, 2)
+                .unwrap()
+                .fields
+                .len(),
+            2
+        );
+    }
+
     #[test]
     fn test_header_too_long() {
         let mut trailers = http::HeaderMap::new();
@@ -339,9 +403,9 @@ mod tests {
         let mut buf = bytes::BytesMut::new();
         let _ = crate::qpack::encode_stateless(
             &mut buf,
-            crate::proto::headers::Header::trailer(trailers),
+            crate::proto::headers::Header::trailer(trailers).unwrap(),
         );
-        let result = decode_stateless(&mut buf, 2);
+        let result = decode_stateless(&mut buf, 2, usize::MAX);
         assert_eq!(result, Err(Error::HeaderTooLong(44)));
     }
 
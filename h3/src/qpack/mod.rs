@@ -1,4 +1,7 @@
-pub use self::{
+//! QPACK header compression
+
+#[allow(unused_imports)]
+pub(crate) use self::{
     decoder::{
         ack_header, decode_stateless, stream_canceled, Decoded, Decoder, Error as DecoderError,
     },
@@ -12,6 +15,7 @@ mod dynamic;
 mod field;
 mod parse_error;
 mod static_;
+pub mod static_table;
 mod stream;
 mod vas;
 
@@ -25,7 +29,7 @@ mod prefix_string;
 mod tests;
 
 #[derive(Debug)]
-pub enum Error {
+pub(crate) enum Error {
     Encoder(EncoderError),
     Decoder(DecoderError),
 }
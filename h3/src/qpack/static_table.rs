@@ -0,0 +1,70 @@
+//! Lookups in the QPACK static table
+//!
+//! The static table holds the 99 predefined header fields of [RFC 9204
+//! Appendix A](https://www.rfc-editor.org/rfc/rfc9204#appendix-A). Field
+//! lines referencing them are encoded with their index only, which helps
+//! building header blocks ahead of time, like the ones sent with
+//! [`RequestStream::send_response_raw()`](crate::server::RequestStream::send_response_raw).
+
+use super::static_::StaticTable;
+
+/// A header field of the QPACK static table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaticEntry {
+    /// Index in the static table, starting from 0
+    pub index: usize,
+    /// Field name, lowercase
+    pub name: &'static [u8],
+    /// Field value, empty for entries only matching the name
+    pub value: &'static [u8],
+}
+
+/// Find the static table entry matching both `name` and `value`
+///
+/// Names are compared byte by byte, so they must be lowercase to match.
+pub fn find(name: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Option<StaticEntry> {
+    StaticTable::find_field(name.as_ref(), value.as_ref()).and_then(get)
+}
+
+/// Get the static table entry at `index`
+pub fn get(index: usize) -> Option<StaticEntry> {
+    let field = StaticTable::get(index).ok()?;
+    Some(StaticEntry {
+        index,
+        name: &field.name,
+        value: &field.value,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_status() {
+        assert_eq!(
+            find(":status", "200"),
+            Some(StaticEntry {
+                index: 25,
+                name: b":status",
+                value: b"200",
+            })
+        );
+        assert_eq!(find(":status", "201"), None);
+    }
+
+    #[test]
+    fn find_name_only_entry() {
+        assert_eq!(find(":authority", "").map(|e| e.index), Some(0));
+        assert_eq!(find(":authority", "example.com"), None);
+    }
+
+    #[test]
+    fn find_matches_get() {
+        for index in 0..99 {
+            let entry = get(index).unwrap();
+            assert_eq!(find(entry.name, entry.value), Some(entry));
+        }
+        assert_eq!(get(99), None);
+    }
+}
@@ -3,9 +3,15 @@
 //! This module includes traits and types meant to allow being generic over any
 //! QUIC implementation.
 
-use std::task::{self, Poll};
+use std::{
+    any::Any,
+    fmt,
+    net::SocketAddr,
+    sync::Arc,
+    task::{self, Poll},
+};
 
-use bytes::Buf;
+use bytes::{Buf, Bytes};
 
 pub use crate::proto::stream::{InvalidStreamId, StreamId};
 pub use crate::stream::WriteBuf;
@@ -17,10 +23,26 @@ pub use crate::stream::WriteBuf;
 /// Trait that represent an error from the transport layer
 pub trait Error: std::error::Error + Send + Sync {
     /// Check if the current error is a transport timeout
+    ///
+    /// This is true when the connection has been closed by the QUIC idle
+    /// timeout, which h3 reports with [`crate::Error::is_timeout()`].
     fn is_timeout(&self) -> bool;
 
     /// Get the QUIC error code from connection close or stream stop
     fn err_code(&self) -> Option<u64>;
+
+    /// Get the error code and reason of a connection closed by the peer
+    ///
+    /// `None` if the error does not result from a CONNECTION_CLOSE frame sent
+    /// by the peer, e.g. for a stream reset.
+    fn peer_close(&self) -> Option<(u64, &[u8])> {
+        None
+    }
+
+    /// Check if the error results from the peer resetting or stopping the stream
+    fn is_stream_reset(&self) -> bool {
+        false
+    }
 }
 
 impl<'a, E: Error + 'a> From<E> for Box<dyn Error + 'a> {
@@ -75,6 +97,62 @@ pub trait Connection<B: Buf> {
 
     /// Close the connection immediately
     fn close(&mut self, code: crate::error::Code, reason: &[u8]);
+
+    /// Address of the peer, if the implementation knows it
+    ///
+    /// It can change over the lifetime of the connection, with migration.
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// Poll for the connection migrating to another path
+    ///
+    /// Resolves with the new address of the peer, once per change. h3 only
+    /// reports it to the application, the HTTP/3 state of the connection is
+    /// not affected. The default implementation never reports a change.
+    fn poll_path_change(&mut self, _cx: &mut task::Context<'_>) -> Poll<SocketAddr> {
+        Poll::Pending
+    }
+
+    /// Local address of the connection, if the implementation knows it
+    fn local_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// Details negotiated by the TLS handshake, if the implementation provides them
+    fn tls_info(&self) -> Option<TlsInfo> {
+        None
+    }
+}
+
+/// Details negotiated by the TLS handshake of a QUIC connection
+///
+/// The content depends on the QUIC implementation and its TLS library, so it
+/// is kept opaque. See the implementation's documentation for the concrete
+/// types to [`downcast`](Box::downcast) to.
+#[derive(Default)]
+pub struct TlsInfo {
+    /// Handshake data, such as the negotiated ALPN protocol and the SNI
+    pub handshake_data: Option<Box<dyn Any>>,
+    /// Identity of the peer, such as its certificate chain
+    pub peer_identity: Option<Box<dyn Any>>,
+    /// Certificate chain presented by the peer, DER encoded, leaf first
+    ///
+    /// Unlike `peer_identity`, this does not depend on the TLS library.
+    pub peer_certificates: Option<Arc<[Bytes]>>,
+}
+
+impl fmt::Debug for TlsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsInfo")
+            .field("handshake_data", &self.handshake_data.is_some())
+            .field("peer_identity", &self.peer_identity.is_some())
+            .field(
+                "peer_certificates",
+                &self.peer_certificates.as_ref().map(|c| c.len()),
+            )
+            .finish()
+    }
 }
 
 /// Trait for opening outgoing streams
@@ -110,6 +188,10 @@ pub trait SendStream<B: Buf> {
     type Error: Into<Box<dyn Error>>;
 
     /// Polls if the stream can send more data.
+    ///
+    /// This also writes out data passed to `send_data`, and must only be ready
+    /// once the stream has accepted all of it. Callers rely on this to know
+    /// the data will be transmitted without further action.
     fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>>;
 
     /// Send more data on the stream.
@@ -123,6 +205,20 @@ pub trait SendStream<B: Buf> {
 
     /// Get QUIC send stream id
     fn id(&self) -> StreamId;
+
+    /// Set the priority of the stream among the streams of the connection
+    ///
+    /// Data of streams with a higher priority is sent first. Transports
+    /// without stream priorities can ignore it, which is the default.
+    fn set_priority(&mut self, _priority: i32) {}
+
+    /// Poll until the peer sends `STOP_SENDING` for the stream, returning its code
+    ///
+    /// This lets a reset be noticed without writing to the stream. Transports
+    /// that cannot report it stay pending, which is the default.
+    fn poll_stopped(&mut self, _cx: &mut task::Context<'_>) -> Poll<Result<u64, Self::Error>> {
+        Poll::Pending
+    }
 }
 
 /// A trait describing the "receive" actions of a QUIC stream.
@@ -143,6 +239,9 @@ pub trait RecvStream {
 
     /// Send a `STOP_SENDING` QUIC code.
     fn stop_sending(&mut self, error_code: u64);
+
+    /// Get QUIC receive stream id
+    fn recv_id(&self) -> StreamId;
 }
 
 /// Optional trait to allow "splitting" a bidirectional stream into two sides.
@@ -154,4 +253,18 @@ pub trait BidiStream<B: Buf>: SendStream<B> + RecvStream {
 
     /// Split this stream into two halves.
     fn split(self) -> (Self::SendStream, Self::RecvStream);
+
+    /// Join the two halves of a stream returned by `split`.
+    ///
+    /// The halves are given back if they cannot be joined, which is what the
+    /// default implementation does.
+    fn unsplit(
+        send: Self::SendStream,
+        recv: Self::RecvStream,
+    ) -> Result<Self, (Self::SendStream, Self::RecvStream)>
+    where
+        Self: Sized,
+    {
+        Err((send, recv))
+    }
 }
@@ -0,0 +1,94 @@
+//! Conversions of quinn errors into [`crate::Error`]
+//!
+//! Implementing [`quic::Error`] for quinn's error types makes
+//! `h3::Error::from(e)` available for them:
+//!
+//! - an idle timeout gives a timeout error, see [`crate::Error::is_timeout()`]
+//! - a close from the peer gives a connection error with its code and reason,
+//!   or a closed connection for `H3_NO_ERROR`
+//! - a stream reset ([`quinn::ReadError::Reset`]) or stop
+//!   ([`quinn::WriteError::Stopped`]) gives a stream error for the request
+//!   error codes, such as `H3_REQUEST_CANCELLED`, and a connection error for
+//!   the others
+//! - anything else is a transport error
+
+use crate::quic;
+
+fn peer_close(err: &quinn::ConnectionError) -> Option<(u64, &[u8])> {
+    match err {
+        quinn::ConnectionError::ApplicationClosed(close) => {
+            Some((close.error_code.into_inner(), &close.reason))
+        }
+        _ => None,
+    }
+}
+
+impl quic::Error for quinn::ConnectionError {
+    fn is_timeout(&self) -> bool {
+        matches!(self, quinn::ConnectionError::TimedOut)
+    }
+
+    fn err_code(&self) -> Option<u64> {
+        peer_close(self).map(|(code, _)| code)
+    }
+
+    fn peer_close(&self) -> Option<(u64, &[u8])> {
+        peer_close(self)
+    }
+}
+
+impl quic::Error for quinn::ReadError {
+    fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            quinn::ReadError::ConnectionLost(quinn::ConnectionError::TimedOut)
+        )
+    }
+
+    fn err_code(&self) -> Option<u64> {
+        match self {
+            quinn::ReadError::ConnectionLost(e) => e.err_code(),
+            quinn::ReadError::Reset(code) => Some(code.into_inner()),
+            _ => None,
+        }
+    }
+
+    fn peer_close(&self) -> Option<(u64, &[u8])> {
+        match self {
+            quinn::ReadError::ConnectionLost(e) => peer_close(e),
+            _ => None,
+        }
+    }
+
+    fn is_stream_reset(&self) -> bool {
+        matches!(self, quinn::ReadError::Reset(_))
+    }
+}
+
+impl quic::Error for quinn::WriteError {
+    fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            quinn::WriteError::ConnectionLost(quinn::ConnectionError::TimedOut)
+        )
+    }
+
+    fn err_code(&self) -> Option<u64> {
+        match self {
+            quinn::WriteError::ConnectionLost(e) => e.err_code(),
+            quinn::WriteError::Stopped(code) => Some(code.into_inner()),
+            _ => None,
+        }
+    }
+
+    fn peer_close(&self) -> Option<(u64, &[u8])> {
+        match self {
+            quinn::WriteError::ConnectionLost(e) => peer_close(e),
+            _ => None,
+        }
+    }
+
+    fn is_stream_reset(&self) -> bool {
+        matches!(self, quinn::WriteError::Stopped(_))
+    }
+}
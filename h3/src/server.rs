@@ -51,29 +51,44 @@
 //! A ready-to-use example of a file server is available [here](https://github.com/hyperium/h3/blob/master/examples/client.rs)
 
 use std::{
-    collections::HashSet,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
     convert::TryFrom,
-    sync::Arc,
+    future::Future,
+    hash::{Hash, Hasher},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
-use bytes::{Buf, BytesMut};
-use futures_util::future;
-use http::{response, HeaderMap, Request, Response, StatusCode};
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::{future, ready};
+use http::{header::TRAILER, response, HeaderMap, HeaderName, Request, Response, StatusCode};
 use quic::StreamId;
-use tokio::sync::mpsc;
 
 use crate::{
+    channel::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    config::{Config, GreaseFrames, PeerSettings, StreamResetHook},
     connection::{self, ConnectionInner, ConnectionState, SharedStateRef},
-    error::{Code, Error, ErrorLevel},
-    frame::FrameStream,
-    proto::{frame::Frame, headers::Header, push::PushId, varint::VarInt},
+    error::{Code, Error, ErrorLevel, ReuniteError},
+    frame::{FrameStream, FrameStreamError},
+    proto::{
+        frame::{Frame, SettingId},
+        headers::{check_connection_specific, Header},
+        varint::VarInt,
+    },
     qpack,
     quic::{self, RecvStream as _, SendStream as _},
+    stats::{ConnectionStats, StreamStats},
     stream,
 };
 use tracing::{error, trace, warn};
 
+#[cfg(feature = "wire-tap")]
+use crate::config::{Direction, Tap};
+
+pub use crate::proto::push::PushId;
+
 /// Create a builder of HTTP/3 server connections
 ///
 /// This function creates a [`Builder`] that carries settings that can
@@ -89,6 +104,14 @@ pub fn builder() -> Builder {
 /// Create a new Instance with [`Connection::new()`].
 /// Accept incoming requests with [`Connection::accept()`].
 /// And shutdown a connection with [`Connection::shutdown()`].
+///
+/// Dropping it closes the QUIC connection immediately with `H3_NO_ERROR`,
+/// without sending a GOAWAY first: a GOAWAY written then would be discarded
+/// by the transport along with the close. Call [`Connection::shutdown()`],
+/// or use [`Connection::accept_with_shutdown()`], and keep accepting until
+/// `Ok(None)` for the client to learn which requests were processed. With
+/// [`Builder::goaway_on_accept_drop()`], abandoning an `accept()` future
+/// sends the GOAWAY, which arrives if the connection is kept a little longer.
 pub struct Connection<C, B>
 where
     C: quic::Connection<B>,
@@ -96,17 +119,55 @@ where
 {
     inner: ConnectionInner<C, B>,
     max_field_section_size: u64,
+    // maximum size of a response header section we send
+    max_response_field_section_size: u64,
+    // maximum number of fields in a request header section
+    max_header_fields: usize,
+    // attach the encoded request headers to the request extensions
+    capture_raw_headers: bool,
+    // reject response trailers not declared in the `Trailer` header
+    enforce_declared_trailers: bool,
+    // fields added to final responses which do not set them
+    default_response_headers: Arc<HeaderMap>,
     // List of all incoming streams that are currently running.
     ongoing_streams: HashSet<StreamId>,
     // Let the streams tell us when they are no longer running.
-    request_end_recv: mpsc::UnboundedReceiver<StreamId>,
-    request_end_send: mpsc::UnboundedSender<StreamId>,
+    request_end_recv: UnboundedReceiver<StreamId>,
+    request_end_send: UnboundedSender<StreamId>,
     // Has a GOAWAY frame been sent? If so, this StreamId is the last we are willing to accept.
     sent_closing: Option<StreamId>,
     // Has a GOAWAY frame been received? If so, this is PushId the last the remote will accept.
     recv_closing: Option<PushId>,
     // The id of the last stream received by this connection.
     last_accepted_stream: Option<StreamId>,
+    // A request stream accepted while its headers have not been received yet.
+    pending_request: Option<FrameStream<C::BidiStream, B>>,
+    // Certificate chain of the client, shared with each request.
+    peer_certificates: Option<Arc<[Bytes]>>,
+    // Requests reset by the client before their headers were received, since
+    // the start of the current window.
+    early_resets: usize,
+    early_reset_window_start: Option<Instant>,
+    max_early_resets: usize,
+    early_reset_window: Duration,
+    // Send GOAWAY when an accept future is dropped before completing
+    goaway_on_accept_drop: bool,
+    // Reports request streams reset by the client before their headers
+    on_stream_reset: Option<StreamResetHook>,
+    // Encoded response headers shared by the request streams, if enabled
+    response_cache: Option<Arc<Mutex<ResponseCache>>>,
+}
+
+/// Outcome of polling for an incoming request
+#[allow(clippy::large_enum_variant)]
+enum Accepted<S, B> {
+    Request(Request<()>, RequestStream<S, B>),
+    /// The request headers are beyond the limit, a 431 response should be sent
+    HeaderTooBig(RequestStream<S, B>, Error),
+    /// No more requests will be accepted, a final GOAWAY should be sent
+    Idle,
+    /// The connection has been closed
+    Closed,
 }
 
 impl<C, B> ConnectionState for Connection<C, B>
@@ -144,40 +205,216 @@ where
     /// It returns a tuple with a [`http::Request`] and an [`RequestStream`].
     /// The [`http::Request`] is the received request from the client.
     /// The [`RequestStream`] can be used to send the response.
+    ///
+    /// The client's control and QPACK streams are only read while this is
+    /// polled. Keep calling it while requests are in progress, and handle
+    /// them on other tasks, so that frames like GOAWAY are processed in time.
+    ///
+    /// This method is cancel-safe while it waits for a request, so it can be
+    /// raced against a shutdown signal with `select!`: a request stream whose
+    /// headers have not been received yet is kept and resumed on the next
+    /// call. Cancelling it while it sends a 431 response or the final GOAWAY
+    /// drops them. [`Connection::accept_with_shutdown()`] handles the signal
+    /// without that caveat. With [`Builder::goaway_on_accept_drop()`],
+    /// cancelling it sends a GOAWAY instead.
     pub async fn accept(
         &mut self,
     ) -> Result<Option<(Request<()>, RequestStream<C::BidiStream, B>)>, Error> {
-        // Accept the incoming stream
-        let mut stream = match future::poll_fn(|cx| self.poll_accept_request(cx)).await {
-            Ok(Some(s)) => FrameStream::new(s),
-            Ok(None) => {
+        let mut guard = AcceptGuard::new(self);
+        let accepted = future::poll_fn(|cx| guard.conn.poll_accept_inner(cx)).await;
+        guard.armed = false;
+        guard.conn.handle_accepted(accepted?).await
+    }
+
+    /// Accept an incoming request, or start a graceful shutdown once `signal` completes
+    ///
+    /// When `signal` completes, a GOAWAY is sent as with
+    /// [`Connection::shutdown()`] with `0`: requests in flight continue, new
+    /// ones are rejected, and this returns `Ok(None)` once all requests are
+    /// complete. Its output is ignored. Once the shutdown started, `signal`
+    /// is not polled anymore, so the same `&mut` reference to a future, like
+    /// a `oneshot::Receiver`, can be passed on each call.
+    pub async fn accept_with_shutdown<F>(
+        &mut self,
+        signal: F,
+    ) -> Result<Option<(Request<()>, RequestStream<C::BidiStream, B>)>, Error>
+    where
+        F: Future,
+    {
+        if self.sent_closing.is_none() {
+            futures_util::pin_mut!(signal);
+            let mut guard = AcceptGuard::new(self);
+            let accepted = future::poll_fn(|cx| {
+                if let Poll::Ready(accepted) = guard.conn.poll_accept_inner(cx) {
+                    return Poll::Ready(Some(accepted));
+                }
+                signal.as_mut().poll(cx).map(|_| None)
+            })
+            .await;
+            guard.armed = false;
+            drop(guard);
+            match accepted {
+                Some(accepted) => return self.handle_accepted(accepted?).await,
+                None => self.shutdown(0).await?,
+            }
+        }
+        self.accept().await
+    }
+
+    async fn handle_accepted(
+        &mut self,
+        accepted: Accepted<C::BidiStream, B>,
+    ) -> Result<Option<(Request<()>, RequestStream<C::BidiStream, B>)>, Error> {
+        match accepted {
+            Accepted::Request(req, request_stream) => Ok(Some((req, request_stream))),
+            Accepted::HeaderTooBig(mut request_stream, err) => {
+                //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
+                //# An HTTP/3 implementation MAY impose a limit on the maximum size of
+                //# the message header it will accept on an individual HTTP message.
+                // The rejection only holds a status, the limit set for the
+                // responses of the application must not prevent it.
+                request_stream
+                    .inner
+                    .limit_sent_field_section_size(VarInt::MAX.0);
+                request_stream
+                    .send_response(
+                        http::Response::builder()
+                            .status(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+                            .body(())
+                            .expect("header too big response"),
+                    )
+                    .await?;
+                request_stream.finish().await?;
+                Err(err)
+            }
+            Accepted::Idle => {
                 // We always send a last GoAway frame to the client, so it knows which was the last
                 // non-rejected request.
                 self.shutdown(0).await?;
-                return Ok(None);
+                Ok(None)
             }
-            Err(err) => {
-                match err.inner.kind {
-                    crate::error::Kind::Closed => return Ok(None),
-                    crate::error::Kind::Application {
-                        code,
-                        reason,
-                        level: ErrorLevel::ConnectionError,
-                    } => {
-                        return Err(self.inner.close(
-                            code,
-                            reason.unwrap_or_else(|| String::into_boxed_str(String::from(""))),
-                        ))
-                    }
-                    _ => return Err(err),
-                };
+            Accepted::Closed => Ok(None),
+        }
+    }
+
+    /// Poll for an incoming request
+    ///
+    /// This is the poll-based counterpart of [`Connection::accept()`], for
+    /// executors which do not drive `async` code directly. Once it returns
+    /// `Ready`, the request headers have been fully received and decoded.
+    ///
+    /// This method is cancel-safe: a request stream whose headers have not
+    /// been received yet is kept by the [`Connection`] and resumed on the next
+    /// call. It differs from [`Connection::accept()`] on the cases which need
+    /// to send something:
+    /// - when the request headers are too big, the request stream is reset
+    ///   with `H3_REQUEST_REJECTED` instead of receiving a 431 response.
+    /// - when no more requests will be accepted, `Ok(None)` is returned
+    ///   without sending the final GOAWAY, use [`Connection::shutdown()`]
+    ///   with `0` to send it.
+    #[allow(clippy::type_complexity)]
+    pub fn poll_accept(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<(Request<()>, RequestStream<C::BidiStream, B>)>, Error>> {
+        let accepted = match ready!(self.poll_accept_inner(cx)) {
+            Ok(Accepted::Request(req, request_stream)) => Ok(Some((req, request_stream))),
+            Ok(Accepted::HeaderTooBig(mut request_stream, err)) => {
+                request_stream.stop_stream(Code::H3_REQUEST_REJECTED);
+                Err(err)
             }
+            Ok(Accepted::Idle) | Ok(Accepted::Closed) => Ok(None),
+            Err(err) => Err(err),
         };
+        Poll::Ready(accepted)
+    }
 
-        let frame = future::poll_fn(|cx| stream.poll_next(cx)).await;
+    fn poll_accept_inner(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Accepted<C::BidiStream, B>, Error>> {
+        // Accept the incoming stream, unless a previous call was interrupted
+        // while waiting for its headers
+        let (frame, mut stream) = loop {
+            if self.pending_request.is_none() {
+                match ready!(self.poll_accept_request(cx)) {
+                    Ok(Some(s)) => {
+                        let mut stream = FrameStream::new(s);
+                        stream.cancel_on_drop();
+                        stream.set_tap(self.inner.tap.clone());
+                        self.pending_request = Some(stream);
+                    }
+                    Ok(None) => return Poll::Ready(Ok(Accepted::Idle)),
+                    Err(err) => {
+                        let err = match err.inner.kind {
+                            crate::error::Kind::Closed => return Poll::Ready(Ok(Accepted::Closed)),
+                            // Unless the client closed the connection itself
+                            crate::error::Kind::Application {
+                                code,
+                                reason,
+                                level: ErrorLevel::ConnectionError,
+                            } if err.close_origin().is_none() => self.inner.close(
+                                code,
+                                reason.unwrap_or_else(|| String::into_boxed_str(String::from(""))),
+                            ),
+                            _ => err,
+                        };
+                        return Poll::Ready(Err(err));
+                    }
+                }
+            }
+
+            let frame = ready!(self
+                .pending_request
+                .as_mut()
+                .expect("pending request stream")
+                .poll_next(cx));
+            let stream = self.pending_request.take().expect("pending request stream");
+
+            match frame {
+                //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1.1
+                //# Implementations SHOULD cancel requests by abruptly terminating any
+                //# directions of a stream that are still open.
+                Err(FrameStreamError::Quic(e)) if e.is_stream_reset() => {
+                    // Dropping the stream resets its sending part
+                    self.ongoing_streams.remove(&stream.id());
+                    if let (Some(hook), Some(code)) =
+                        (&self.on_stream_reset, Error::from(e).stream_reset())
+                    {
+                        hook(stream.id(), code);
+                    }
+                    let now = Instant::now();
+                    match self.early_reset_window_start {
+                        Some(start) if now.duration_since(start) < self.early_reset_window => {
+                            self.early_resets += 1
+                        }
+                        _ => {
+                            self.early_reset_window_start = Some(now);
+                            self.early_resets = 1;
+                        }
+                    }
+
+                    //= https://www.rfc-editor.org/rfc/rfc9114#section-10.5
+                    //# An endpoint MAY
+                    //# treat activity that is suspicious as a connection error of type
+                    //# H3_EXCESSIVE_LOAD, but false positives will result in disrupting
+                    //# valid connections and requests.
+                    if self.early_resets > self.max_early_resets {
+                        return Poll::Ready(Err(self.inner.close(
+                            Code::H3_EXCESSIVE_LOAD,
+                            "too many requests reset before their headers",
+                        )));
+                    }
+                }
+                frame => break (frame, stream),
+            }
+        };
 
         let mut encoded = match frame {
-            Ok(Some(Frame::Headers(h))) => h,
+            Ok(Some(Frame::Headers(h))) => {
+                self.inner.shared.counters().headers_frames_recv.add(1);
+                h
+            }
 
             //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
             //# If a client-initiated
@@ -185,10 +422,10 @@ where
             //# complete response, the server SHOULD abort its response stream with
             //# the error code H3_REQUEST_INCOMPLETE.
             Ok(None) => {
-                return Err(self.inner.close(
+                return Poll::Ready(Err(self.inner.close(
                     Code::H3_REQUEST_INCOMPLETE,
                     "request stream closed before headers",
-                ))
+                )))
             }
 
             //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
@@ -204,103 +441,111 @@ where
                 //# Receipt of an invalid sequence of frames MUST be treated as a
                 //# connection error of type H3_FRAME_UNEXPECTED.
                 // Close if the first frame is not a header frame
-                return Err(self.inner.close(
+                return Poll::Ready(Err(self.inner.close(
                     Code::H3_FRAME_UNEXPECTED,
                     "first request frame is not headers",
-                ));
+                )));
             }
             Err(e) => {
                 let err: Error = e.into();
-                if err.is_closed() {
-                    return Ok(None);
-                }
-                match err.inner.kind {
-                    crate::error::Kind::Closed => return Ok(None),
+                let err = match err.inner.kind {
+                    crate::error::Kind::Closed => return Poll::Ready(Ok(Accepted::Closed)),
                     crate::error::Kind::Application {
                         code,
                         reason,
                         level: ErrorLevel::ConnectionError,
-                    } => {
-                        return Err(self.inner.close(
-                            code,
-                            reason.unwrap_or_else(|| String::into_boxed_str(String::from(""))),
-                        ))
-                    }
+                    } if err.close_origin().is_none() => self.inner.close(
+                        code,
+                        reason.unwrap_or_else(|| String::into_boxed_str(String::from(""))),
+                    ),
                     crate::error::Kind::Application {
                         code,
                         reason: _,
                         level: ErrorLevel::StreamError,
                     } => {
                         stream.reset(code.into());
-                        return Err(err);
+                        err
                     }
-                    _ => return Err(err),
+                    _ => err,
                 };
+                return Poll::Ready(Err(err));
             }
         };
 
+        let stream_id = stream.id();
         let mut request_stream = RequestStream {
             request_end: Arc::new(RequestEnd {
                 request_end: self.request_end_send.clone(),
-                stream_id: stream.id(),
+                stream_id,
             }),
             inner: connection::RequestStream::new(
                 stream,
+                stream_id,
                 self.max_field_section_size,
                 self.inner.shared.clone(),
                 self.inner.send_grease_frame,
             ),
+            remote_addr: self.inner.remote_addr(),
+            peer_certificates: self.peer_certificates.clone(),
+            response_sent: false,
+            response_cache: self.response_cache.clone(),
+            declared_trailers: self.enforce_declared_trailers.then(Vec::new),
+            default_response_headers: self.default_response_headers.clone(),
         };
+        request_stream
+            .inner
+            .limit_sent_field_section_size(self.max_response_field_section_size);
 
-        let qpack::Decoded { fields, .. } =
-            match qpack::decode_stateless(&mut encoded, self.max_field_section_size) {
-                //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
-                //# An HTTP/3 implementation MAY impose a limit on the maximum size of
-                //# the message header it will accept on an individual HTTP message.
-                Err(qpack::DecoderError::HeaderTooLong(cancel_size)) => {
-                    request_stream
-                        .send_response(
-                            http::Response::builder()
-                                .status(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
-                                .body(())
-                                .expect("header too big response"),
-                        )
-                        .await?;
-                    return Err(Error::header_too_big(
-                        cancel_size,
-                        self.max_field_section_size,
-                    ));
-                }
-                Ok(decoded) => decoded,
-                Err(e) => {
-                    let err: Error = e.into();
-                    if err.is_closed() {
-                        return Ok(None);
+        let raw_headers = self
+            .capture_raw_headers
+            .then(|| RawHeaders(encoded.clone()));
+
+        let qpack::Decoded { fields, .. } = match qpack::decode_stateless(
+            &mut encoded,
+            self.max_field_section_size,
+            self.max_header_fields,
+        ) {
+            Err(qpack::DecoderError::HeaderTooLong(cancel_size)) => {
+                return Poll::Ready(Ok(Accepted::HeaderTooBig(
+                    request_stream,
+                    Error::header_too_big(cancel_size, self.max_field_section_size),
+                )));
+            }
+            Err(qpack::DecoderError::TooManyFields(max)) => {
+                return Poll::Ready(Ok(Accepted::HeaderTooBig(
+                    request_stream,
+                    Code::H3_EXCESSIVE_LOAD.with_reason(
+                        format!("request has more than {} header fields", max),
+                        ErrorLevel::StreamError,
+                    ),
+                )));
+            }
+            Ok(decoded) => decoded,
+            Err(e) => {
+                let err: Error = e.into();
+                let err = match err.inner.kind {
+                    crate::error::Kind::Closed => return Poll::Ready(Ok(Accepted::Closed)),
+                    crate::error::Kind::Application {
+                        code,
+                        reason,
+                        level: ErrorLevel::ConnectionError,
+                    } => self.inner.close(
+                        code,
+                        reason.unwrap_or_else(|| String::into_boxed_str(String::from(""))),
+                    ),
+                    crate::error::Kind::Application {
+                        code,
+                        reason: _,
+                        level: ErrorLevel::StreamError,
+                    } => {
+                        request_stream.stop_stream(code);
+                        err
                     }
-                    match err.inner.kind {
-                        crate::error::Kind::Closed => return Ok(None),
-                        crate::error::Kind::Application {
-                            code,
-                            reason,
-                            level: ErrorLevel::ConnectionError,
-                        } => {
-                            return Err(self.inner.close(
-                                code,
-                                reason.unwrap_or_else(|| String::into_boxed_str(String::from(""))),
-                            ))
-                        }
-                        crate::error::Kind::Application {
-                            code,
-                            reason: _,
-                            level: ErrorLevel::StreamError,
-                        } => {
-                            request_stream.stop_stream(code);
-                            return Err(err);
-                        }
-                        _ => return Err(err),
-                    };
-                }
-            };
+                    _ => err,
+                };
+                return Poll::Ready(Err(err));
+            }
+        };
 
         // Parse the request headers
         let (method, uri, headers) = match Header::try_from(fields) {
@@ -313,7 +558,7 @@ where
                     let error: Error = err.into();
                     request_stream
                         .stop_stream(error.try_get_code().unwrap_or(Code::H3_MESSAGE_ERROR));
-                    return Err(error);
+                    return Poll::Ready(Err(error));
                 }
             },
             Err(err) => {
@@ -322,31 +567,164 @@ where
                 //# detected MUST be treated as a stream error of type H3_MESSAGE_ERROR.
                 let error: Error = err.into();
                 request_stream.stop_stream(error.try_get_code().unwrap_or(Code::H3_MESSAGE_ERROR));
-                return Err(error);
+                return Poll::Ready(Err(error));
             }
         };
-        //  request_stream.stop_stream(Code::H3_MESSAGE_ERROR).await;
         let mut req = http::Request::new(());
         *req.method_mut() = method;
         *req.uri_mut() = uri;
         *req.headers_mut() = headers;
         *req.version_mut() = http::Version::HTTP_3;
-        // send the grease frame only once
-        self.inner.send_grease_frame = false;
+        if let Some(raw_headers) = raw_headers {
+            req.extensions_mut().insert(raw_headers);
+        }
+        // send the grease frame only once, unless configured otherwise
+        if !self.inner.grease_every_request {
+            self.inner.send_grease_frame = false;
+        }
 
-        Ok(Some((req, request_stream)))
+        Poll::Ready(Ok(Accepted::Request(req, request_stream)))
     }
 
     /// Itiniate a graceful shutdown, accepting `max_request` potentially still in-flight
     ///
     /// See [connection shutdown](https://www.rfc-editor.org/rfc/rfc9114.html#connection-shutdown) for more information.
     pub async fn shutdown(&mut self, max_requests: usize) -> Result<(), Error> {
-        let max_id = self
-            .last_accepted_stream
+        let max_id = self.goaway_id(max_requests);
+        self.inner.shutdown(&mut self.sent_closing, max_id).await
+    }
+
+    fn goaway_id(&self, max_requests: usize) -> StreamId {
+        self.last_accepted_stream
             .map(|id| id + max_requests)
-            .unwrap_or(StreamId::FIRST_REQUEST);
+            .unwrap_or(StreamId::FIRST_REQUEST)
+    }
 
-        self.inner.shutdown(&mut self.sent_closing, max_id).await
+    /// Close the connection immediately with `code` and `reason`
+    ///
+    /// Unlike [`Connection::shutdown()`], requests in flight are aborted. The
+    /// reason is sent to the peer in the QUIC CONNECTION_CLOSE frame and is
+    /// meant for debugging. It is truncated to 1024 bytes, on a character
+    /// boundary. Only the first close reaches the peer, dropping the
+    /// connection afterwards does not send another one.
+    pub fn close(&mut self, code: Code, reason: &str) {
+        self.inner.close(code, reason);
+    }
+
+    /// Send a frame of an extension type on the control stream
+    ///
+    /// This allows prototyping control frames h3 does not implement. The
+    /// peer ignores frames of types it does not know. Types defined by
+    /// HTTP/3, or reserved from HTTP/2, are refused without sending anything.
+    pub async fn send_control_frame(
+        &mut self,
+        frame_type: u64,
+        payload: Bytes,
+    ) -> Result<(), Error> {
+        self.inner.send_control_frame(frame_type, payload).await
+    }
+
+    /// Open a unidirectional stream of an extension type
+    ///
+    /// The `stream_type` varint is written at the start of the stream, which
+    /// is returned ready to be written to. h3 does not frame anything else on
+    /// it: writing the content in the format the extension defines, and
+    /// finishing the stream, is up to the caller. Types defined by HTTP/3 or
+    /// of the grease format are refused, unless
+    /// [`Builder::allow_reserved_stream_types()`] is set.
+    pub async fn open_uni(&mut self, stream_type: u64) -> Result<C::SendStream, Error> {
+        self.inner.open_uni(stream_type).await
+    }
+
+    /// Send a frame on the control stream to keep the connection alive
+    ///
+    /// The frame has a reserved type, which the peer ignores. Like any packet,
+    /// it resets the QUIC idle timeout on both ends and refreshes NAT bindings
+    /// along the path. Calling this periodically is only useful when the QUIC
+    /// keep-alive of the transport is disabled or too slow for the network.
+    pub async fn send_keepalive(&mut self) -> Result<(), Error> {
+        self.inner.send_keepalive().await
+    }
+
+    /// Address of the client, if the QUIC implementation provides it
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.inner.remote_addr()
+    }
+
+    /// Local address of the connection, if the QUIC implementation provides it
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    /// Details negotiated by the TLS handshake, if the QUIC implementation provides them
+    ///
+    /// This allows routing on the SNI, or identifying a client by its certificate.
+    pub fn tls_info(&self) -> Option<quic::TlsInfo> {
+        self.inner.tls_info()
+    }
+
+    /// Cumulated statistics of this connection and its request streams
+    pub fn stats(&self) -> ConnectionStats {
+        self.inner.shared.counters().snapshot()
+    }
+
+    /// SETTINGS received from the client
+    ///
+    /// Returns `None` until the client's control stream has been read, which
+    /// happens while [`Connection::accept()`] is polled.
+    pub fn peer_settings(&self) -> Option<&PeerSettings> {
+        self.inner.peer_settings()
+    }
+
+    /// Ready once the client has sent a GOAWAY frame
+    ///
+    /// The connection is then draining: the client will not send new requests, but requests already
+    /// in flight can complete. This happens before the connection is closed,
+    /// and a connection can also close without any GOAWAY, in which case this
+    /// stays pending. The GOAWAY is read while [`Connection::accept()`] is polled.
+    pub fn poll_closing(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.inner.shared.poll_goaway(cx)
+    }
+
+    /// Did the client enable extended CONNECT?
+    ///
+    /// `false` until the client's SETTINGS have been received.
+    pub fn peer_supports_extended_connect(&self) -> bool {
+        self.peer_settings()
+            .map_or(false, PeerSettings::enable_connect_protocol)
+    }
+
+    /// Did the client enable HTTP datagrams?
+    ///
+    /// `false` until the client's SETTINGS have been received.
+    pub fn peer_supports_datagrams(&self) -> bool {
+        self.peer_settings()
+            .map_or(false, PeerSettings::h3_datagram)
+    }
+
+    /// Did the client enable WebTransport?
+    ///
+    /// `false` until the client's SETTINGS have been received.
+    pub fn peer_supports_webtransport(&self) -> bool {
+        self.peer_settings()
+            .map_or(false, PeerSettings::enable_webtransport)
+    }
+
+    /// Push ID carried by the last GOAWAY frame received from the client
+    ///
+    /// Pushes with this ID or greater will not be accepted by the client.
+    /// `None` until a GOAWAY has been received.
+    pub fn peer_goaway_id(&self) -> Option<PushId> {
+        self.recv_closing
+    }
+
+    /// ID of the last request stream accepted from the client
+    ///
+    /// Streams count once accepted from the QUIC connection, even when the
+    /// client resets them before their headers. [`Connection::shutdown()`]
+    /// lets requests up to this ID, plus `max_requests`, complete.
+    pub fn last_accepted_stream(&self) -> Option<StreamId> {
+        self.last_accepted_stream
     }
 
     fn poll_accept_request(
@@ -383,6 +761,7 @@ where
                         if s.id() > max_id {
                             s.stop_sending(Code::H3_REQUEST_REJECTED.value());
                             s.reset(Code::H3_REQUEST_REJECTED.value());
+                            self.inner.shared.counters().requests_rejected.add(1);
                             if self.poll_requests_completion(cx).is_ready() {
                                 break Poll::Ready(Ok(None));
                             }
@@ -391,6 +770,7 @@ where
                     }
                     self.last_accepted_stream = Some(s.id());
                     self.ongoing_streams.insert(s.id());
+                    self.inner.shared.counters().requests.add(1);
                     break Poll::Ready(Ok(Some(s)));
                 }
             };
@@ -467,6 +847,47 @@ where
     }
 }
 
+/// Sends GOAWAY if an accept future is dropped while waiting for a request
+///
+/// Only when enabled with [`Builder::goaway_on_accept_drop()`].
+struct AcceptGuard<'a, C, B>
+where
+    C: quic::Connection<B>,
+    B: Buf,
+{
+    conn: &'a mut Connection<C, B>,
+    armed: bool,
+}
+
+impl<'a, C, B> AcceptGuard<'a, C, B>
+where
+    C: quic::Connection<B>,
+    B: Buf,
+{
+    fn new(conn: &'a mut Connection<C, B>) -> Self {
+        let armed = conn.goaway_on_accept_drop;
+        Self { conn, armed }
+    }
+}
+
+impl<'a, C, B> Drop for AcceptGuard<'a, C, B>
+where
+    C: quic::Connection<B>,
+    B: Buf,
+{
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        // Requests accepted so far are processed
+        let conn = &mut *self.conn;
+        let max_id = conn.goaway_id(1);
+        if let Err(e) = conn.inner.shutdown_now(&mut conn.sent_closing, max_id) {
+            warn!("GOAWAY not sent on accept drop: {}", e);
+        }
+    }
+}
+
 //= https://www.rfc-editor.org/rfc/rfc9114#section-6.1
 //= type=TODO
 //# In order to
@@ -503,32 +924,261 @@ where
 /// }
 /// ```
 pub struct Builder {
-    pub(super) max_field_section_size: u64,
-    pub(super) send_grease: bool,
+    pub(super) config: Config,
 }
 
 impl Builder {
     /// Creates a new [`Builder`] with default settings.
     pub(super) fn new() -> Self {
         Builder {
-            max_field_section_size: VarInt::MAX.0,
-            send_grease: true,
+            config: Config::default(),
         }
     }
-    /// Set the maximum header size this client is willing to accept
+    /// Set the maximum header size of both the requests accepted and the
+    /// responses sent
     ///
-    /// See [header size constraints] section of the specification for details.
+    /// This is a shortcut for [`Builder::max_request_field_section_size()`]
+    /// and [`Builder::max_response_field_section_size()`].
+    pub fn max_field_section_size(&mut self, value: u64) -> &mut Self {
+        self.max_request_field_section_size(value)
+            .max_response_field_section_size(value)
+    }
+
+    /// Set the maximum header size this server is willing to accept
+    ///
+    /// It is advertised to the client in the SETTINGS. See [header size
+    /// constraints] section of the specification for details.
     ///
     /// [header size constraints]: https://www.rfc-editor.org/rfc/rfc9114.html#name-header-size-constraints
-    pub fn max_field_section_size(&mut self, value: u64) -> &mut Self {
-        self.max_field_section_size = value;
+    pub fn max_request_field_section_size(&mut self, value: u64) -> &mut Self {
+        self.config.max_field_section_size = value;
+        self
+    }
+
+    /// Set the maximum header size of the responses and trailers sent
+    ///
+    /// Sending a larger header section fails with an error, as it does
+    /// when it exceeds the limit advertised by the client. No limit other
+    /// than the client's is applied by default.
+    pub fn max_response_field_section_size(&mut self, value: u64) -> &mut Self {
+        self.config.max_response_field_section_size = value;
         self
     }
 
     /// Send grease values to the Client.
     /// See [setting](https://www.rfc-editor.org/rfc/rfc9114.html#settings-parameters), [frame](https://www.rfc-editor.org/rfc/rfc9114.html#frame-reserved) and [stream](https://www.rfc-editor.org/rfc/rfc9114.html#stream-grease) for more information.
     pub fn send_grease(&mut self, value: bool) -> &mut Self {
-        self.send_grease = value;
+        self.config.send_grease = value;
+        self
+    }
+
+    /// Choose the responses ending with a grease frame
+    ///
+    /// Only the first one by default. Has no effect when grease is disabled
+    /// with [`Builder::send_grease()`].
+    pub fn grease_frames(&mut self, value: GreaseFrames) -> &mut Self {
+        self.config.grease_frames = value;
+        self
+    }
+
+    /// Advertise an additional SETTINGS parameter to the client
+    ///
+    /// This allows prototyping HTTP/3 extensions. The client can read it back
+    /// from its peer settings. Identifiers defined by HTTP/3 or of the grease
+    /// format are rejected when building the connection, unless
+    /// [`Builder::allow_reserved_settings()`] is set. Identifiers reserved
+    /// from HTTP/2 are always rejected. Up to 5 settings unknown to h3 can
+    /// be added, as many as an h3 peer keeps.
+    pub fn extra_setting(&mut self, id: u64, value: u64) -> &mut Self {
+        self.config.extra_settings.push((SettingId(id), value));
+        self
+    }
+
+    /// Allow [`Builder::extra_setting()`] to use defined or grease identifiers
+    pub fn allow_reserved_settings(&mut self, value: bool) -> &mut Self {
+        self.config.allow_reserved_settings = value;
+        self
+    }
+
+    /// Allow [`Connection::open_uni()`] to use defined or grease stream types
+    ///
+    /// The peer interprets streams of defined types, such as a second
+    /// control stream, so misusing them is likely to break the connection.
+    pub fn allow_reserved_stream_types(&mut self, value: bool) -> &mut Self {
+        self.config.allow_reserved_stream_types = value;
+        self
+    }
+
+    /// Set the QUIC priority of the control stream
+    ///
+    /// Streams with a higher priority are sent first. The control stream
+    /// carries SETTINGS and GOAWAY, so it defaults to `i32::MAX` to be sent
+    /// ahead of request data under load. No effect if the QUIC implementation
+    /// does not support stream priorities.
+    pub fn control_stream_priority(&mut self, priority: i32) -> &mut Self {
+        self.config.control_stream_priority = priority;
+        self
+    }
+
+    /// Set how many empty DATA frames the client may send in a row in requests
+    ///
+    /// Frames are counted on each request, until a non-empty DATA frame is
+    /// received. Exceeding the limit fails
+    /// [`RequestStream::recv_data()`] with `H3_EXCESSIVE_LOAD`, and the
+    /// connection is closed with that code while [`Connection::accept()`] is
+    /// polled. Defaults to 32.
+    pub fn max_empty_data_frames(&mut self, value: usize) -> &mut Self {
+        self.config.max_empty_data_frames = value;
+        self
+    }
+
+    /// Set how many requests the client may reset before sending their headers,
+    /// within each [`Builder::early_reset_window()`]
+    ///
+    /// Such requests are skipped by [`Connection::accept()`]. Exceeding the
+    /// limit closes the connection with `H3_EXCESSIVE_LOAD`, which mitigates
+    /// rapid reset attacks. Defaults to 100.
+    pub fn max_early_resets(&mut self, value: usize) -> &mut Self {
+        self.config.max_early_resets = value;
+        self
+    }
+
+    /// Send a GOAWAY when an accept future is dropped before it completes
+    ///
+    /// This tells the client which requests were accepted when the accept
+    /// loop is abandoned without calling [`Connection::shutdown()`], for
+    /// instance when a task running it is cancelled. The frame is handed to
+    /// the transport right away, but dropping the [`Connection`] right after
+    /// closes the QUIC connection, which may discard it: keep the connection
+    /// until the client goes away or a grace period elapses. Do not enable
+    /// it when racing [`Connection::accept()`] against other futures in a
+    /// loop. Disabled by default.
+    pub fn goaway_on_accept_drop(&mut self, value: bool) -> &mut Self {
+        self.config.goaway_on_accept_drop = value;
+        self
+    }
+
+    /// Set the period over which requests reset before their headers are counted
+    ///
+    /// A window starts with the first such reset and the count starts over
+    /// once it has elapsed. Defaults to 10 seconds.
+    pub fn early_reset_window(&mut self, value: Duration) -> &mut Self {
+        self.config.early_reset_window = value;
+        self
+    }
+
+    /// Call `hook` when the client resets or stops a request stream
+    ///
+    /// It receives the stream ID and the error code sent by the client, once
+    /// per [`RequestStream`], or per half after [`RequestStream::split()`].
+    /// Requests reset before their headers are received are reported too. The
+    /// hook runs on the task driving the request, so it should be quick.
+    pub fn on_stream_reset<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(StreamId, Code) + Send + Sync + 'static,
+    {
+        self.config.on_stream_reset = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `hook` with the new address of the client when the connection
+    /// migrates to another path
+    ///
+    /// This is only a notification, for logging or security checks: requests
+    /// carry on as if nothing happened. Changes are noticed while
+    /// [`Connection::accept()`] is polled, depending on the QUIC
+    /// implementation, see [`quic::Connection::poll_path_change()`].
+    pub fn on_path_change<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(SocketAddr) + Send + Sync + 'static,
+    {
+        self.config.on_path_change = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `tap` with the raw bytes h3 writes to and reads from QUIC streams
+    ///
+    /// This helps diagnosing interoperability issues at the byte level, which
+    /// packet captures cannot show as QUIC is encrypted. Bytes are reported as
+    /// the transport takes or yields them, without telling which stream they
+    /// belong to. The tap is called for every chunk, on the task doing the
+    /// I/O, which slows transfers down: it is meant for debugging. Streams
+    /// returned by [`Connection::open_uni()`] are only reported up to their
+    /// stream type. Requires the `wire-tap` feature.
+    #[cfg(feature = "wire-tap")]
+    pub fn wire_tap<F>(&mut self, tap: F) -> &mut Self
+    where
+        F: Fn(Direction, &[u8]) + Send + Sync + 'static,
+    {
+        self.config.tap = Tap::new(Arc::new(tap));
+        self
+    }
+
+    /// Set how many encoded response header blocks are kept for reuse
+    ///
+    /// Responses whose status and headers match a cached block are sent
+    /// without being encoded again, trading memory for CPU on servers which
+    /// send the same headers repeatedly. The cache is shared by all requests
+    /// of a connection. Defaults to 0, which disables it.
+    pub fn response_cache_size(&mut self, value: usize) -> &mut Self {
+        self.config.response_cache_size = value;
+        self
+    }
+
+    /// Set the maximum number of fields in a request header section
+    ///
+    /// Requests with more fields are refused like those exceeding
+    /// [`Builder::max_field_section_size()`]: [`Connection::accept()`]
+    /// responds with a 431 status and returns an error. Pseudo-header fields
+    /// count too. Unlimited by default.
+    pub fn max_header_fields(&mut self, value: usize) -> &mut Self {
+        self.config.max_header_fields = value;
+        self
+    }
+
+    /// Attach the QPACK-encoded header block of each request to its extensions
+    ///
+    /// The block is available as [`RawHeaders`] in [`Request::extensions()`],
+    /// exactly as the client sent it. This helps diagnosing QPACK interop
+    /// issues. Disabled by default, as it keeps the block alive with the request.
+    pub fn capture_raw_headers(&mut self, value: bool) -> &mut Self {
+        self.config.capture_raw_headers = value;
+        self
+    }
+
+    /// Only allow response trailers declared in the `Trailer` header
+    ///
+    /// When set, [`RequestStream::send_trailers()`] fails without sending
+    /// anything if a field is not listed in the `Trailer` header of the
+    /// response, as some clients expect. Responses sent with
+    /// [`RequestStream::send_response_raw()`] are not checked. Disabled by
+    /// default.
+    pub fn enforce_declared_trailers(&mut self, value: bool) -> &mut Self {
+        self.config.enforce_declared_trailers = value;
+        self
+    }
+
+    /// Set fields added to every final response sent on this connection
+    ///
+    /// They are merged in [`RequestStream::send_response()`] for each name the
+    /// response does not set: the response's own fields always win, even if
+    /// they only carry some of the values of a default field. Informational
+    /// responses are sent as is. This suits fields such as `server`, `alt-svc`
+    /// or security headers. The build fails if a default is a
+    /// connection-specific field, pseudo-headers cannot be represented in a
+    /// `HeaderMap` in the first place.
+    pub fn default_response_headers(&mut self, headers: HeaderMap) -> &mut Self {
+        self.config.default_response_headers = headers;
+        self
+    }
+
+    /// Set how many unidirectional streams the client may have open before their
+    /// stream type is received
+    ///
+    /// Exceeding it closes the connection with `H3_EXCESSIVE_LOAD`. It should
+    /// leave room for the control and QPACK streams. Defaults to 100.
+    pub fn max_pending_uni_streams(&mut self, value: usize) -> &mut Self {
+        self.config.max_pending_uni_streams = value;
         self
     }
 }
@@ -537,36 +1187,88 @@ impl Builder {
     /// Build an HTTP/3 connection from a QUIC connection
     ///
     /// This method creates a [`Connection`] instance with the settings in the [`Builder`].
+    ///
+    /// The settings are sent to the client as the connection starts, and
+    /// HTTP/3 does not allow changing them later. The connection keeps its
+    /// own copy: modifying the builder afterwards only affects the
+    /// connections it builds next.
     pub async fn build<C, B>(&self, conn: C) -> Result<Connection<C, B>, Error>
     where
         C: quic::Connection<B>,
         B: Buf,
     {
-        let (sender, receiver) = mpsc::unbounded_channel();
+        self.config.validate().map_err(|e| {
+            Code::H3_INTERNAL_ERROR
+                .with_reason("invalid configuration", ErrorLevel::ConnectionError)
+                .with_cause(e)
+        })?;
+        check_connection_specific(&self.config.default_response_headers).map_err(|e| {
+            Code::H3_INTERNAL_ERROR
+                .with_reason(
+                    "invalid default response headers",
+                    ErrorLevel::ConnectionError,
+                )
+                .with_cause(e)
+        })?;
+
+        let peer_certificates = conn.tls_info().and_then(|info| info.peer_certificates);
+        let (sender, receiver) = unbounded_channel();
         Ok(Connection {
-            inner: ConnectionInner::new(
-                conn,
-                self.max_field_section_size,
-                SharedStateRef::default(),
-                self.send_grease,
-            )
-            .await?,
-            max_field_section_size: self.max_field_section_size,
+            inner: ConnectionInner::new(conn, SharedStateRef::default(), &self.config).await?,
+            max_field_section_size: self.config.max_field_section_size,
+            max_response_field_section_size: self.config.max_response_field_section_size,
+            max_header_fields: self.config.max_header_fields,
+            capture_raw_headers: self.config.capture_raw_headers,
+            enforce_declared_trailers: self.config.enforce_declared_trailers,
+            default_response_headers: Arc::new(self.config.default_response_headers.clone()),
             request_end_send: sender,
             request_end_recv: receiver,
             ongoing_streams: HashSet::new(),
             sent_closing: None,
             recv_closing: None,
             last_accepted_stream: None,
+            pending_request: None,
+            peer_certificates,
+            early_resets: 0,
+            early_reset_window_start: None,
+            max_early_resets: self.config.max_early_resets,
+            early_reset_window: self.config.early_reset_window,
+            goaway_on_accept_drop: self.config.goaway_on_accept_drop,
+            on_stream_reset: self.config.on_stream_reset.clone(),
+            response_cache: (self.config.response_cache_size > 0).then(|| {
+                Arc::new(Mutex::new(ResponseCache::new(
+                    self.config.response_cache_size,
+                )))
+            }),
         })
     }
 }
 
+/// QPACK-encoded header block of a request, as received from the client
+///
+/// Found in the request extensions when enabled with
+/// [`Builder::capture_raw_headers()`].
+#[derive(Clone, Debug)]
+pub struct RawHeaders(Bytes);
+
+impl RawHeaders {
+    /// The encoded field section, without the HEADERS frame header
+    pub fn as_bytes(&self) -> &Bytes {
+        &self.0
+    }
+}
+
 struct RequestEnd {
-    request_end: mpsc::UnboundedSender<StreamId>,
+    request_end: UnboundedSender<StreamId>,
     stream_id: StreamId,
 }
 
+/// Halves of a split [`RequestStream`], given back when they cannot be joined
+type Halves<S, B> = ReuniteError<
+    RequestStream<<S as quic::BidiStream<B>>::SendStream, B>,
+    RequestStream<<S as quic::BidiStream<B>>::RecvStream, B>,
+>;
+
 /// Manage request and response transfer for an incoming request
 ///
 /// The [`RequestStream`] struct is used to send and/or receive
@@ -574,6 +1276,15 @@ struct RequestEnd {
 pub struct RequestStream<S, B> {
     inner: connection::RequestStream<S, B>,
     request_end: Arc<RequestEnd>,
+    remote_addr: Option<SocketAddr>,
+    peer_certificates: Option<Arc<[Bytes]>>,
+    // Has a final (non informational) response been sent?
+    response_sent: bool,
+    response_cache: Option<Arc<Mutex<ResponseCache>>>,
+    // Trailer fields declared by the response, when they are enforced
+    declared_trailers: Option<Vec<HeaderName>>,
+    // Fields added to final responses which do not set them
+    default_response_headers: Arc<HeaderMap>,
 }
 
 impl<S, B> AsMut<connection::RequestStream<S, B>> for RequestStream<S, B> {
@@ -588,20 +1299,150 @@ impl<S, B> ConnectionState for RequestStream<S, B> {
     }
 }
 
+impl<S, B> RequestStream<S, B> {
+    /// Body bytes and DATA frames sent and received so far on this stream
+    ///
+    /// The size of the response headers and trailers sent, before and after
+    /// QPACK encoding, is also reported. After [`RequestStream::split()`], each half only
+    /// counts its own direction.
+    pub fn stats(&self) -> StreamStats {
+        self.inner.stats()
+    }
+
+    /// ID of the QUIC stream carrying the request
+    ///
+    /// Both halves returned by [`RequestStream::split()`] keep the ID of the
+    /// bidirectional stream, which helps correlate them in logs.
+    pub fn id(&self) -> StreamId {
+        self.inner.id()
+    }
+
+    /// Address of the client when the request was accepted
+    ///
+    /// `None` if the QUIC implementation does not provide it.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// Certificate chain the client authenticated with, DER encoded, leaf first
+    ///
+    /// `None` without mutual TLS, or if the QUIC implementation does not
+    /// provide it. The identity is established by the TLS handshake, so it is
+    /// the same for every request of a connection. h3 does not parse the
+    /// certificates, the subject has to be extracted with an X.509 library.
+    pub fn peer_identity(&self) -> Option<&[Bytes]> {
+        self.peer_certificates.as_deref()
+    }
+
+    /// Has a final response been sent on this stream?
+    ///
+    /// Informational (1xx) responses do not count. This lets a handler check
+    /// whether it can still reply with an error response.
+    pub fn response_sent(&self) -> bool {
+        self.response_sent
+    }
+
+    /// Get a handle resolving once the client cancels the request
+    ///
+    /// It resolves with the code of the client's reset or `STOP_SENDING`,
+    /// once this stream, or any half of it after [`RequestStream::split()`],
+    /// notices it: when sending or receiving fails because of it, or while
+    /// [`RequestStream::stopped()`] is awaited. The handle does not borrow
+    /// the stream, so it can be raced against a write or moved to another
+    /// task, and it can be cloned.
+    pub fn cancellation(&self) -> Cancellation {
+        Cancellation(self.inner.cancel())
+    }
+}
+
+/// Resolves with the code of a client cancelling a request
+///
+/// Returned by [`RequestStream::cancellation()`].
+#[derive(Clone, Debug)]
+pub struct Cancellation(Arc<connection::StreamCancel>);
+
+impl Future for Cancellation {
+    type Output = Code;
+
+    fn poll(self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Code> {
+        self.0.poll(cx)
+    }
+}
+
 impl<S, B> RequestStream<S, B>
 where
     S: quic::RecvStream,
 {
     /// Receive data sent from the client
+    ///
+    /// Chunks are never empty: empty DATA frames are skipped, and `None`
+    /// marks the end of the body.
     pub async fn recv_data(&mut self) -> Result<Option<impl Buf>, Error> {
         self.inner.recv_data().await
     }
 
+    /// Poll for some of the body
+    ///
+    /// This is the poll-based counterpart of [`RequestStream::recv_data()`].
+    /// Progress is kept in the stream, so polling can stop at any `Pending`
+    /// and resume later, possibly from another task.
+    pub fn poll_recv_data(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<impl Buf>, Error>> {
+        self.inner.poll_recv_data(cx)
+    }
+
     /// Receive an optional set of trailers for the request
     pub async fn recv_trailers(&mut self) -> Result<Option<HeaderMap>, Error> {
         self.inner.recv_trailers().await
     }
 
+    /// Wait for the type and payload length of the next frame, without consuming it
+    ///
+    /// This lets a proxy or an inspection tool decide how to handle the frame
+    /// before reading it with [`RequestStream::recv_data()`] or
+    /// [`RequestStream::recv_trailers()`]. Only the frame header is buffered,
+    /// the payload is not read ahead. Unknown frame types are reported too,
+    /// although receiving skips them. `None` marks the end of the stream. It
+    /// fails while a DATA payload is partially read, and the frame following
+    /// the body has already been read once `recv_data()` returned `None`.
+    pub async fn peek_frame(&mut self) -> Result<Option<(u64, u64)>, Error> {
+        future::poll_fn(|cx| self.inner.poll_peek_frame(cx)).await
+    }
+
+    /// Poll for the type and payload length of the next frame
+    ///
+    /// This is the poll-based counterpart of [`RequestStream::peek_frame()`].
+    pub fn poll_peek_frame(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<(u64, u64)>, Error>> {
+        self.inner.poll_peek_frame(cx)
+    }
+
+    /// Limit the cumulated size of the request body to `max_size` bytes
+    ///
+    /// Once more body has been received, [`RequestStream::recv_data()`] fails
+    /// with an error for which [`Error::is_body_too_big()`] is true. The client
+    /// is then asked to stop sending with `H3_NO_ERROR`, so a `413 Content Too
+    /// Large` response can still be sent on this stream.
+    pub fn limit_body(&mut self, max_size: u64) {
+        self.inner.limit_body(max_size);
+    }
+
+    /// Receive and discard the rest of the request body and its trailers
+    ///
+    /// This is useful to read a request to completion without needing its
+    /// body, for example when responding with an error. Body chunks are
+    /// dropped as they are received, and trailers are still subject to the
+    /// maximum field section size.
+    pub async fn drain_body(&mut self) -> Result<(), Error> {
+        while self.inner.recv_data().await?.is_some() {}
+        self.inner.recv_trailers().await?;
+        Ok(())
+    }
+
     /// Tell the peer to stop sending into the underlying QUIC stream
     pub fn stop_sending(&mut self, error_code: crate::error::Code) {
         self.inner.stream.stop_sending(error_code)
@@ -620,18 +1461,51 @@ where
     pub async fn send_response(&mut self, resp: Response<()>) -> Result<(), Error> {
         let (parts, _) = resp.into_parts();
         let response::Parts {
-            status, headers, ..
+            status,
+            mut headers,
+            ..
         } = parts;
-        let headers = Header::response(status, headers);
-
-        let mut block = BytesMut::new();
-        let mem_size = qpack::encode_stateless(&mut block, headers)?;
+        if !status.is_informational() {
+            for name in self.default_response_headers.keys() {
+                if !headers.contains_key(name) {
+                    for value in self.default_response_headers.get_all(name) {
+                        headers.append(name, value.clone());
+                    }
+                }
+            }
+        }
+        if let (Some(declared), false) = (&mut self.declared_trailers, status.is_informational()) {
+            *declared = declared_trailers(&headers);
+        }
+        let cached = self
+            .response_cache
+            .as_ref()
+            .and_then(|cache| cache.lock().unwrap().get(status, &headers));
+        let (block, mem_size) = match cached {
+            Some(cached) => cached,
+            None => {
+                let mut block = BytesMut::new();
+                match self.response_cache {
+                    Some(ref cache) => {
+                        let fields = Header::response(status, headers.clone())?;
+                        let mem_size = qpack::encode_stateless(&mut block, fields)?;
+                        let block = block.freeze();
+                        cache
+                            .lock()
+                            .unwrap()
+                            .insert(status, headers, block.clone(), mem_size);
+                        (block, mem_size)
+                    }
+                    None => {
+                        let fields = Header::response(status, headers)?;
+                        let mem_size = qpack::encode_stateless(&mut block, fields)?;
+                        (block.freeze(), mem_size)
+                    }
+                }
+            }
+        };
 
-        let max_mem_size = self
-            .inner
-            .conn_state
-            .read("send_response")
-            .peer_max_field_section_size;
+        let max_mem_size = self.inner.max_sent_field_section_size();
 
         //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
         //# An implementation that
@@ -642,18 +1516,145 @@ where
             return Err(Error::header_too_big(mem_size, max_mem_size));
         }
 
-        stream::write(&mut self.inner.stream, Frame::Headers(block.freeze()))
+        let encoded_size = block.len();
+        stream::write(&mut self.inner.stream, Frame::Headers(block))
+            .await
+            .map_err(|e| self.inner.stream_err(e))?;
+        self.inner.conn_state.counters().headers_frames_sent.add(1);
+        self.inner.record_headers_sent(encoded_size, mem_size);
+        if !status.is_informational() {
+            self.response_sent = true;
+        }
+
+        Ok(())
+    }
+
+    /// Send the HTTP/3 response from a field section already encoded with QPACK
+    ///
+    /// This skips encoding for responses which are sent over and over, such
+    /// as cached ones. The caller is responsible for `block` holding a valid
+    /// final response: it is neither decoded nor checked against the maximum
+    /// field section size of the client. As the status is not read, the
+    /// stream takes the response as final, informational responses have to
+    /// go through [`RequestStream::send_response()`]. The dynamic table is
+    /// never used, so blocks with a non-zero Required Insert Count are refused.
+    pub async fn send_response_raw(&mut self, block: Bytes) -> Result<(), Error> {
+        // A Required Insert Count of 0 is encoded as a single 0 byte, followed
+        // by the Base
+        if block.len() < 2 || block[0] != 0 {
+            return Err(Code::H3_INTERNAL_ERROR.with_reason(
+                "encoded field section may reference the dynamic table",
+                ErrorLevel::StreamError,
+            ));
+        }
+
+        let encoded_size = block.len();
+        stream::write(&mut self.inner.stream, Frame::Headers(block))
             .await
-            .map_err(|e| self.maybe_conn_err(e))?;
+            .map_err(|e| self.inner.stream_err(e))?;
+        self.inner.conn_state.counters().headers_frames_sent.add(1);
+        // The size before encoding is unknown without decoding the block
+        self.inner.record_headers_sent(encoded_size, 0);
+        self.response_sent = true;
 
         Ok(())
     }
 
+    /// Send a response without body and finish the stream
+    ///
+    /// This is meant to reject a request right after reading its headers,
+    /// for example with a 401 or a 404. It fails without sending anything if
+    /// a final response has already been sent on this stream, or if the
+    /// status is informational.
+    pub async fn respond_and_finish(&mut self, resp: Response<()>) -> Result<(), Error> {
+        if self.response_sent {
+            return Err(Code::H3_INTERNAL_ERROR
+                .with_reason("response already sent", ErrorLevel::StreamError));
+        }
+        if resp.status().is_informational() {
+            return Err(Code::H3_INTERNAL_ERROR.with_reason(
+                format!("{} response cannot complete a request", resp.status()),
+                ErrorLevel::StreamError,
+            ));
+        }
+        self.send_response(resp).await?;
+        self.finish().await
+    }
+
+    /// Send a complete response with its body, and finish the stream
+    ///
+    /// This suits small responses held in memory, use [`RequestStream::send_response()`]
+    /// and [`RequestStream::send_data()`] to stream a body. Nothing is sent if a
+    /// final response has already been sent, if the status is informational,
+    /// or if the status does not allow a body (204 and 304) and the body is not
+    /// empty.
+    pub async fn send_complete(&mut self, resp: Response<Bytes>) -> Result<(), Error>
+    where
+        B: From<Bytes>,
+    {
+        let status = resp.status();
+        if self.response_sent {
+            return Err(Code::H3_INTERNAL_ERROR
+                .with_reason("response already sent", ErrorLevel::StreamError));
+        }
+        if status.is_informational() {
+            return Err(Code::H3_INTERNAL_ERROR.with_reason(
+                format!("{} response cannot complete a request", status),
+                ErrorLevel::StreamError,
+            ));
+        }
+        let (parts, body) = resp.into_parts();
+        if !body.is_empty()
+            && (status == StatusCode::NO_CONTENT || status == StatusCode::NOT_MODIFIED)
+        {
+            return Err(Code::H3_INTERNAL_ERROR.with_reason(
+                format!("{} response cannot have a body", status),
+                ErrorLevel::StreamError,
+            ));
+        }
+
+        self.send_response(Response::from_parts(parts, ())).await?;
+        if !body.is_empty() {
+            self.inner.send_data(B::from(body)).await?;
+        }
+        self.finish().await
+    }
+
     /// Send some data on the response body.
+    ///
+    /// This returns once the QUIC stream has accepted the frame, which is then
+    /// transmitted without waiting for more data or [`RequestStream::finish`].
+    /// Streamed responses don't need to flush. It fails with an error for
+    /// which [`Error::is_response_not_sent()`] is true if no final response
+    /// has been sent yet, as the body cannot come before the headers.
     pub async fn send_data(&mut self, buf: B) -> Result<(), Error> {
+        if !self.response_sent {
+            return Err(Error::response_not_sent());
+        }
         self.inner.send_data(buf).await
     }
 
+    /// Start sending some data on the body
+    ///
+    /// Together with [`RequestStream::poll_ready()`], this is the poll-based
+    /// counterpart of [`RequestStream::send_data()`]. The frame is written
+    /// as `poll_ready()` is polled, which must return `Ready` before the next
+    /// call, or this fails. Like `send_data()`, it fails if no final
+    /// response has been sent yet.
+    pub fn start_send_data(&mut self, buf: B) -> Result<(), Error> {
+        if !self.response_sent {
+            return Err(Error::response_not_sent());
+        }
+        self.inner.start_send_data(buf)
+    }
+
+    /// Poll until the data passed to [`RequestStream::start_send_data()`] is written
+    ///
+    /// It is `Ready` right away when there is nothing to write.
+    pub fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.inner.poll_ready(cx)
+    }
+
     /// Stop a stream with an error code
     ///
     /// The code can be [`Code::H3_NO_ERROR`].
@@ -661,12 +1662,36 @@ where
         self.inner.stop_stream(error_code);
     }
 
+    /// Wait for the client to stop the stream
+    ///
+    /// Resolves with the code of the client's `STOP_SENDING`, which it sends
+    /// along with a reset when abandoning a request, so a handler can give up
+    /// on expensive work without writing to the stream. The handles from
+    /// [`RequestStream::cancellation()`] resolve too. It never resolves if the
+    /// transport cannot report it, see [`quic::SendStream::poll_stopped()`].
+    pub async fn stopped(&mut self) -> Result<Code, Error> {
+        future::poll_fn(|cx| self.inner.poll_stopped(cx)).await
+    }
+
     /// Send a set of trailers to end the response.
     ///
     /// Either [`RequestStream::finish`] or
     /// [`RequestStream::send_trailers`] must be called to finalize a
-    /// request.
+    /// request. Trailers can be sent after a partial body, or without any
+    /// body at all, and finish the stream: calling [`RequestStream::finish`]
+    /// afterwards does nothing.
     pub async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), Error> {
+        if let Some(ref declared) = self.declared_trailers {
+            if let Some(name) = trailers.keys().find(|name| !declared.contains(name)) {
+                return Err(Code::H3_INTERNAL_ERROR.with_reason(
+                    format!(
+                        "trailer field {} is not declared in the Trailer header",
+                        name
+                    ),
+                    ErrorLevel::StreamError,
+                ));
+            }
+        }
         self.inner.send_trailers(trailers).await
     }
 
@@ -678,14 +1703,6 @@ where
     pub async fn finish(&mut self) -> Result<(), Error> {
         self.inner.finish().await
     }
-
-    //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1.1
-    //= type=TODO
-    //# Implementations SHOULD cancel requests by abruptly terminating any
-    //# directions of a stream that are still open.  To do so, an
-    //# implementation resets the sending parts of streams and aborts reading
-    //# on the receiving parts of streams; see Section 2.4 of
-    //# [QUIC-TRANSPORT].
 }
 
 impl<S, B> RequestStream<S, B>
@@ -706,13 +1723,73 @@ where
             RequestStream {
                 inner: send,
                 request_end: self.request_end.clone(),
+                remote_addr: self.remote_addr,
+                peer_certificates: self.peer_certificates.clone(),
+                response_sent: self.response_sent,
+                response_cache: self.response_cache,
+                declared_trailers: self.declared_trailers,
+                default_response_headers: self.default_response_headers.clone(),
             },
             RequestStream {
                 inner: recv,
                 request_end: self.request_end,
+                remote_addr: self.remote_addr,
+                peer_certificates: self.peer_certificates,
+                response_sent: false,
+                response_cache: None,
+                declared_trailers: None,
+                default_response_headers: self.default_response_headers,
             },
         )
     }
+
+    /// Joins the halves returned by [`RequestStream::split()`] back together.
+    ///
+    /// Fails if the halves come from different streams, or if the QUIC
+    /// implementation cannot join them. Both halves are then given back in
+    /// the error.
+    #[allow(clippy::result_large_err)] // as large as `Self`
+    pub fn unsplit(
+        send: RequestStream<S::SendStream, B>,
+        recv: RequestStream<S::RecvStream, B>,
+    ) -> Result<Self, Halves<S, B>> {
+        let inner = match connection::RequestStream::unsplit(send.inner, recv.inner) {
+            Ok(inner) => inner,
+            Err((send_inner, recv_inner)) => {
+                return Err(ReuniteError(
+                    RequestStream {
+                        inner: send_inner,
+                        ..send
+                    },
+                    RequestStream {
+                        inner: recv_inner,
+                        ..recv
+                    },
+                ))
+            }
+        };
+        Ok(RequestStream {
+            inner,
+            request_end: send.request_end,
+            remote_addr: send.remote_addr,
+            peer_certificates: send.peer_certificates,
+            response_sent: send.response_sent,
+            response_cache: send.response_cache,
+            declared_trailers: send.declared_trailers,
+            default_response_headers: send.default_response_headers,
+        })
+    }
+}
+
+/// Field names listed in the `Trailer` headers of a response
+fn declared_trailers(headers: &HeaderMap) -> Vec<HeaderName> {
+    headers
+        .get_all(TRAILER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect()
 }
 
 impl Drop for RequestEnd {
@@ -725,3 +1802,79 @@ impl Drop for RequestEnd {
         }
     }
 }
+
+/// Recently encoded response header blocks, by a hash of their status and headers
+///
+/// Lookups do not depend on the number of entries. Only inserting into a
+/// full cache walks them, to evict the least recently used one, and that
+/// follows a response being encoded, which costs more.
+struct ResponseCache {
+    capacity: usize,
+    entries: HashMap<u64, CachedResponse>,
+    // incremented by each use of an entry
+    clock: u64,
+}
+
+struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    block: Bytes,
+    mem_size: u64,
+    last_used: u64,
+}
+
+impl ResponseCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            clock: 0,
+        }
+    }
+
+    fn key(status: StatusCode, headers: &HeaderMap) -> u64 {
+        // Equal maps can list their fields in a different order
+        let fields = headers.iter().fold(0u64, |sum, field| {
+            let mut hasher = DefaultHasher::new();
+            field.hash(&mut hasher);
+            sum.wrapping_add(hasher.finish())
+        });
+        let mut hasher = DefaultHasher::new();
+        (status, fields).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn get(&mut self, status: StatusCode, headers: &HeaderMap) -> Option<(Bytes, u64)> {
+        let entry = self.entries.get_mut(&Self::key(status, headers))?;
+        // The hash only narrows the search, reusing a block needs equal fields
+        if entry.status != status || entry.headers != *headers {
+            return None;
+        }
+        self.clock += 1;
+        entry.last_used = self.clock;
+        Some((entry.block.clone(), entry.mem_size))
+    }
+
+    fn insert(&mut self, status: StatusCode, headers: HeaderMap, block: Bytes, mem_size: u64) {
+        let key = Self::key(status, &headers);
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        let entry = CachedResponse {
+            status,
+            headers,
+            block,
+            mem_size,
+            last_used: self.clock,
+        };
+        self.entries.insert(key, entry);
+    }
+}
@@ -0,0 +1,105 @@
+//! Statistics on HTTP/3 streams and connections
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Body transfer statistics of a request stream
+///
+/// Only the payload of DATA frames is accounted for in the byte counters,
+/// headers, trailers and frame headers are not. The size of the header
+/// sections sent is tracked separately, to measure QPACK compression.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct StreamStats {
+    /// Body bytes sent
+    pub bytes_sent: u64,
+    /// Body bytes received
+    pub bytes_recv: u64,
+    /// DATA frames sent
+    pub data_frames_sent: u64,
+    /// DATA frames received
+    pub data_frames_recv: u64,
+    /// QPACK-encoded size of the header sections sent, trailers included
+    pub headers_encoded_sent: u64,
+    /// Size of the header sections sent, before encoding
+    ///
+    /// It is computed like the limit of `SETTINGS_MAX_FIELD_SECTION_SIZE`:
+    /// the length of each field name and value, plus 32 bytes per field.
+    /// Responses sent already encoded, with
+    /// [`send_response_raw()`](crate::server::RequestStream::send_response_raw),
+    /// are only counted in `headers_encoded_sent`.
+    pub headers_size_sent: u64,
+}
+
+/// Cumulated statistics of an HTTP/3 connection
+///
+/// Byte counters only account for request and response bodies, like
+/// [`StreamStats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ConnectionStats {
+    /// Request streams accepted by the server, or opened by the client
+    pub requests: u64,
+    /// Request streams rejected by the server during a graceful shutdown
+    pub requests_rejected: u64,
+    /// HEADERS frames sent, including trailers
+    pub headers_frames_sent: u64,
+    /// HEADERS frames received, including trailers
+    pub headers_frames_recv: u64,
+    /// DATA frames sent
+    pub data_frames_sent: u64,
+    /// DATA frames received
+    pub data_frames_recv: u64,
+    /// Frames received on the peer's control stream
+    pub control_frames_recv: u64,
+    /// Body bytes sent
+    pub bytes_sent: u64,
+    /// Body bytes received
+    pub bytes_recv: u64,
+}
+
+/// Counters behind [`ConnectionStats`], shared by a connection and its streams
+///
+/// They are updated without locking, so the hot paths are not slowed down.
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionCounters {
+    pub(crate) requests: Counter,
+    pub(crate) requests_rejected: Counter,
+    pub(crate) headers_frames_sent: Counter,
+    pub(crate) headers_frames_recv: Counter,
+    pub(crate) data_frames_sent: Counter,
+    pub(crate) data_frames_recv: Counter,
+    pub(crate) control_frames_recv: Counter,
+    pub(crate) bytes_sent: Counter,
+    pub(crate) bytes_recv: Counter,
+}
+
+impl ConnectionCounters {
+    pub(crate) fn snapshot(&self) -> ConnectionStats {
+        ConnectionStats {
+            requests: self.requests.get(),
+            requests_rejected: self.requests_rejected.get(),
+            headers_frames_sent: self.headers_frames_sent.get(),
+            headers_frames_recv: self.headers_frames_recv.get(),
+            data_frames_sent: self.data_frames_sent.get(),
+            data_frames_recv: self.data_frames_recv.get(),
+            control_frames_recv: self.control_frames_recv.get(),
+            bytes_sent: self.bytes_sent.get(),
+            bytes_recv: self.bytes_recv.get(),
+        }
+    }
+}
+
+/// A statistics counter, updated with relaxed ordering as each counter is
+/// independent
+#[derive(Debug, Default)]
+pub(crate) struct Counter(AtomicU64);
+
+impl Counter {
+    pub(crate) fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub(crate) fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
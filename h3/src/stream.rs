@@ -6,18 +6,23 @@ use quic::RecvStream;
 
 use crate::{
     buf::BufList,
+    config::Tap,
     error::{Code, ErrorLevel},
     frame::FrameStream,
     proto::{
         coding::{BufExt, Decode as _, Encode},
-        frame::Frame,
-        stream::StreamType,
+        frame::{Frame, Settings},
+        stream::{StreamId, StreamType},
         varint::VarInt,
     },
     quic::{self, SendStream},
     Error,
 };
 
+/// Write a frame, waiting until the QUIC stream has accepted all of it
+///
+/// Nothing is buffered at this layer: once this returns, transmitting the
+/// frame is up to the transport, so there is no separate flush.
 #[inline]
 pub(crate) async fn write<S, D, B>(stream: &mut S, data: D) -> Result<(), Error>
 where
@@ -31,7 +36,9 @@ where
     Ok(())
 }
 
-const WRITE_BUF_ENCODE_SIZE: usize = StreamType::MAX_ENCODED_SIZE + Frame::MAX_ENCODED_SIZE;
+// Settings are the only frame whose payload is encoded along with the header
+const WRITE_BUF_ENCODE_SIZE: usize =
+    StreamType::MAX_ENCODED_SIZE + Frame::MAX_ENCODED_SIZE + Settings::MAX_ENCODED_SIZE;
 
 /// Wrap frames to encode their header on the stack before sending them on the wire
 ///
@@ -51,6 +58,7 @@ where
     len: usize,
     pos: usize,
     frame: Option<Frame<B>>,
+    tap: Tap,
 }
 
 impl<B> WriteBuf<B>
@@ -63,6 +71,10 @@ where
         self.len = WRITE_BUF_ENCODE_SIZE - buf_mut.remaining_mut();
     }
 
+    pub(crate) fn set_tap(&mut self, tap: Tap) {
+        self.tap = tap;
+    }
+
     fn encode_frame_header(&mut self) {
         if let Some(frame) = self.frame.as_ref() {
             let mut buf_mut = &mut self.buf[self.len..];
@@ -82,6 +94,7 @@ where
             len: 0,
             pos: 0,
             frame: None,
+            tap: Tap::default(),
         };
         me.encode_stream_type(ty);
         me
@@ -98,6 +111,7 @@ where
             len: 0,
             pos: 0,
             frame: Some(frame),
+            tap: Tap::default(),
         };
         me.encode_frame_header();
         me
@@ -115,6 +129,7 @@ where
             len: 0,
             pos: 0,
             frame: Some(frame),
+            tap: Tap::default(),
         };
         me.encode_stream_type(ty);
         me.encode_frame_header();
@@ -122,6 +137,22 @@ where
     }
 }
 
+impl<B> From<B> for WriteBuf<B>
+where
+    B: Buf,
+{
+    /// Data written as is, without a frame header, for extension streams
+    fn from(data: B) -> Self {
+        Self {
+            buf: [0; WRITE_BUF_ENCODE_SIZE],
+            len: 0,
+            pos: 0,
+            frame: Some(Frame::Data(data)),
+            tap: Tap::default(),
+        }
+    }
+}
+
 impl<B> Buf for WriteBuf<B>
 where
     B: Buf,
@@ -149,11 +180,20 @@ where
         let remaining_header = self.len - self.pos;
         if remaining_header > 0 {
             let advanced = usize::min(cnt, remaining_header);
+            self.tap.sent(&self.buf[self.pos..self.pos + advanced]);
             self.pos += advanced;
             cnt -= advanced;
         }
 
         if let Some(payload) = self.frame.as_mut().and_then(|f| f.payload_mut()) {
+            // The payload can span several chunks, report them one by one
+            while self.tap.is_set() && cnt > 0 && !payload.chunk().is_empty() {
+                let chunk = payload.chunk();
+                let advanced = usize::min(cnt, chunk.len());
+                self.tap.sent(&chunk[..advanced]);
+                payload.advance(advanced);
+                cnt -= advanced;
+            }
             payload.advance(cnt);
         }
     }
@@ -164,7 +204,7 @@ where
     S: quic::RecvStream,
 {
     Control(FrameStream<S, B>),
-    Push(u64, FrameStream<S, B>),
+    Push(u64, StreamId, FrameStream<S, B>),
     Encoder(S),
     Decoder(S),
     Reserved,
@@ -179,34 +219,37 @@ where
     push_id: Option<u64>,
     buf: BufList<Bytes>,
     expected: Option<usize>,
+    tap: Tap,
 }
 
 impl<S> AcceptRecvStream<S>
 where
     S: RecvStream,
 {
-    pub fn new(stream: S) -> Self {
+    pub fn new(stream: S, tap: Tap) -> Self {
         Self {
             stream,
             ty: None,
             push_id: None,
             buf: BufList::new(),
             expected: None,
+            tap,
         }
     }
 
     pub fn into_stream<B>(self) -> Result<AcceptedRecvStream<S, B>, Error> {
         Ok(match self.ty.expect("Stream type not resolved yet") {
             StreamType::CONTROL => {
-                AcceptedRecvStream::Control(FrameStream::with_bufs(self.stream, self.buf))
+                AcceptedRecvStream::Control(FrameStream::with_bufs(self.stream, self.buf, self.tap))
             }
             StreamType::PUSH => AcceptedRecvStream::Push(
                 self.push_id.expect("Push ID not resolved yet"),
-                FrameStream::with_bufs(self.stream, self.buf),
+                self.stream.recv_id(),
+                FrameStream::with_bufs(self.stream, self.buf, self.tap),
             ),
             StreamType::ENCODER => AcceptedRecvStream::Encoder(self.stream),
             StreamType::DECODER => AcceptedRecvStream::Decoder(self.stream),
-            t if t.value() > 0x21 && (t.value() - 0x21) % 0x1f == 0 => AcceptedRecvStream::Reserved,
+            t if t.is_grease() => AcceptedRecvStream::Reserved,
 
             //= https://www.rfc-editor.org/rfc/rfc9114#section-6.2
             //# Recipients of unknown stream types MUST
@@ -235,29 +278,26 @@ where
         loop {
             match (self.ty.as_ref(), self.push_id) {
                 // When accepting a Push stream, we want to parse two VarInts: [StreamType, PUSH_ID]
-                (Some(&StreamType::PUSH), Some(_)) | (Some(_), _) => return Poll::Ready(Ok(())),
+                (Some(&StreamType::PUSH), None) => (),
+                (Some(_), _) => return Poll::Ready(Ok(())),
                 _ => (),
             }
 
-            match ready!(self.stream.poll_data(cx))? {
-                Some(mut b) => self.buf.push_bytes(&mut b),
-                None => {
-                    return Poll::Ready(Err(Code::H3_STREAM_CREATION_ERROR.with_reason(
-                        "Stream closed before type received",
-                        ErrorLevel::ConnectionError,
-                    )));
-                }
-            };
-
             if self.expected.is_none() && self.buf.remaining() >= 1 {
                 self.expected = Some(VarInt::encoded_size(self.buf.chunk()[0]));
             }
 
-            if let Some(expected) = self.expected {
-                if self.buf.remaining() < expected {
-                    continue;
-                }
-            } else {
+            // The push ID may have been received along with the stream type
+            if !matches!(self.expected, Some(expected) if self.buf.remaining() >= expected) {
+                match ready!(self.stream.poll_data(cx))? {
+                    Some(mut b) => self.tap.received(self.buf.push_bytes(&mut b)),
+                    None => {
+                        return Poll::Ready(Err(Code::H3_STREAM_CREATION_ERROR.with_reason(
+                            "Stream closed before type received",
+                            ErrorLevel::ConnectionError,
+                        )));
+                    }
+                };
                 continue;
             }
 
@@ -334,4 +374,12 @@ mod tests {
         wbuf.advance(4);
         assert_eq!(wbuf.chunk(), b"ey");
     }
+
+    #[test]
+    fn write_buf_raw_data_has_no_header() {
+        let wbuf = WriteBuf::<Bytes>::from(Bytes::from("hey"));
+
+        assert_eq!(wbuf.remaining(), 3);
+        assert_eq!(wbuf.chunk(), b"hey");
+    }
 }
@@ -1,7 +1,13 @@
 // identity_op: we write out how test values are computed
 #![allow(clippy::identity_op)]
 
-use std::{borrow::BorrowMut, time::Duration};
+use std::{
+    borrow::BorrowMut,
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc, Mutex},
+    task::Poll,
+    time::Duration,
+};
 
 use assert_matches::assert_matches;
 use bytes::{Buf, Bytes, BytesMut};
@@ -11,12 +17,12 @@ use http::{Request, Response, StatusCode};
 use crate::{
     client::{self, SendRequest},
     connection::ConnectionState,
-    error::{Code, Error, Kind},
+    error::{CloseOrigin, Code, Error, ErrorLevel, Kind},
     proto::{
-        coding::Encode as _,
-        frame::{Frame, Settings},
+        coding::{Decode as _, Encode as _},
+        frame::{Frame, FrameError, SettingId, Settings},
         push::PushId,
-        stream::StreamType,
+        stream::{StreamId, StreamType},
         varint::VarInt,
     },
     quic::{self, SendStream},
@@ -24,7 +30,7 @@ use crate::{
 };
 
 use super::h3_quinn;
-use super::{init_tracing, Pair};
+use super::{init_tracing, MockConnection, Pair};
 
 #[tokio::test]
 async fn connect() {
@@ -43,6 +49,86 @@ async fn connect() {
     tokio::join!(server_fut, client_fut);
 }
 
+#[tokio::test]
+async fn remote_addr() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, send_request) = client::new(pair.client().await).await.unwrap();
+        let remote = driver.remote_addr().expect("server address");
+        assert!(remote.ip().is_loopback());
+        assert_eq!(remote.port(), pair.port);
+
+        let drive = async {
+            future::poll_fn(|cx| driver.poll_close(cx)).await.unwrap();
+        };
+        tokio::select! {
+            res = request(send_request) => { res.unwrap(); }
+            _ = drive => panic!("driver resolved first"),
+        };
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        let remote = incoming.remote_addr().expect("client address");
+        assert!(remote.ip().is_loopback());
+
+        let (_, stream) = incoming.accept().await.unwrap().unwrap();
+        assert_eq!(stream.remote_addr(), Some(remote));
+        response(stream).await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn tls_info() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (driver, _send_request) = client::new(pair.client().await).await.unwrap();
+        let info = driver.tls_info().expect("tls info");
+        let handshake = info
+            .handshake_data
+            .expect("handshake data")
+            .downcast::<quinn::crypto::rustls::HandshakeData>()
+            .unwrap();
+        assert_eq!(handshake.protocol.as_deref(), Some(&b"h3"[..]));
+        let certs = info
+            .peer_identity
+            .expect("server identity")
+            .downcast::<Vec<rustls::Certificate>>()
+            .unwrap();
+        assert_eq!(certs.len(), 1);
+        // The DER chain is also exposed without downcasting
+        let der = info.peer_certificates.expect("server certificates");
+        assert_eq!(der.len(), 1);
+        assert_eq!(der[0], pair.cert.0);
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let incoming = server::Connection::new(conn).await.unwrap();
+        let info = incoming.tls_info().expect("tls info");
+        let handshake = info
+            .handshake_data
+            .expect("handshake data")
+            .downcast::<quinn::crypto::rustls::HandshakeData>()
+            .unwrap();
+        assert_eq!(handshake.server_name.as_deref(), Some("localhost"));
+        assert_eq!(handshake.protocol.as_deref(), Some(&b"h3"[..]));
+        // The client does not authenticate
+        assert!(info.peer_identity.is_none());
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
 #[tokio::test]
 async fn accept_request_end_on_client_close() {
     let mut pair = Pair::default();
@@ -215,6 +301,330 @@ async fn settings_exchange_server() {
     tokio::select! { _ = server_fut => (), _ = client_fut => () };
 }
 
+#[tokio::test]
+async fn settings_exchange_extra_setting() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut conn, send_request) = client::builder()
+            .extra_setting(0x4242, 7)
+            .build::<_, _, Bytes>(pair.client().await)
+            .await
+            .expect("client init");
+        let peer_setting = future::poll_fn(|cx| {
+            if let Poll::Ready(res) = conn.poll_close(cx) {
+                panic!("driver resolved first: {:?}", res);
+            }
+            match conn.peer_settings() {
+                Some(settings) => Poll::Ready(settings.get(0x2424)),
+                None => Poll::Pending,
+            }
+        })
+        .await;
+        assert_eq!(peer_setting, Some(3));
+
+        let drive = future::poll_fn(|cx| conn.poll_close(cx));
+        tokio::select! { res = request(send_request) => assert_matches!(res, Ok(_)), _ = drive => panic!("driver resolved first") };
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .extra_setting(0x2424, 3)
+            .build(conn)
+            .await
+            .unwrap();
+        let (_, stream) = incoming.accept().await.unwrap().unwrap();
+        let peer_settings = incoming.peer_settings().expect("peer settings");
+        assert_eq!(peer_settings.get(0x4242), Some(7));
+        response(stream).await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[cfg(feature = "wire-tap")]
+#[tokio::test]
+async fn wire_tap_settings() {
+    use crate::{config::Direction, proto::frame::PayloadLen};
+
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let contains = |bytes: &[u8], needle: &[u8]| bytes.windows(needle.len()).any(|w| w == needle);
+
+    let tapped = Arc::new(Mutex::new((Vec::new(), Vec::new())));
+    let tap = tapped.clone();
+
+    let client_fut = async {
+        let (mut conn, _send_request) = client::builder()
+            .max_field_section_size(12)
+            .wire_tap(move |direction, bytes| {
+                let mut tapped = tap.lock().unwrap();
+                match direction {
+                    Direction::Sent => tapped.0.extend_from_slice(bytes),
+                    Direction::Received => tapped.1.extend_from_slice(bytes),
+                }
+            })
+            .build::<_, _, Bytes>(pair.client().await)
+            .await
+            .expect("client init");
+        future::poll_fn(|cx| {
+            if let Poll::Ready(res) = conn.poll_close(cx) {
+                panic!("driver resolved first: {:?}", res);
+            }
+            match conn.peer_settings() {
+                Some(_) => Poll::Ready(()),
+                None => Poll::Pending,
+            }
+        })
+        .await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .send_grease(false)
+            .max_field_section_size(207)
+            .build(conn)
+            .await
+            .unwrap();
+        incoming.accept().await
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
+
+    let (sent, received) = &*tapped.lock().unwrap();
+    // The control stream is written first, its SETTINGS include a grease setting
+    let mut control = &sent[..];
+    assert_eq!(
+        StreamType::decode(&mut control).unwrap(),
+        StreamType::CONTROL
+    );
+    assert_matches!(
+        Frame::<PayloadLen>::decode(&mut control),
+        Ok(Frame::Settings(settings)) if settings.get(SettingId::MAX_HEADER_LIST_SIZE) == Some(12)
+    );
+
+    // The server sends no grease
+    let mut settings = Settings::default();
+    settings
+        .insert(SettingId::MAX_HEADER_LIST_SIZE, 207)
+        .unwrap();
+    let mut server_settings = BytesMut::new();
+    StreamType::CONTROL.encode(&mut server_settings);
+    Frame::<Bytes>::Settings(settings).encode(&mut server_settings);
+    assert!(contains(received, &server_settings));
+}
+
+#[tokio::test]
+async fn builder_changes_do_not_affect_built_connection() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut conn, send_request) = client::new(pair.client().await).await.expect("client init");
+        let drive = future::poll_fn(|cx| conn.poll_close(cx));
+        tokio::select! { res = request(send_request) => assert_matches!(res, Ok(_)), _ = drive => panic!("driver resolved first") };
+        let peer_settings = conn.peer_settings().expect("peer settings");
+        assert_eq!(peer_settings.get(0x2424), Some(3));
+        assert_eq!(peer_settings.get(0x4242), None);
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut builder = server::builder();
+        builder.extra_setting(0x2424, 3);
+        let mut incoming = builder.build(conn).await.unwrap();
+        builder.extra_setting(0x4242, 7).max_field_section_size(12);
+
+        let (_, stream) = incoming.accept().await.unwrap().unwrap();
+        response(stream).await;
+        let _ = incoming.accept().await;
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
+}
+
+#[tokio::test]
+async fn peer_capabilities() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut conn, send_request) = client::builder()
+            .extra_setting(SettingId::ENABLE_CONNECT_PROTOCOL.0, 1)
+            .extra_setting(SettingId::H3_DATAGRAM.0, 1)
+            .build::<_, _, Bytes>(pair.client().await)
+            .await
+            .expect("client init");
+        assert!(!conn.peer_supports_extended_connect());
+
+        let drive = future::poll_fn(|cx| conn.poll_close(cx));
+        tokio::select! { res = request(send_request) => assert_matches!(res, Ok(_)), _ = drive => panic!("driver resolved first") };
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        let (_, stream) = incoming.accept().await.unwrap().unwrap();
+        assert!(incoming.peer_supports_extended_connect());
+        assert!(incoming.peer_supports_datagrams());
+        assert!(!incoming.peer_supports_webtransport());
+        response(stream).await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn connection_stats() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut send_request) = client::new(pair.client().await).await.unwrap();
+        let requests = async {
+            for _ in 0..3 {
+                let mut request_stream = send_request
+                    .send_request(Request::post("http://no.way").body(()).unwrap())
+                    .await
+                    .unwrap();
+                request_stream
+                    .send_data(Bytes::from("hello"))
+                    .await
+                    .unwrap();
+                request_stream.finish().await.unwrap();
+                request_stream.recv_response().await.unwrap();
+            }
+        };
+        let drive = async {
+            future::poll_fn(|cx| driver.poll_close(cx)).await.unwrap();
+        };
+        tokio::select! { _ = requests => (), _ = drive => panic!("driver resolved first") };
+
+        let stats = driver.stats();
+        assert_eq!(stats.requests, 3);
+        assert_eq!(stats.headers_frames_sent, 3);
+        assert_eq!(stats.headers_frames_recv, 3);
+        assert_eq!(stats.data_frames_sent, 3);
+        assert_eq!(stats.bytes_sent, 15);
+        assert_eq!(stats.bytes_recv, 0);
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        for _ in 0..3 {
+            let (_, mut stream) = incoming.accept().await.unwrap().unwrap();
+            while stream.recv_data().await.unwrap().is_some() {}
+            response(stream).await;
+        }
+
+        let stats = incoming.stats();
+        assert_eq!(stats.requests, 3);
+        assert_eq!(stats.requests_rejected, 0);
+        assert_eq!(stats.headers_frames_recv, 3);
+        assert_eq!(stats.headers_frames_sent, 3);
+        assert_eq!(stats.data_frames_recv, 3);
+        assert_eq!(stats.bytes_recv, 15);
+        assert_eq!(stats.data_frames_sent, 0);
+        assert!(incoming.peer_settings().is_none() || stats.control_frames_recv >= 1);
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn request_before_server_settings() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut send_request) = client::new(pair.client().await).await.unwrap();
+
+        // The driver has not been polled, SETTINGS from the server are unknown
+        let mut request_stream = send_request
+            .send_request(Request::get("http://no.way").body(()).unwrap())
+            .await
+            .unwrap();
+        assert!(driver.peer_settings().is_none());
+
+        let drive = async {
+            future::poll_fn(|cx| driver.poll_close(cx)).await.unwrap();
+        };
+        tokio::select! {
+            res = request_stream.recv_response() => {
+                assert_eq!(res.unwrap().status(), StatusCode::IM_A_TEAPOT)
+            }
+            _ = drive => panic!("driver resolved first"),
+        };
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        let (_, stream) = incoming.accept().await.unwrap().unwrap();
+        response(stream).await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn request_waits_for_server_settings() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut send_request) = client::builder()
+            .wait_for_settings(true)
+            .build::<_, _, Bytes>(pair.client().await)
+            .await
+            .unwrap();
+
+        let mut request_stream = {
+            let drive = future::poll_fn(|cx| driver.poll_close(cx));
+            tokio::select! {
+                res = send_request.send_request(Request::get("http://no.way").body(()).unwrap()) => {
+                    res.unwrap()
+                }
+                res = drive => panic!("driver resolved first: {:?}", res),
+            }
+        };
+        assert!(driver.peer_settings().is_some());
+
+        let drive = async {
+            future::poll_fn(|cx| driver.poll_close(cx)).await.unwrap();
+        };
+        tokio::select! {
+            res = request_stream.recv_response() => {
+                assert_eq!(res.unwrap().status(), StatusCode::IM_A_TEAPOT)
+            }
+            _ = drive => panic!("driver resolved first"),
+        };
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        // Delay the server's SETTINGS
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        let (_, stream) = incoming.accept().await.unwrap().unwrap();
+        response(stream).await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
 #[tokio::test]
 async fn client_error_on_bidi_recv() {
     let mut pair = Pair::default();
@@ -305,6 +715,43 @@ async fn two_control_streams() {
     tokio::select! { _ = server_fut => (), _ = client_fut => panic!("client resolved first") };
 }
 
+#[tokio::test]
+async fn too_many_pending_uni_streams() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+
+        // Start a 2-byte stream type varint and never finish it
+        for _ in 0..3 {
+            let mut stream = connection.open_uni().await.unwrap();
+            stream.write_all(&[0x40]).await.unwrap();
+        }
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .max_pending_uni_streams(2)
+            .build(conn)
+            .await
+            .unwrap();
+        assert_matches!(
+            incoming.accept().await.map(|_| ()).unwrap_err().kind(),
+            Kind::Application {
+                code: Code::H3_EXCESSIVE_LOAD,
+                ..
+            }
+        );
+    };
+
+    tokio::select! { _ = server_fut => (), _ = client_fut => panic!("client resolved first") };
+}
+
 #[tokio::test]
 async fn control_close_send_error() {
     init_tracing();
@@ -391,7 +838,7 @@ async fn missing_settings() {
 }
 
 #[tokio::test]
-async fn control_stream_frame_unexpected() {
+async fn settings_grease_ignored() {
     init_tracing();
     let mut pair = Pair::default();
     let mut server = pair.server();
@@ -400,11 +847,51 @@ async fn control_stream_frame_unexpected() {
         let connection = pair.client_inner().await;
         let mut control_stream = connection.open_uni().await.unwrap();
 
-        //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.1
-        //= type=test
-        //# If
-        //# a DATA frame is received on a control stream, the recipient MUST
-        //# respond with a connection error of type H3_FRAME_UNEXPECTED.
+        let mut settings = Settings::default();
+        settings.insert(SettingId(0x21), 1).unwrap();
+        settings
+            .insert(SettingId::MAX_HEADER_LIST_SIZE, 12)
+            .unwrap();
+        settings.insert(SettingId(0x1f * 42 + 0x21), 2).unwrap();
+
+        let mut buf = BytesMut::new();
+        StreamType::CONTROL.encode(&mut buf);
+        Frame::<Bytes>::Settings(settings).encode(&mut buf);
+        control_stream.write_all(&buf[..]).await.unwrap();
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        tokio::select! {
+            res = incoming.accept() => panic!("accept resolved: {:?}", res.map(|_| ())),
+            _ = tokio::time::sleep(Duration::from_millis(50)) => (),
+        };
+        let peer_settings = incoming.peer_settings().expect("peer settings");
+        assert_eq!(peer_settings.max_field_section_size(), Some(12));
+        assert_eq!(peer_settings.iter().count(), 1);
+    };
+
+    tokio::select! { _ = server_fut => (), _ = client_fut => panic!("client resolved first") };
+}
+
+#[tokio::test]
+async fn control_stream_frame_unexpected() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+        let mut control_stream = connection.open_uni().await.unwrap();
+
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.1
+        //= type=test
+        //# If
+        //# a DATA frame is received on a control stream, the recipient MUST
+        //# respond with a connection error of type H3_FRAME_UNEXPECTED.
         let mut buf = BytesMut::new();
         StreamType::CONTROL.encode(&mut buf);
         Frame::Data(Bytes::from("")).encode(&mut buf);
@@ -453,6 +940,147 @@ async fn timeout_on_control_frame_read() {
     tokio::join!(server_fut, client_fut);
 }
 
+#[tokio::test]
+async fn idle_timeout_error() {
+    init_tracing();
+    let mut pair = Pair::default();
+    pair.with_timeout(Duration::from_millis(10));
+
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, _send_request) = client::new(pair.client().await).await.unwrap();
+        let err = future::poll_fn(|cx| driver.poll_close(cx))
+            .await
+            .unwrap_err();
+        assert!(err.is_timeout());
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        let err = incoming.accept().await.map(|_| ()).unwrap_err();
+        assert!(err.is_timeout());
+        assert_eq!(err.to_string(), "connection idle timeout");
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn send_keepalive() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+        let (mut control, mut buf) = loop {
+            let mut recv = connection.accept_uni().await.unwrap();
+            let chunk = recv.read_chunk(usize::MAX, true).await.unwrap().unwrap();
+            let mut buf = chunk.bytes;
+            if StreamType::decode(&mut buf).unwrap() == StreamType::CONTROL {
+                break (recv, buf);
+            }
+        };
+
+        // SETTINGS, then the keep-alive frame of a reserved type
+        loop {
+            let mut frame = buf.clone();
+            match Frame::decode(&mut frame) {
+                Ok(Frame::Settings(_)) => buf = frame,
+                Err(FrameError::UnknownFrame(ty)) => {
+                    assert_eq!((ty - 0x21) % 0x1f, 0);
+                    break;
+                }
+                Err(FrameError::Incomplete(_)) => {
+                    let chunk = control.read_chunk(usize::MAX, true).await.unwrap().unwrap();
+                    buf = [buf, chunk.bytes].concat().into();
+                }
+                other => panic!("unexpected frame: {:?}", other),
+            }
+        }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        incoming.send_keepalive().await.unwrap();
+        let _ = incoming.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn send_control_frame() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, send_request) = client::new(pair.client().await).await.unwrap();
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        tokio::select! {
+            res = request(send_request) => assert_matches!(res, Ok(_)),
+            res = drive_fut => panic!("client driver resolved: {:?}", res),
+        }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        assert!(incoming
+            .send_control_frame(0x4, Bytes::from_static(b"settings"))
+            .await
+            .is_err());
+        incoming
+            .send_control_frame(0xf0700, Bytes::from_static(b"extension"))
+            .await
+            .unwrap();
+        let (_, stream) = incoming.accept().await.unwrap().unwrap();
+        response(stream).await;
+        let _ = incoming.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn open_uni_extension_stream() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+        let mut recv = loop {
+            let mut recv = connection.accept_uni().await.unwrap();
+            // 0x3a is encoded on a single byte
+            let chunk = recv.read_chunk(1, true).await.unwrap().unwrap();
+            if chunk.bytes[..] == [0x3a] {
+                break recv;
+            }
+        };
+        // Only the stream type is written by h3, the rest is up to the user
+        assert_eq!(recv.read_to_end(64).await.unwrap(), b"extension");
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        assert!(incoming.open_uni(0x00).await.is_err());
+        assert!(incoming.open_uni(0x21).await.is_err());
+        let mut stream = incoming.open_uni(0x3a).await.unwrap();
+        assert!(stream.send_data(Bytes::from_static(b"extension")).is_ok());
+        assert!(future::poll_fn(|cx| stream.poll_ready(cx)).await.is_ok());
+        assert!(future::poll_fn(|cx| stream.poll_finish(cx)).await.is_ok());
+        let _ = incoming.accept().await;
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
+}
+
 #[tokio::test]
 async fn goaway_from_server_not_request_id() {
     init_tracing();
@@ -545,12 +1173,64 @@ async fn graceful_shutdown_server_rejects() {
         response(stream).await;
         incoming.shutdown(0).await.unwrap();
         assert_matches!(incoming.accept().await.map(|x| x.map(|_| ())), Ok(None));
+        assert_eq!(incoming.stats().requests, 1);
+        assert_eq!(incoming.stats().requests_rejected, 1);
         server.endpoint.wait_idle().await;
     };
 
     tokio::join!(server_fut, client_fut);
 }
 
+#[tokio::test]
+async fn goaway_received() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+    let (goaway_sent, wait_goaway) = tokio::sync::oneshot::channel();
+    let (goaway_recv, wait_server) = tokio::sync::oneshot::channel();
+
+    let client_fut = async {
+        let (mut driver, send_request) = client::new(pair.client().await).await.unwrap();
+        assert!(
+            future::poll_fn(|cx| Poll::Ready(send_request.poll_closing(cx)))
+                .await
+                .is_pending()
+        );
+        assert_eq!(driver.peer_goaway_id(), None);
+        wait_goaway.await.unwrap();
+
+        let drive = future::poll_fn(|cx| driver.poll_close(cx));
+        tokio::select! {
+            _ = future::poll_fn(|cx| send_request.poll_closing(cx)) => (),
+            res = drive => panic!("driver resolved first: {:?}", res),
+        }
+        assert!(future::poll_fn(|cx| Poll::Ready(driver.poll_closing(cx)))
+            .await
+            .is_ready());
+        assert_eq!(driver.peer_goaway_id(), Some(StreamId::FIRST_REQUEST));
+        // Draining is not closing: the connection is still up
+        assert!(driver.peer_settings().is_some());
+        driver.shutdown(0).await.unwrap();
+        wait_server.await.unwrap();
+        assert_matches!(future::poll_fn(|cx| driver.poll_close(cx)).await, Ok(()));
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        incoming.shutdown(0).await.unwrap();
+        goaway_sent.send(()).unwrap();
+        assert!(incoming.accept().await.unwrap().is_none());
+        assert!(future::poll_fn(|cx| Poll::Ready(incoming.poll_closing(cx)))
+            .await
+            .is_ready());
+        assert_eq!(incoming.peer_goaway_id(), Some(PushId(0)));
+        goaway_recv.send(()).unwrap();
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
 #[tokio::test]
 async fn graceful_shutdown_grace_interval() {
     init_tracing();
@@ -653,6 +1333,310 @@ async fn graceful_shutdown_closes_when_idle() {
     };
 }
 
+#[tokio::test]
+async fn drop_after_graceful_shutdown_closes_once() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (_driver, mut send_request) = client::new(pair.client().await).await.unwrap();
+
+        let mut first = send_request
+            .send_request(Request::get("http://no.way").body(()).unwrap())
+            .await
+            .unwrap();
+        let mut rejected = send_request
+            .send_request(Request::get("http://no.way").body(()).unwrap())
+            .await
+            .unwrap();
+        assert_matches!(first.recv_response().await, Ok(_));
+        assert!(rejected.recv_response().await.is_err());
+    };
+
+    let server_fut = async {
+        let conn = MockConnection::new(server.next().await);
+        let closes = conn.closes.clone();
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        let (_, stream) = incoming.accept().await.unwrap().unwrap();
+        response(stream).await;
+        incoming.shutdown(0).await.unwrap();
+        assert_matches!(incoming.accept().await.map(|x| x.map(|_| ())), Ok(None));
+        assert_eq!(closes.load(Ordering::SeqCst), 0);
+        drop(incoming);
+        assert_eq!(closes.load(Ordering::SeqCst), 1);
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn control_stream_priority() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        for _ in 0..2 {
+            let _ = client::new(pair.client().await).await.expect("client init");
+        }
+    };
+
+    let server_fut = async {
+        let conn = MockConnection::new(server.next().await);
+        let priorities = conn.priorities.clone();
+        let _ = server::Connection::new(conn).await.unwrap();
+        // The grease stream keeps the transport's default priority
+        assert_eq!(priorities.lock().unwrap()[..], [i32::MAX]);
+
+        let conn = MockConnection::new(server.next().await);
+        let priorities = conn.priorities.clone();
+        let _ = server::builder()
+            .control_stream_priority(7)
+            .build(conn)
+            .await
+            .unwrap();
+        assert_eq!(priorities.lock().unwrap()[..], [7]);
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn path_change_reported() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut send_request) = client::new(pair.client().await).await.unwrap();
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = send_request
+                .send_request(Request::get("http://no.way").body(()).unwrap())
+                .await
+                .unwrap();
+            request_stream.finish().await.unwrap();
+            assert_matches!(request_stream.recv_response().await, Ok(_));
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let migrated: SocketAddr = "[::1]:4433".parse().unwrap();
+        let conn = MockConnection::new(server.next().await);
+        conn.path_changes.lock().unwrap().push_back(migrated);
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let mut incoming = server::builder()
+            .on_path_change({
+                let reported = reported.clone();
+                move |addr| reported.lock().unwrap().push(addr)
+            })
+            .build(conn)
+            .await
+            .unwrap();
+
+        // The request goes on as usual over the new path
+        let (_, stream) = incoming.accept().await.unwrap().unwrap();
+        assert_eq!(reported.lock().unwrap()[..], [migrated]);
+        response(stream).await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn drop_after_connection_error_does_not_close_again() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+        for _ in 0..=1 {
+            let mut control_stream = connection.open_uni().await.unwrap();
+            let mut buf = BytesMut::new();
+            StreamType::CONTROL.encode(&mut buf);
+            control_stream.write_all(&buf[..]).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    let server_fut = async {
+        let conn = MockConnection::new(server.next().await);
+        let closes = conn.closes.clone();
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        assert!(incoming.accept().await.is_err());
+        assert_eq!(closes.load(Ordering::SeqCst), 1);
+        drop(incoming);
+        assert_eq!(closes.load(Ordering::SeqCst), 1);
+    };
+
+    tokio::select! { _ = server_fut => (), _ = client_fut => panic!("client resolved first") };
+}
+
+#[tokio::test]
+async fn close_origin_peer() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+        connection.close(
+            quinn::VarInt::from_u64(Code::H3_EXCESSIVE_LOAD.value()).unwrap(),
+            b"overloaded",
+        );
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        let err = incoming.accept().await.map(|_| ()).unwrap_err();
+        assert_eq!(
+            err.close_origin(),
+            Some(&CloseOrigin::Peer {
+                code: Code::H3_EXCESSIVE_LOAD,
+                reason: Some("overloaded".into()),
+            })
+        );
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn server_close_reason() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, _client) = client::new(pair.client().await).await.expect("client init");
+        let err = future::poll_fn(|cx| driver.poll_close(cx))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.close_origin(),
+            Some(&CloseOrigin::Peer {
+                code: Code::H3_EXCESSIVE_LOAD,
+                reason: Some("going down for maintenance".into()),
+            })
+        );
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        incoming.close(Code::H3_EXCESSIVE_LOAD, "going down for maintenance");
+        assert!(incoming.accept().await.is_err());
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn client_close_reason() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, _client) = client::new(pair.client().await).await.expect("client init");
+        driver.close(Code::H3_INTERNAL_ERROR, "configuration reloaded");
+        // Keep the connection until the server has seen the close
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        let err = incoming.accept().await.map(|_| ()).unwrap_err();
+        assert_eq!(
+            err.close_origin(),
+            Some(&CloseOrigin::Peer {
+                code: Code::H3_INTERNAL_ERROR,
+                reason: Some("configuration reloaded".into()),
+            })
+        );
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn close_reason_truncated() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+    // 2 bytes per char, so the 1024 bytes limit falls in the middle of one
+    let reason = format!("x{}", "é".repeat(2048));
+
+    let client_fut = async {
+        let (mut driver, _client) = client::new(pair.client().await).await.expect("client init");
+        let err = future::poll_fn(|cx| driver.poll_close(cx))
+            .await
+            .unwrap_err();
+        let expected: Box<str> = reason[..1023].into();
+        assert_eq!(
+            err.close_origin(),
+            Some(&CloseOrigin::Peer {
+                code: Code::H3_INTERNAL_ERROR,
+                reason: Some(expected),
+            })
+        );
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        incoming.close(Code::H3_INTERNAL_ERROR, &reason);
+        let err = incoming.accept().await.map(|_| ()).unwrap_err();
+        assert_matches!(
+            err.close_origin(),
+            Some(CloseOrigin::Local {
+                code: Code::H3_INTERNAL_ERROR,
+                reason: Some(r),
+            }) if r.len() == 1023
+        );
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn close_origin_local() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+        for _ in 0..=1 {
+            let mut control_stream = connection.open_uni().await.unwrap();
+            let mut buf = BytesMut::new();
+            StreamType::CONTROL.encode(&mut buf);
+            control_stream.write_all(&buf[..]).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        let err = incoming.accept().await.map(|_| ()).unwrap_err();
+        assert_matches!(
+            err.close_origin(),
+            Some(CloseOrigin::Local {
+                code: Code::H3_STREAM_CREATION_ERROR,
+                reason: Some(_),
+            })
+        );
+    };
+
+    tokio::select! { _ = server_fut => (), _ = client_fut => panic!("client resolved first") };
+}
+
 #[tokio::test]
 async fn graceful_shutdown_client() {
     init_tracing();
@@ -681,6 +1665,273 @@ async fn graceful_shutdown_client() {
     tokio::join!(server_fut, client_fut);
 }
 
+#[tokio::test]
+async fn graceful_shutdown_client_during_request() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+    let (goaway_sent, wait_goaway) = tokio::sync::oneshot::channel();
+
+    let client_fut = async {
+        let (mut driver, mut send_request) = client::new(pair.client().await).await.unwrap();
+        let mut request_stream = send_request
+            .send_request(Request::get("http://no.way").body(()).unwrap())
+            .await
+            .unwrap();
+        driver.shutdown(0).await.unwrap();
+        goaway_sent.send(()).unwrap();
+
+        let req_fut = async {
+            request_stream.finish().await.unwrap();
+            let response = request_stream.recv_response().await.unwrap();
+            assert_eq!(response.status(), StatusCode::IM_A_TEAPOT);
+        };
+        let drive_fut = future::poll_fn(|cx| driver.poll_close(cx));
+        let (_, closed) = tokio::join!(req_fut, drive_fut);
+        assert_matches!(closed, Ok(()));
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        let (_, stream) = incoming.accept().await.unwrap().unwrap();
+
+        let response_fut = async {
+            // Only respond once the client's GOAWAY is on its way
+            wait_goaway.await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            response(stream).await;
+        };
+        // Reads the GOAWAY while the request is in progress, then returns once
+        // the request is complete
+        let accept_fut = async { assert!(incoming.accept().await.unwrap().is_none()) };
+        tokio::join!(response_fut, accept_fut);
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn accept_with_shutdown_drains_requests() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+    let (shutdown_send, mut shutdown_recv) = tokio::sync::oneshot::channel::<()>();
+
+    let client_fut = async {
+        let (mut driver, mut send_request) = client::new(pair.client().await).await.unwrap();
+        let mut request_stream = send_request
+            .send_request(Request::get("http://no.way").body(()).unwrap())
+            .await
+            .unwrap();
+
+        let req_fut = async move {
+            request_stream.finish().await.unwrap();
+            let response = request_stream.recv_response().await.unwrap();
+            assert_eq!(response.status(), StatusCode::IM_A_TEAPOT);
+            // Let the connection close once the request is complete
+            drop(send_request);
+        };
+        let drive_fut = future::poll_fn(|cx| driver.poll_close(cx));
+        let (_, closed) = tokio::join!(req_fut, drive_fut);
+        assert_matches!(closed, Ok(()));
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        let (_, stream) = incoming
+            .accept_with_shutdown(&mut shutdown_recv)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let response_fut = async {
+            shutdown_send.send(()).unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            response(stream).await;
+        };
+        // The signal interrupts the wait for a new request, then the
+        // connection drains the request in flight
+        let accept_fut = async {
+            assert!(incoming
+                .accept_with_shutdown(&mut shutdown_recv)
+                .await
+                .unwrap()
+                .is_none());
+            assert!(incoming
+                .accept_with_shutdown(&mut shutdown_recv)
+                .await
+                .unwrap()
+                .is_none());
+        };
+        tokio::join!(response_fut, accept_fut);
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn goaway_on_accept_drop() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+    let (goaway_recv, wait_client) = tokio::sync::oneshot::channel();
+
+    let client_fut = async {
+        let (mut driver, mut send_request) = client::new(pair.client().await).await.unwrap();
+        let drive = future::poll_fn(|cx| driver.poll_close(cx));
+        let closing = async {
+            request(&mut send_request).await.unwrap();
+            future::poll_fn(|cx| send_request.poll_closing(cx)).await;
+        };
+        tokio::select! { _ = closing => (), res = drive => panic!("driver resolved first: {:?}", res) };
+        // The request accepted before the drop was processed
+        assert_eq!(driver.peer_goaway_id(), Some(StreamId::FIRST_REQUEST + 1));
+        goaway_recv.send(()).unwrap();
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .goaway_on_accept_drop(true)
+            .build(conn)
+            .await
+            .unwrap();
+        let (_, stream) = incoming.accept().await.unwrap().unwrap();
+        response(stream).await;
+        tokio::select! {
+            _ = incoming.accept() => panic!("accept resolved"),
+            _ = tokio::time::sleep(Duration::from_millis(50)) => (),
+        }
+        // The GOAWAY only arrives if the connection is not closed right away
+        wait_client.await.unwrap();
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn last_accepted_stream() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut send_request) = client::new(pair.client().await).await.unwrap();
+        let req_fut = async {
+            for _ in 0..3 {
+                request(&mut send_request).await.unwrap();
+            }
+        };
+        tokio::select! { _ = req_fut => (), _ = future::poll_fn(|cx| driver.poll_close(cx)) => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        assert_eq!(incoming.last_accepted_stream(), None);
+        let mut previous = None;
+        for _ in 0..3 {
+            let (_, stream) = incoming.accept().await.unwrap().unwrap();
+            let id = stream.id();
+            assert_eq!(incoming.last_accepted_stream(), Some(id));
+            assert!(previous.map_or(true, |p| p < id));
+            previous = Some(id);
+            response(stream).await;
+        }
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[test]
+fn quinn_error_mapping() {
+    use h3_quinn::quinn::{ConnectionError, ReadError, WriteError};
+
+    let closed = |code: Code| {
+        ConnectionError::ApplicationClosed(quinn_proto::ApplicationClose {
+            error_code: h3_quinn::VarInt::from_u64(code.value()).unwrap(),
+            reason: Bytes::from_static(b"bye"),
+        })
+    };
+
+    let err = Error::from(h3_quinn::ConnectionError::from(ConnectionError::TimedOut));
+    assert!(err.is_timeout());
+
+    let err = Error::from(h3_quinn::ConnectionError::from(closed(Code::H3_NO_ERROR)));
+    assert_matches!(err.kind(), Kind::Closed);
+
+    let err = Error::from(h3_quinn::ConnectionError::from(closed(
+        Code::H3_INTERNAL_ERROR,
+    )));
+    assert_eq!(err.try_get_code(), Some(Code::H3_INTERNAL_ERROR));
+    assert_eq!(err.get_error_level(), ErrorLevel::ConnectionError);
+
+    let err = Error::from(h3_quinn::ReadError::from(ReadError::ConnectionLost(
+        closed(Code::H3_INTERNAL_ERROR),
+    )));
+    assert_eq!(err.try_get_code(), Some(Code::H3_INTERNAL_ERROR));
+    assert_eq!(err.get_error_level(), ErrorLevel::ConnectionError);
+
+    let err = Error::from(h3_quinn::ReadError::from(ReadError::Reset(
+        h3_quinn::VarInt::from_u64(Code::H3_REQUEST_CANCELLED.value()).unwrap(),
+    )));
+    assert_eq!(err.try_get_code(), Some(Code::H3_REQUEST_CANCELLED));
+    assert_eq!(err.get_error_level(), ErrorLevel::StreamError);
+
+    let err = Error::from(h3_quinn::SendStreamError::from(WriteError::Stopped(
+        h3_quinn::VarInt::from_u64(Code::H3_REQUEST_REJECTED.value()).unwrap(),
+    )));
+    assert_eq!(err.try_get_code(), Some(Code::H3_REQUEST_REJECTED));
+    assert_eq!(err.get_error_level(), ErrorLevel::StreamError);
+
+    // A reset with a code that is not about the request alone
+    let err = Error::from(h3_quinn::ReadError::from(ReadError::Reset(
+        h3_quinn::VarInt::from_u64(Code::H3_FRAME_UNEXPECTED.value()).unwrap(),
+    )));
+    assert_eq!(err.try_get_code(), Some(Code::H3_FRAME_UNEXPECTED));
+    assert_eq!(err.get_error_level(), ErrorLevel::ConnectionError);
+}
+
+#[cfg(feature = "quinn")]
+#[test]
+fn quinn_error_direct_conversion() {
+    use h3_quinn::quinn::{ConnectionError, ReadError, WriteError};
+
+    let code = |code: Code| h3_quinn::VarInt::from_u64(code.value()).unwrap();
+
+    let err = Error::from(ConnectionError::TimedOut);
+    assert!(err.is_timeout());
+
+    let err = Error::from(ConnectionError::ApplicationClosed(
+        quinn_proto::ApplicationClose {
+            error_code: code(Code::H3_EXCESSIVE_LOAD),
+            reason: Bytes::from_static(b"busy"),
+        },
+    ));
+    assert_eq!(err.try_get_code(), Some(Code::H3_EXCESSIVE_LOAD));
+    assert_eq!(err.get_error_level(), ErrorLevel::ConnectionError);
+
+    let err = Error::from(ReadError::ConnectionLost(ConnectionError::TimedOut));
+    assert!(err.is_timeout());
+
+    let err = Error::from(ReadError::Reset(code(Code::H3_REQUEST_CANCELLED)));
+    assert_eq!(err.try_get_code(), Some(Code::H3_REQUEST_CANCELLED));
+    assert_eq!(err.get_error_level(), ErrorLevel::StreamError);
+
+    let err = Error::from(ReadError::Reset(code(Code::H3_INTERNAL_ERROR)));
+    assert_eq!(err.try_get_code(), Some(Code::H3_INTERNAL_ERROR));
+    assert_eq!(err.get_error_level(), ErrorLevel::ConnectionError);
+
+    let err = Error::from(WriteError::Stopped(code(Code::H3_REQUEST_INCOMPLETE)));
+    assert_eq!(err.try_get_code(), Some(Code::H3_REQUEST_INCOMPLETE));
+    assert_eq!(err.get_error_level(), ErrorLevel::StreamError);
+
+    let err = Error::from(WriteError::Stopped(code(Code::H3_NO_ERROR)));
+    assert_matches!(err.kind(), Kind::Closed);
+}
+
 async fn request<T, O, B>(mut send_request: T) -> Result<Response<()>, Error>
 where
     T: BorrowMut<SendRequest<O, B>>,
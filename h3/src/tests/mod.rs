@@ -12,13 +12,18 @@ mod connection;
 mod request;
 
 use std::{
+    collections::VecDeque,
     convert::TryInto,
-    net::{Ipv6Addr, ToSocketAddrs},
-    sync::Arc,
+    net::{Ipv6Addr, SocketAddr, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
     time::Duration,
 };
 
-use bytes::Bytes;
+use bytes::{Buf, Bytes};
 use rustls::{Certificate, PrivateKey};
 
 use crate::quic;
@@ -135,6 +140,143 @@ impl Server {
     }
 }
 
+/// Wraps a connection to alter or observe what h3 sees of it
+pub struct MockConnection<C> {
+    inner: C,
+    /// Certificates reported in `tls_info()`
+    pub peer_certificates: Option<Arc<[Bytes]>>,
+    /// Number of calls to `close()`
+    pub closes: Arc<AtomicUsize>,
+    /// Priorities set on the unidirectional streams h3 opened
+    pub priorities: Arc<Mutex<Vec<i32>>>,
+    /// Peer addresses reported by `poll_path_change()`, as if the
+    /// connection migrated
+    pub path_changes: Arc<Mutex<VecDeque<SocketAddr>>>,
+}
+
+impl<C> MockConnection<C> {
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            peer_certificates: None,
+            closes: Arc::new(AtomicUsize::new(0)),
+            priorities: Arc::new(Mutex::new(Vec::new())),
+            path_changes: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+}
+
+impl<C, B> quic::Connection<B> for MockConnection<C>
+where
+    C: quic::Connection<B>,
+    B: Buf,
+{
+    type BidiStream = C::BidiStream;
+    type SendStream = MockSendStream<C::SendStream>;
+    type RecvStream = C::RecvStream;
+    type OpenStreams = C::OpenStreams;
+    type Error = C::Error;
+
+    fn poll_accept_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<Self::RecvStream>, Self::Error>> {
+        self.inner.poll_accept_recv(cx)
+    }
+
+    fn poll_accept_bidi(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<Self::BidiStream>, Self::Error>> {
+        self.inner.poll_accept_bidi(cx)
+    }
+
+    fn poll_open_bidi(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::BidiStream, Self::Error>> {
+        self.inner.poll_open_bidi(cx)
+    }
+
+    fn poll_open_send(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::SendStream, Self::Error>> {
+        self.inner
+            .poll_open_send(cx)
+            .map_ok(|inner| MockSendStream {
+                inner,
+                priorities: self.priorities.clone(),
+            })
+    }
+
+    fn opener(&self) -> Self::OpenStreams {
+        self.inner.opener()
+    }
+
+    fn close(&mut self, code: crate::error::Code, reason: &[u8]) {
+        self.closes.fetch_add(1, Ordering::SeqCst);
+        self.inner.close(code, reason)
+    }
+
+    fn poll_path_change(&mut self, _cx: &mut Context<'_>) -> Poll<SocketAddr> {
+        match self.path_changes.lock().unwrap().pop_front() {
+            Some(addr) => Poll::Ready(addr),
+            None => Poll::Pending,
+        }
+    }
+
+    fn tls_info(&self) -> Option<quic::TlsInfo> {
+        Some(quic::TlsInfo {
+            peer_certificates: self.peer_certificates.clone(),
+            ..self.inner.tls_info().unwrap_or_default()
+        })
+    }
+}
+
+/// Send stream opened through a [`MockConnection`]
+pub struct MockSendStream<S> {
+    inner: S,
+    priorities: Arc<Mutex<Vec<i32>>>,
+}
+
+impl<S, B> quic::SendStream<B> for MockSendStream<S>
+where
+    S: quic::SendStream<B>,
+    B: Buf,
+{
+    type Error = S::Error;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn send_data<T: Into<quic::WriteBuf<B>>>(&mut self, data: T) -> Result<(), Self::Error> {
+        self.inner.send_data(data)
+    }
+
+    fn poll_finish(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_finish(cx)
+    }
+
+    fn reset(&mut self, reset_code: u64) {
+        self.inner.reset(reset_code)
+    }
+
+    fn id(&self) -> quic::StreamId {
+        self.inner.id()
+    }
+
+    fn set_priority(&mut self, priority: i32) {
+        self.priorities.lock().unwrap().push(priority);
+        self.inner.set_priority(priority)
+    }
+
+    fn poll_stopped(&mut self, cx: &mut Context<'_>) -> Poll<Result<u64, Self::Error>> {
+        self.inner.poll_stopped(cx)
+    }
+}
+
 pub fn build_certs() -> (Certificate, PrivateKey) {
     let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
     let key = PrivateKey(cert.serialize_private_key_der());
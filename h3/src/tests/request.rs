@@ -1,26 +1,32 @@
-use std::time::Duration;
+use std::{
+    sync::{Arc, Mutex},
+    task::Context,
+    time::Duration,
+};
 
 use assert_matches::assert_matches;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use futures_util::future;
-use http::{request, HeaderMap, Request, Response, StatusCode};
+use http::{request, HeaderMap, HeaderValue, Method, Request, Response, StatusCode};
 
 use crate::{
     client,
+    config::GreaseFrames,
     connection::ConnectionState,
-    error::{Code, Error, Kind},
+    error::{Code, Error, ErrorLevel, Kind, ReuniteError},
     proto::{
         coding::Encode,
-        frame::{self, Frame, FrameType},
+        frame::{self, Frame, FrameType, PayloadLen, Settings},
         headers::Header,
         push::PushId,
+        stream::{StreamId, StreamType},
         varint::VarInt,
     },
     qpack, server,
 };
 
 use super::h3_quinn;
-use super::{init_tracing, Pair};
+use super::{init_tracing, MockConnection, Pair};
 
 #[tokio::test]
 async fn get() {
@@ -75,7 +81,7 @@ async fn get() {
 }
 
 #[tokio::test]
-async fn get_with_trailers_unknown_content_type() {
+async fn get_streaming_unknown_length() {
     init_tracing();
     let mut pair = Pair::default();
     let mut server = pair.server();
@@ -88,20 +94,16 @@ async fn get_with_trailers_unknown_content_type() {
                 .send_request(Request::get("http://localhost/salut").body(()).unwrap())
                 .await
                 .expect("request");
-            request_stream.recv_response().await.expect("recv response");
-            request_stream
-                .recv_data()
-                .await
-                .expect("recv data")
-                .expect("body");
 
-            assert!(request_stream.recv_data().await.unwrap().is_none());
-            let trailers = request_stream
-                .recv_trailers()
-                .await
-                .expect("recv trailers")
-                .expect("trailers none");
-            assert_eq!(trailers.get("trailer").unwrap(), &"value");
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(response.headers().get("content-length").is_none());
+
+            let mut body = BytesMut::new();
+            while let Some(mut chunk) = request_stream.recv_data().await.expect("recv data") {
+                body.put(&mut chunk);
+            }
+            assert_eq!(&body[..], b"wonderful streamed hypertext");
         };
         tokio::select! { _ = req_fut => (), _ = drive_fut => () }
     };
@@ -110,7 +112,7 @@ async fn get_with_trailers_unknown_content_type() {
         let conn = server.next().await;
         let mut incoming_req = server::Connection::new(conn).await.unwrap();
 
-        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let (_request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
         request_stream
             .send_response(
                 Response::builder()
@@ -120,16 +122,12 @@ async fn get_with_trailers_unknown_content_type() {
             )
             .await
             .expect("send_response");
-        request_stream
-            .send_data("wonderful hypertext".into())
-            .await
-            .expect("send_data");
-        let mut trailers = HeaderMap::new();
-        trailers.insert("trailer", "value".parse().unwrap());
-        request_stream
-            .send_trailers(trailers)
-            .await
-            .expect("send_trailers");
+        for chunk in ["wonderful ", "streamed ", "hypertext"] {
+            request_stream
+                .send_data(chunk.into())
+                .await
+                .expect("send_data");
+        }
         request_stream.finish().await.expect("finish");
     };
 
@@ -137,32 +135,36 @@ async fn get_with_trailers_unknown_content_type() {
 }
 
 #[tokio::test]
-async fn get_with_trailers_known_content_type() {
+async fn send_data_received_before_finish() {
     init_tracing();
     let mut pair = Pair::default();
     let mut server = pair.server();
+    let (received, wait_received) = tokio::sync::oneshot::channel();
 
     let client_fut = async {
         let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
         let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
         let req_fut = async {
             let mut request_stream = client
-                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .send_request(Request::get("http://localhost/events").body(()).unwrap())
                 .await
                 .expect("request");
-            request_stream.recv_response().await.expect("recv response");
-            request_stream
+
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
+            let mut chunk = request_stream
                 .recv_data()
                 .await
                 .expect("recv data")
-                .expect("body");
+                .expect("first event");
+            assert_eq!(chunk.copy_to_bytes(chunk.remaining()), "data: 1\n\n");
+            received.send(()).unwrap();
 
-            let trailers = request_stream
-                .recv_trailers()
+            assert!(request_stream
+                .recv_data()
                 .await
-                .expect("recv trailers")
-                .expect("trailers none");
-            assert_eq!(trailers.get("trailer").unwrap(), &"value");
+                .expect("recv data")
+                .is_none());
         };
         tokio::select! { _ = req_fut => (), _ = drive_fut => () }
     };
@@ -173,25 +175,74 @@ async fn get_with_trailers_known_content_type() {
 
         let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
         request_stream
-            .send_response(
-                Response::builder()
-                    .status(200)
-                    .body(())
-                    .expect("build response"),
-            )
+            .send_response(Response::builder().status(200).body(()).unwrap())
             .await
             .expect("send_response");
         request_stream
-            .send_data("wonderful hypertext".into())
+            .send_data("data: 1\n\n".into())
             .await
             .expect("send_data");
+        // The client only lets the response end once it got the first event
+        wait_received.await.unwrap();
+        request_stream.finish().await.expect("finish");
+    };
 
-        let mut trailers = HeaderMap::new();
-        trailers.insert("trailer", "value".parse().unwrap());
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn send_data_small_events_not_coalesced() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+    let (received, mut wait_received) = tokio::sync::mpsc::unbounded_channel();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/events").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.recv_response().await.expect("recv response");
+
+            for i in 0..5 {
+                let mut chunk = request_stream
+                    .recv_data()
+                    .await
+                    .expect("recv data")
+                    .expect("event");
+                let event = chunk.copy_to_bytes(chunk.remaining());
+                assert_eq!(event, format!("data: {}\n\n", i));
+                received.send(()).unwrap();
+            }
+            assert!(request_stream
+                .recv_data()
+                .await
+                .expect("recv data")
+                .is_none());
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
         request_stream
-            .send_trailers(trailers)
+            .send_response(Response::builder().status(200).body(()).unwrap())
             .await
-            .expect("send_trailers");
+            .expect("send_response");
+        for i in 0..5 {
+            request_stream
+                .send_data(Bytes::from(format!("data: {}\n\n", i)))
+                .await
+                .expect("send_data");
+            // Each event must reach the client on its own
+            wait_received.recv().await.unwrap();
+        }
         request_stream.finish().await.expect("finish");
     };
 
@@ -199,27 +250,39 @@ async fn get_with_trailers_known_content_type() {
 }
 
 #[tokio::test]
-async fn post() {
+async fn connect_tunnel() {
     init_tracing();
     let mut pair = Pair::default();
     let mut server = pair.server();
+    let payload: Vec<u8> = (0..=255u8).collect();
 
     let client_fut = async {
         let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
         let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
         let req_fut = async {
             let mut request_stream = client
-                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .send_request(
+                    Request::connect("https://example.com:443")
+                        .body(())
+                        .unwrap(),
+                )
                 .await
                 .expect("request");
 
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
+
             request_stream
-                .send_data("wonderful json".into())
+                .send_data(Bytes::from(payload.clone()))
                 .await
                 .expect("send_data");
             request_stream.finish().await.expect("client finish");
 
-            request_stream.recv_response().await.expect("recv response");
+            let mut echoed = BytesMut::new();
+            while let Some(mut chunk) = request_stream.recv_data().await.expect("recv data") {
+                echoed.put(&mut chunk);
+            }
+            assert_eq!(&echoed[..], &payload[..]);
         };
         tokio::select! { _ = req_fut => (), _ = drive_fut => () }
     };
@@ -228,139 +291,140 @@ async fn post() {
         let conn = server.next().await;
         let mut incoming_req = server::Connection::new(conn).await.unwrap();
 
-        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let (request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        assert_eq!(request.method(), http::Method::CONNECT);
         request_stream
-            .send_response(
-                Response::builder()
-                    .status(200)
-                    .body(())
-                    .expect("build response"),
-            )
+            .send_response(Response::builder().status(200).body(()).unwrap())
             .await
             .expect("send_response");
 
-        let request_body = request_stream
-            .recv_data()
-            .await
-            .expect("recv data")
-            .expect("server recv body");
-        assert_eq!(request_body.chunk(), b"wonderful json");
-        request_stream.finish().await.expect("client finish");
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.4
+        //= type=test
+        //# Once the CONNECT method has completed, only DATA frames are permitted
+        //# to be sent on the stream.
+        while let Some(mut chunk) = request_stream.recv_data().await.expect("recv data") {
+            let chunk = chunk.copy_to_bytes(chunk.remaining());
+            request_stream.send_data(chunk).await.expect("send_data");
+        }
+        request_stream.finish().await.expect("finish");
     };
 
     tokio::join!(server_fut, client_fut);
 }
 
 #[tokio::test]
-async fn header_too_big_response_from_server() {
+async fn split_stream_id() {
     init_tracing();
     let mut pair = Pair::default();
-    let mut server = pair.server();
+    let server = pair.server();
+    let (sent_id, wait_id) = tokio::sync::oneshot::channel();
 
     let client_fut = async {
-        // Do not poll driver so client doesn't know about server's max_field section size setting
         let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
         let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
         let req_fut = async {
-            let mut request_stream = client
+            let request_stream = client
                 .send_request(Request::get("http://localhost/salut").body(()).unwrap())
                 .await
                 .expect("request");
-            request_stream.finish().await.expect("client finish");
-            let response = request_stream.recv_response().await.unwrap();
-            assert_eq!(
-                response.status(),
-                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
-            );
+            let id = request_stream.id();
+            assert!(id.is_request());
+            sent_id.send(id).unwrap();
+
+            let (mut send, mut recv) = request_stream.split();
+            assert_eq!(send.id(), id);
+            assert_eq!(recv.id(), id);
+            send.finish().await.expect("client finish");
+            recv.recv_response().await.expect("recv response");
         };
-        tokio::select! {biased; _ = req_fut => (), _ = drive_fut => () }
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
     };
 
     let server_fut = async {
-        let conn = server.next().await;
-        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
-        //= type=test
-        //# An HTTP/3 implementation MAY impose a limit on the maximum size of
-        //# the message header it will accept on an individual HTTP message.
-        let mut incoming_req = server::builder()
-            .max_field_section_size(12)
-            .build(conn)
+        // Spelled out so the stream type is known to be splittable
+        let conn =
+            h3_quinn::Connection::new(server.endpoint.accept().await.unwrap().await.unwrap());
+        let mut incoming_req = server::Connection::<_, Bytes>::new(conn).await.unwrap();
+
+        let (_, request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let id = request_stream.id();
+        assert_eq!(id, wait_id.await.unwrap());
+
+        let (mut send, recv) = request_stream.split();
+        assert_eq!(send.id(), id);
+        assert_eq!(recv.id(), id);
+        send.send_response(Response::builder().status(200).body(()).unwrap())
             .await
-            .unwrap();
-
-        let err_kind = incoming_req.accept().await.map(|_| ()).unwrap_err().kind();
-        assert_matches!(
-            err_kind,
-            Kind::HeaderTooBig {
-                actual_size: 42,
-                max_size: 12,
-                ..
-            }
-        );
-        let _ = incoming_req.accept().await;
+            .expect("send_response");
+        send.finish().await.expect("finish");
     };
 
     tokio::join!(server_fut, client_fut);
 }
 
 #[tokio::test]
-async fn header_too_big_response_from_server_trailers() {
+async fn split_unsplit() {
     init_tracing();
     let mut pair = Pair::default();
-    let mut server = pair.server();
+    let server = pair.server();
 
     let client_fut = async {
-        // Do not poll driver so client doesn't know about server's max_field_section_size setting
         let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
         let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
         let req_fut = async {
-            let mut request_stream = client
-                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+            let request_stream = client
+                .send_request(Request::post("http://localhost/salut").body(()).unwrap())
                 .await
                 .expect("request");
+            let (send, recv) = request_stream.split();
+            let mut request_stream =
+                client::RequestStream::<h3_quinn::BidiStream<Bytes>, _>::unsplit(send, recv)
+                    .expect("unsplit");
             request_stream
-                .send_data("wonderful json".into())
+                .send_data(Bytes::from("hello"))
                 .await
                 .expect("send_data");
-
-            let mut trailers = HeaderMap::new();
-            trailers.insert("trailer", "A".repeat(200).parse().unwrap());
-            request_stream
-                .send_trailers(trailers)
-                .await
-                .expect("send trailers");
             request_stream.finish().await.expect("client finish");
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
+            let mut body = request_stream
+                .recv_data()
+                .await
+                .expect("recv data")
+                .unwrap();
+            assert_eq!(body.copy_to_bytes(body.remaining()), "world");
         };
-        tokio::select! {biased; _ = req_fut => (), _ = drive_fut => () }
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
     };
 
     let server_fut = async {
-        let conn = server.next().await;
-        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
-        //= type=test
-        //# An HTTP/3 implementation MAY impose a limit on the maximum size of
-        //# the message header it will accept on an individual HTTP message.
-        let mut incoming_req = server::builder()
-            .max_field_section_size(207)
-            .build(conn)
-            .await
-            .unwrap();
-
-        let (_request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
-        let _ = request_stream
+        let conn =
+            h3_quinn::Connection::new(server.endpoint.accept().await.unwrap().await.unwrap());
+        let mut incoming_req = server::Connection::<_, Bytes>::new(conn).await.unwrap();
+
+        let (_, request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let id = request_stream.id();
+        let (send, recv) = request_stream.split();
+        let mut request_stream =
+            server::RequestStream::<h3_quinn::BidiStream<Bytes>, _>::unsplit(send, recv)
+                .expect("unsplit");
+        assert_eq!(request_stream.id(), id);
+
+        let mut body = request_stream
             .recv_data()
             .await
             .expect("recv data")
-            .expect("body");
-        let err_kind = request_stream.recv_trailers().await.unwrap_err().kind();
-        assert_matches!(
-            err_kind,
-            Kind::HeaderTooBig {
-                actual_size: 239,
-                max_size: 207,
-                ..
-            }
-        );
+            .unwrap();
+        assert_eq!(body.copy_to_bytes(body.remaining()), "hello");
+        request_stream
+            .send_response(Response::builder().status(200).body(()).unwrap())
+            .await
+            .expect("send_response");
+        request_stream
+            .send_data(Bytes::from("world"))
+            .await
+            .expect("send_data");
+        request_stream.finish().await.expect("finish");
         let _ = incoming_req.accept().await;
     };
 
@@ -368,168 +432,156 @@ async fn header_too_big_response_from_server_trailers() {
 }
 
 #[tokio::test]
-async fn header_too_big_client_error() {
+async fn unsplit_different_streams() {
     init_tracing();
     let mut pair = Pair::default();
-    let mut server = pair.server();
+    let server = pair.server();
 
     let client_fut = async {
         let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
         let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
         let req_fut = async {
-            // pretend client already received server's settings
-            client
-                .shared_state()
-                .write("client")
-                .peer_max_field_section_size = 12;
-
-            let req = Request::get("http://localhost/salut").body(()).unwrap();
-            let err_kind = client
-                .send_request(req)
+            let first = client
+                .send_request(Request::get("http://localhost/first").body(()).unwrap())
                 .await
-                .map(|_| ())
-                .unwrap_err()
-                .kind();
-            assert_matches!(
-                err_kind,
-                Kind::HeaderTooBig {
-                    actual_size: 179,
-                    max_size: 12,
-                    ..
-                }
-            );
+                .expect("request");
+            let second = client
+                .send_request(Request::get("http://localhost/second").body(()).unwrap())
+                .await
+                .expect("request");
+            let (first_id, second_id) = (first.id(), second.id());
+            let (first_send, first_recv) = first.split();
+            let (second_send, second_recv) = second.split();
+
+            // Both halves are given back, and can still be joined with their own
+            let ReuniteError(first_send, second_recv) =
+                match client::RequestStream::<h3_quinn::BidiStream<Bytes>, _>::unsplit(
+                    first_send,
+                    second_recv,
+                ) {
+                    Ok(_) => panic!("halves of different streams joined"),
+                    Err(e) => e,
+                };
+            assert_eq!(first_send.id(), first_id);
+            assert_eq!(second_recv.id(), second_id);
+            let first = client::RequestStream::<h3_quinn::BidiStream<Bytes>, _>::unsplit(
+                first_send, first_recv,
+            )
+            .expect("unsplit");
+            assert_eq!(first.id(), first_id);
+            let second = client::RequestStream::<h3_quinn::BidiStream<Bytes>, _>::unsplit(
+                second_send,
+                second_recv,
+            )
+            .expect("unsplit");
+            assert_eq!(second.id(), second_id);
         };
-        tokio::select! {biased; _ = req_fut => (),_ = drive_fut => () }
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
     };
 
     let server_fut = async {
-        let conn = server.next().await;
-        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
-        //= type=test
-        //# An HTTP/3 implementation MAY impose a limit on the maximum size of
-        //# the message header it will accept on an individual HTTP message.
-        server::builder()
-            .max_field_section_size(12)
-            .build(conn)
-            .await
-            .unwrap();
+        let conn =
+            h3_quinn::Connection::new(server.endpoint.accept().await.unwrap().await.unwrap());
+        let _ = server::Connection::<_, Bytes>::new(conn).await.unwrap();
     };
 
     tokio::join!(server_fut, client_fut);
 }
 
 #[tokio::test]
-async fn header_too_big_client_error_trailer() {
+async fn poll_send_recv_data() {
     init_tracing();
     let mut pair = Pair::default();
     let mut server = pair.server();
+    let (polled, wait_polled) = tokio::sync::oneshot::channel();
 
     let client_fut = async {
-        // Do not poll driver so client doesn't know about server's max_field_section_size setting
         let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
         let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
         let req_fut = async {
-            client
-                .shared_state()
-                .write("client")
-                .peer_max_field_section_size = 200;
-
             let mut request_stream = client
-                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .send_request(Request::post("http://localhost/salut").body(()).unwrap())
                 .await
                 .expect("request");
+            wait_polled.await.unwrap();
+
             request_stream
-                .send_data("wonderful json".into())
+                .start_send_data("ping".into())
+                .expect("start_send_data");
+            // The frame is still being written
+            assert!(request_stream.start_send_data("ping".into()).is_err());
+            future::poll_fn(|cx| request_stream.poll_ready(cx))
                 .await
-                .expect("send_data");
-
-            let mut trailers = HeaderMap::new();
-            trailers.insert("trailer", "A".repeat(200).parse().unwrap());
+                .expect("poll_ready");
+            request_stream.finish().await.expect("client finish");
 
-            let err_kind = request_stream
-                .send_trailers(trailers)
+            request_stream.recv_response().await.expect("recv response");
+            let mut chunk = future::poll_fn(|cx| request_stream.poll_recv_data(cx))
                 .await
-                .unwrap_err()
-                .kind();
-            assert_matches!(
-                err_kind,
-                Kind::HeaderTooBig {
-                    actual_size: 239,
-                    max_size: 200,
-                    ..
-                }
-            );
-
-            request_stream.finish().await.expect("client finish");
+                .expect("recv data")
+                .expect("body");
+            assert_eq!(chunk.copy_to_bytes(chunk.remaining()), "pong");
         };
-        tokio::select! {biased; _ = req_fut => (), _ = drive_fut => () }
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
     };
 
     let server_fut = async {
         let conn = server.next().await;
-        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
-        //= type=test
-        //# An HTTP/3 implementation MAY impose a limit on the maximum size of
-        //# the message header it will accept on an individual HTTP message.
-        let mut incoming_req = server::builder()
-            .max_field_section_size(207)
-            .build(conn)
-            .await
-            .unwrap();
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
 
-        let (_request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
-        let _ = request_stream
-            .recv_data()
+        // Nothing has been sent yet: give up on this poll, then resume
+        let mut cx = Context::from_waker(futures_util::task::noop_waker_ref());
+        assert!(request_stream.poll_recv_data(&mut cx).is_pending());
+        polled.send(()).unwrap();
+        let mut chunk = future::poll_fn(|cx| request_stream.poll_recv_data(cx))
             .await
             .expect("recv data")
             .expect("body");
-        let _ = incoming_req.accept().await;
+        assert_eq!(chunk.copy_to_bytes(chunk.remaining()), "ping");
+
+        request_stream
+            .send_response(Response::builder().status(200).body(()).unwrap())
+            .await
+            .expect("send_response");
+        request_stream
+            .start_send_data("pong".into())
+            .expect("start_send_data");
+        future::poll_fn(|cx| request_stream.poll_ready(cx))
+            .await
+            .expect("poll_ready");
+        request_stream.finish().await.expect("finish");
     };
 
     tokio::join!(server_fut, client_fut);
 }
 
 #[tokio::test]
-async fn header_too_big_discard_from_client() {
+async fn get_empty_data_frame() {
     init_tracing();
     let mut pair = Pair::default();
     let mut server = pair.server();
 
     let client_fut = async {
-        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
-        //= type=test
-        //# An implementation that
-        //# has received this parameter SHOULD NOT send an HTTP message header
-        //# that exceeds the indicated size, as the peer will likely refuse to
-        //# process it.
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
 
-        // Do not poll driver so client doesn't know about server's max_field section size setting
-        let (_conn, mut client) = client::builder()
-            .max_field_section_size(12)
-            .build::<_, _, Bytes>(pair.client().await)
-            .await
-            .expect("client init");
-        let mut request_stream = client
-            .send_request(Request::get("http://localhost/salut").body(()).unwrap())
-            .await
-            .expect("request");
-        request_stream.finish().await.expect("client finish");
-        let err_kind = request_stream.recv_response().await.unwrap_err().kind();
-        assert_matches!(
-            err_kind,
-            Kind::HeaderTooBig {
-                actual_size: 42,
-                max_size: 12,
-                ..
-            }
-        );
+            request_stream.recv_response().await.expect("recv response");
 
-        let mut request_stream = client
-            .send_request(Request::get("http://localhost/salut").body(()).unwrap())
-            .await
-            .expect("request");
-        request_stream.finish().await.expect("client finish");
-        let _ = request_stream.recv_response().await.unwrap_err();
+            // Empty DATA frames neither end the body nor show up as empty chunks
+            let mut body = BytesMut::new();
+            while let Some(mut chunk) = request_stream.recv_data().await.expect("recv data") {
+                assert!(chunk.has_remaining());
+                body.put(&mut chunk);
+            }
+            assert_eq!(&body[..], b"wonderful");
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
     };
 
     let server_fut = async {
@@ -537,213 +589,264 @@ async fn header_too_big_discard_from_client() {
         let mut incoming_req = server::Connection::new(conn).await.unwrap();
 
         let (_request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
-        // pretend server didn't receive settings
-        incoming_req
-            .shared_state()
-            .write("client")
-            .peer_max_field_section_size = u64::MAX;
         request_stream
-            .send_response(
-                Response::builder()
-                    .status(200)
-                    .body(())
-                    .expect("build response"),
-            )
+            .send_response(Response::builder().status(200).body(()).unwrap())
             .await
             .expect("send_response");
-
-        // Keep sending: wait for the stream to be cancelled by the client
-        let mut err = None;
-        for _ in 0..100 {
-            if let Err(e) = request_stream.send_data("some data".into()).await {
-                err = Some(e);
-                break;
-            }
-            tokio::time::sleep(Duration::from_millis(2)).await;
+        for chunk in ["", "wonder", "", "", "ful", ""] {
+            request_stream
+                .send_data(chunk.into())
+                .await
+                .expect("send_data");
         }
-        assert_matches!(
-            err.as_ref().unwrap().kind(),
-            Kind::Application {
-                code: Code::H3_REQUEST_CANCELLED,
-                ..
-            }
-        );
-        let _ = incoming_req.accept().await;
+        request_stream.finish().await.expect("finish");
     };
 
     tokio::join!(server_fut, client_fut);
 }
 
 #[tokio::test]
-async fn header_too_big_discard_from_client_trailers() {
+async fn get_too_many_empty_data_frames() {
     init_tracing();
     let mut pair = Pair::default();
     let mut server = pair.server();
 
     let client_fut = async {
-        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
-        //= type=test
-        //# An implementation that
-        //# has received this parameter SHOULD NOT send an HTTP message header
-        //# that exceeds the indicated size, as the peer will likely refuse to
-        //# process it.
-
-        // Do not poll driver so client doesn't know about server's max_field section size setting
         let (mut driver, mut client) = client::builder()
-            .max_field_section_size(200)
+            .max_empty_data_frames(2)
             .build::<_, _, Bytes>(pair.client().await)
             .await
             .expect("client init");
         let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
         let req_fut = async {
+            // Empty DATA frames are counted on each response
             let mut request_stream = client
                 .send_request(Request::get("http://localhost/salut").body(()).unwrap())
                 .await
                 .expect("request");
             request_stream.recv_response().await.expect("recv response");
-            request_stream.recv_data().await.expect("recv data");
+            assert!(request_stream
+                .recv_data()
+                .await
+                .expect("recv data")
+                .is_none());
 
-            let err_kind = request_stream.recv_trailers().await.unwrap_err().kind();
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.recv_response().await.expect("recv response");
+            let err = request_stream.recv_data().await.map(|_| ()).unwrap_err();
             assert_matches!(
-                err_kind,
-                Kind::HeaderTooBig {
-                    actual_size: 539,
-                    max_size: 200,
+                err.kind(),
+                Kind::Application {
+                    code: Code::H3_EXCESSIVE_LOAD,
                     ..
                 }
             );
-            request_stream.finish().await.expect("client finish");
         };
-        tokio::select! {biased; _ = req_fut => (), _ = drive_fut => () }
+        let (driver_res, _) = tokio::join!(drive_fut, req_fut);
+        assert_matches!(
+            driver_res.unwrap_err().kind(),
+            Kind::Application {
+                code: Code::H3_EXCESSIVE_LOAD,
+                ..
+            }
+        );
     };
 
     let server_fut = async {
         let conn = server.next().await;
         let mut incoming_req = server::Connection::new(conn).await.unwrap();
 
-        let (_request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
-
-        // pretend server didn't receive settings
-        incoming_req
-            .shared_state()
-            .write("server")
-            .peer_max_field_section_size = u64::MAX;
-
-        request_stream
-            .send_response(
-                Response::builder()
-                    .status(200)
-                    .body(())
-                    .expect("build response"),
-            )
-            .await
-            .expect("send_response");
-
-        request_stream
-            .send_data("wonderful hypertext".into())
-            .await
-            .expect("send_data");
-
-        let mut trailers = HeaderMap::new();
-        trailers.insert("trailer", "value".repeat(100).parse().unwrap());
-        request_stream
-            .send_trailers(trailers)
-            .await
-            .expect("send_trailers");
-        request_stream.finish().await.expect("finish");
+        for empty_frames in [2, 3] {
+            let (_request, mut request_stream) =
+                incoming_req.accept().await.expect("accept").unwrap();
+            request_stream
+                .send_response(Response::builder().status(200).body(()).unwrap())
+                .await
+                .expect("send_response");
+            for _ in 0..empty_frames {
+                request_stream
+                    .send_data(Bytes::new())
+                    .await
+                    .expect("send_data");
+            }
+            request_stream.finish().await.expect("finish");
+        }
 
-        let _ = incoming_req.accept().await;
+        // The client closes the whole connection, not only the request
+        assert_matches!(
+            incoming_req.accept().await.map(|_| ()).unwrap_err().kind(),
+            Kind::Application {
+                code: Code::H3_EXCESSIVE_LOAD,
+                ..
+            }
+        );
     };
 
     tokio::join!(server_fut, client_fut);
 }
 
 #[tokio::test]
-async fn header_too_big_server_error() {
+async fn post_too_many_empty_data_frames() {
     init_tracing();
     let mut pair = Pair::default();
     let mut server = pair.server();
 
     let client_fut = async {
-        let (mut driver, mut client) = client::new(pair.client().await) // header size limit faked for brevity
-            .await
-            .expect("client init");
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
         let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
         let req_fut = async {
-            let req = Request::get("http://localhost/salut").body(()).unwrap();
-            let _ = client
-                .send_request(req)
-                .await
-                .unwrap()
-                .recv_response()
-                .await;
+            for empty_frames in [2, 3] {
+                let mut request_stream = client
+                    .send_request(Request::post("http://localhost/salut").body(()).unwrap())
+                    .await
+                    .expect("request");
+                for _ in 0..empty_frames {
+                    request_stream
+                        .send_data(Bytes::new())
+                        .await
+                        .expect("send_data");
+                }
+                request_stream.finish().await.expect("finish");
+            }
+            future::pending::<()>().await;
         };
-        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+        tokio::select! { _ = req_fut => (), res = drive_fut => assert_matches!(
+            res.unwrap_err().kind(),
+            Kind::Application {
+                code: Code::H3_EXCESSIVE_LOAD,
+                ..
+            }
+        ) }
     };
 
     let server_fut = async {
         let conn = server.next().await;
-        let mut incoming_req = server::Connection::new(conn).await.unwrap();
-
-        let (_request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
-
-        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
-        //= type=test
-        //# An implementation that
-        //# has received this parameter SHOULD NOT send an HTTP message header
-        //# that exceeds the indicated size, as the peer will likely refuse to
-        //# process it.
-
-        // pretend the server received a smaller max_field_section_size
-        incoming_req
-            .shared_state()
-            .write("server")
-            .peer_max_field_section_size = 12;
+        let mut incoming_req = server::builder()
+            .max_empty_data_frames(2)
+            .build(conn)
+            .await
+            .unwrap();
 
-        let err_kind = request_stream
-            .send_response(
-                Response::builder()
-                    .status(200)
-                    .body(())
-                    .expect("build response"),
-            )
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        assert!(request_stream
+            .recv_data()
             .await
-            .map(|_| ())
-            .unwrap_err()
-            .kind();
+            .expect("recv data")
+            .is_none());
 
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let err = request_stream.recv_data().await.map(|_| ()).unwrap_err();
         assert_matches!(
-            err_kind,
-            Kind::HeaderTooBig {
-                actual_size: 42,
-                max_size: 12,
+            err.kind(),
+            Kind::Application {
+                code: Code::H3_EXCESSIVE_LOAD,
                 ..
             }
         );
-    };
 
-    tokio::join!(server_fut, client_fut);
-}
-
-#[tokio::test]
-async fn header_too_big_server_error_trailers() {
+        // Driving the connection closes it
+        assert_matches!(
+            incoming_req.accept().await.map(|_| ()).unwrap_err().kind(),
+            Kind::Application {
+                code: Code::H3_EXCESSIVE_LOAD,
+                ..
+            }
+        );
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn empty_data_frames_counted_per_stream() {
     init_tracing();
     let mut pair = Pair::default();
     let mut server = pair.server();
 
     let client_fut = async {
-        let (mut driver, mut client) = client::new(pair.client().await) // header size limit faked for brevity
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            // Concurrent requests, each with a single empty DATA frame
+            let mut request_streams = Vec::new();
+            for _ in 0..4 {
+                let mut request_stream = client
+                    .send_request(Request::post("http://localhost/salut").body(()).unwrap())
+                    .await
+                    .expect("request");
+                request_stream
+                    .send_data(Bytes::new())
+                    .await
+                    .expect("send_data");
+                request_stream.finish().await.expect("finish");
+                request_streams.push(request_stream);
+            }
+            for mut request_stream in request_streams {
+                let response = request_stream.recv_response().await.expect("recv response");
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::builder()
+            .max_empty_data_frames(1)
+            .build(conn)
+            .await
+            .unwrap();
+
+        let mut request_streams = Vec::new();
+        for _ in 0..4 {
+            let (_, request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+            request_streams.push(request_stream);
+        }
+        for mut request_stream in request_streams {
+            while request_stream
+                .recv_data()
+                .await
+                .expect("recv data")
+                .is_some()
+            {}
+            request_stream
+                .send_response(Response::new(()))
+                .await
+                .expect("send_response");
+            request_stream.finish().await.expect("finish");
+        }
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn get_origin_form_default_authority() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::builder()
+            .default_authority("example.com:4433")
+            .default_scheme("http")
+            .build::<_, _, Bytes>(pair.client().await)
             .await
             .expect("client init");
         let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
         let req_fut = async {
-            let req = Request::get("http://localhost/salut").body(()).unwrap();
-            let _ = client
-                .send_request(req)
-                .await
-                .unwrap()
-                .recv_response()
-                .await;
+            for uri in ["/salut?x=1", "https://localhost/absolute"] {
+                let mut request_stream = client
+                    .send_request(Request::get(uri).body(()).unwrap())
+                    .await
+                    .expect("request");
+                request_stream.finish().await.expect("client finish");
+                let response = request_stream.recv_response().await.expect("recv response");
+                assert_eq!(response.status(), StatusCode::OK);
+            }
         };
         tokio::select! { _ = req_fut => (), _ = drive_fut => () }
     };
@@ -752,216 +855,2757 @@ async fn header_too_big_server_error_trailers() {
         let conn = server.next().await;
         let mut incoming_req = server::Connection::new(conn).await.unwrap();
 
-        let (_request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
-        request_stream
-            .send_response(
-                Response::builder()
-                    .status(200)
-                    .body(())
-                    .expect("build response"),
-            )
-            .await
-            .unwrap();
-        request_stream
-            .send_data("wonderful hypertext".into())
-            .await
-            .expect("send_data");
+        for expected in [
+            "http://example.com:4433/salut?x=1",
+            "https://localhost/absolute",
+        ] {
+            let (request, mut request_stream) =
+                incoming_req.accept().await.expect("accept").unwrap();
+            assert_eq!(request.uri(), expected);
+            request_stream
+                .send_response(
+                    Response::builder()
+                        .status(200)
+                        .body(())
+                        .expect("build response"),
+                )
+                .await
+                .expect("send_response");
+            request_stream.finish().await.expect("server finish");
+        }
+    };
 
-        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
-        //= type=test
-        //# An implementation that
-        //# has received this parameter SHOULD NOT send an HTTP message header
-        //# that exceeds the indicated size, as the peer will likely refuse to
-        //# process it.
+    tokio::join!(server_fut, client_fut);
+}
 
-        // pretend the server already received client's settings
-        incoming_req
-            .shared_state()
-            .write("write")
-            .peer_max_field_section_size = 200;
+#[tokio::test]
+async fn get_origin_form_without_default_authority() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let _server = pair.server();
+
+    let (_driver, mut client) = client::new(pair.client().await).await.expect("client init");
+    let err = client
+        .send_request(Request::get("/salut").body(()).unwrap())
+        .await
+        .map(|_| ())
+        .unwrap_err();
+    assert_matches!(
+        err.kind(),
+        Kind::Application {
+            code: Code::H3_MESSAGE_ERROR,
+            ..
+        }
+    );
+}
 
-        let mut trailers = HeaderMap::new();
-        trailers.insert("trailer", "value".repeat(100).parse().unwrap());
-        let err_kind = request_stream
-            .send_trailers(trailers)
-            .await
-            .unwrap_err()
-            .kind();
-        assert_matches!(
-            err_kind,
-            Kind::HeaderTooBig {
-                actual_size: 539,
-                max_size: 200,
-                ..
+#[tokio::test]
+async fn invalid_default_authority() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let _server = pair.server();
+
+    let res = client::builder()
+        .default_authority("not an authority")
+        .build::<_, _, Bytes>(pair.client().await)
+        .await;
+    assert!(res.is_err());
+}
+
+#[tokio::test]
+async fn peer_identity() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            for _ in 0..2 {
+                let mut request_stream = client
+                    .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                    .await
+                    .expect("request");
+                request_stream.finish().await.expect("client finish");
+                request_stream.recv_response().await.expect("recv response");
             }
-        );
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let mut conn = MockConnection::new(server.next().await);
+        conn.peer_certificates = Some(vec![Bytes::from_static(b"fake certificate")].into());
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        for _ in 0..2 {
+            let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+            assert_eq!(
+                request_stream.peer_identity(),
+                Some(&[Bytes::from_static(b"fake certificate")][..])
+            );
+            request_stream
+                .send_response(
+                    Response::builder()
+                        .status(200)
+                        .body(())
+                        .expect("build response"),
+                )
+                .await
+                .expect("send_response");
+            request_stream.finish().await.expect("server finish");
+        }
     };
 
     tokio::join!(server_fut, client_fut);
 }
 
 #[tokio::test]
-async fn get_timeout_client_recv_response() {
+async fn response_sent() {
     init_tracing();
     let mut pair = Pair::default();
-    pair.with_timeout(Duration::from_millis(100));
     let mut server = pair.server();
 
     let client_fut = async {
-        let (mut conn, mut client) = client::new(pair.client().await).await.expect("client init");
-        let request_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
             let mut request_stream = client
                 .send_request(Request::get("http://localhost/salut").body(()).unwrap())
                 .await
                 .expect("request");
+            request_stream.finish().await.expect("client finish");
 
-            let response = request_stream.recv_response().await;
-            assert_matches!(response.unwrap_err().kind(), Kind::Timeout);
-        };
-
-        let drive_fut = async move {
-            let result = future::poll_fn(|cx| conn.poll_close(cx)).await;
-            assert_matches!(result.unwrap_err().kind(), Kind::Timeout);
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
         };
-
-        tokio::join!(drive_fut, request_fut);
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
     };
 
     let server_fut = async {
         let conn = server.next().await;
         let mut incoming_req = server::Connection::new(conn).await.unwrap();
 
-        // _req must not be dropped, else the connection will be closed and the timeout
-        // wont be triggered
-        let _req = incoming_req.accept().await.expect("accept").unwrap();
-        tokio::time::sleep(Duration::from_millis(500)).await;
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        assert!(!request_stream.response_sent());
+        request_stream
+            .send_response(Response::new(()))
+            .await
+            .expect("send_response");
+        assert!(request_stream.response_sent());
+        request_stream.finish().await.expect("server finish");
     };
 
     tokio::join!(server_fut, client_fut);
 }
 
 #[tokio::test]
-async fn get_timeout_client_recv_data() {
+async fn send_response_connection_specific_field() {
     init_tracing();
     let mut pair = Pair::default();
-    pair.with_timeout(Duration::from_millis(200));
     let mut server = pair.server();
 
     let client_fut = async {
-        let (mut conn, mut client) = client::new(pair.client().await).await.expect("client init");
-        let request_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
             let mut request_stream = client
                 .send_request(Request::get("http://localhost/salut").body(()).unwrap())
                 .await
                 .expect("request");
+            request_stream.finish().await.expect("client finish");
 
-            let _ = request_stream.recv_response().await.unwrap();
-            let data = request_stream.recv_data().await;
-            assert_matches!(data.map(|_| ()).unwrap_err().kind(), Kind::Timeout);
-        };
-
-        let drive_fut = async move {
-            let result = future::poll_fn(|cx| conn.poll_close(cx)).await;
-            assert_matches!(result.unwrap_err().kind(), Kind::Timeout);
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(response.headers().get("connection").is_none());
         };
-
-        tokio::join!(drive_fut, request_fut);
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
     };
 
     let server_fut = async {
         let conn = server.next().await;
         let mut incoming_req = server::Connection::new(conn).await.unwrap();
 
-        let (_request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
-        request_stream
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let err = request_stream
             .send_response(
                 Response::builder()
-                    .status(200)
+                    .header("connection", "close")
                     .body(())
-                    .expect("build response"),
+                    .unwrap(),
             )
             .await
-            .expect("send_response");
-        tokio::time::sleep(Duration::from_millis(500)).await;
+            .unwrap_err();
+        assert_matches!(
+            err.kind(),
+            Kind::Application {
+                code: Code::H3_MESSAGE_ERROR,
+                ..
+            }
+        );
+        assert!(!request_stream.response_sent());
+
+        request_stream
+            .respond_and_finish(Response::new(()))
+            .await
+            .expect("respond and finish");
     };
 
     tokio::join!(server_fut, client_fut);
 }
 
 #[tokio::test]
-async fn get_timeout_server_accept() {
+async fn send_response_raw() {
     init_tracing();
     let mut pair = Pair::default();
-    pair.with_timeout(Duration::from_millis(200));
     let mut server = pair.server();
 
     let client_fut = async {
-        let (mut conn, _client) = client::new(pair.client().await).await.expect("client init");
-        let request_fut = async {
-            tokio::time::sleep(Duration::from_millis(500)).await;
-        };
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
 
-        let drive_fut = async move {
-            let result = future::poll_fn(|cx| conn.poll_close(cx)).await;
-            assert_matches!(result.unwrap_err().kind(), Kind::Timeout);
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["cache-control"], "max-age=3600");
+            assert!(request_stream
+                .recv_data()
+                .await
+                .expect("recv data")
+                .is_none());
         };
-
-        tokio::join!(drive_fut, request_fut);
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
     };
 
     let server_fut = async {
         let conn = server.next().await;
         let mut incoming_req = server::Connection::new(conn).await.unwrap();
 
+        let mut headers = HeaderMap::new();
+        headers.insert("cache-control", "max-age=3600".parse().unwrap());
+        let mut block = BytesMut::new();
+        qpack::encode_stateless(
+            &mut block,
+            Header::response(StatusCode::OK, headers).unwrap(),
+        )
+        .unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        // Required Insert Count of 1
+        let err = request_stream
+            .send_response_raw(Bytes::from_static(&[0x02, 0x00]))
+            .await
+            .unwrap_err();
         assert_matches!(
-            incoming_req.accept().await.map(|_| ()).unwrap_err().kind(),
-            Kind::Timeout
+            err.kind(),
+            Kind::Application {
+                code: Code::H3_INTERNAL_ERROR,
+                ..
+            }
         );
+        assert!(!request_stream.response_sent());
+
+        let encoded_size = block.len() as u64;
+        request_stream
+            .send_response_raw(block.freeze())
+            .await
+            .expect("send_response_raw");
+        assert!(request_stream.response_sent());
+        assert_eq!(request_stream.stats().headers_encoded_sent, encoded_size);
+        assert_eq!(request_stream.stats().headers_size_sent, 0);
+        assert_eq!(incoming_req.stats().headers_frames_sent, 1);
+        request_stream.finish().await.expect("finish");
     };
 
     tokio::join!(server_fut, client_fut);
 }
 
 #[tokio::test]
-async fn post_timeout_server_recv_data() {
+async fn send_response_cached() {
     init_tracing();
     let mut pair = Pair::default();
-    pair.with_timeout(Duration::from_millis(100));
     let mut server = pair.server();
 
     let client_fut = async {
-        let (_conn, mut client) = client::new(pair.client().await).await.expect("client init");
-        let _request_stream = client
-            .send_request(Request::post("http://localhost/salut").body(()).unwrap())
-            .await
-            .expect("request");
-        tokio::time::sleep(Duration::from_millis(500)).await;
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            for (status, tag) in [(200, "a"), (404, "a"), (200, "a"), (200, "b")] {
+                let mut request_stream = client
+                    .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                    .await
+                    .expect("request");
+                request_stream.finish().await.expect("client finish");
+
+                let response = request_stream.recv_response().await.expect("recv response");
+                assert_eq!(response.status(), status);
+                assert_eq!(response.headers()["etag"], tag);
+            }
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
     };
 
     let server_fut = async {
         let conn = server.next().await;
-        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+        let mut incoming_req = server::builder()
+            .response_cache_size(2)
+            .build(conn)
+            .await
+            .unwrap();
+
+        for (status, tag) in [(200, "a"), (404, "a"), (200, "a"), (200, "b")] {
+            let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+            let response = Response::builder()
+                .status(status)
+                .header("etag", tag)
+                .body(())
+                .unwrap();
+            request_stream
+                .send_response(response)
+                .await
+                .expect("send_response");
+            request_stream.finish().await.expect("finish");
+        }
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn capture_raw_headers() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let request = Request::get("http://localhost/salut")
+                .header("x-trace", "forensics")
+                .body(())
+                .unwrap();
+            let mut request_stream = client.send_request(request).await.expect("request");
+            request_stream.finish().await.expect("client finish");
+            request_stream.recv_response().await.expect("recv response");
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::builder()
+            .capture_raw_headers(true)
+            .build(conn)
+            .await
+            .unwrap();
+
+        let (request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let raw = request
+            .extensions()
+            .get::<server::RawHeaders>()
+            .expect("raw headers");
+        let mut block = raw.as_bytes().clone();
+        let fields = qpack::decode_stateless(&mut block, u64::MAX, usize::MAX)
+            .unwrap()
+            .fields;
+        assert!(fields
+            .iter()
+            .any(|f| &f.name[..] == b"x-trace" && &f.value[..] == b"forensics"));
+        request_stream
+            .send_response(Response::new(()))
+            .await
+            .expect("send_response");
+        request_stream.finish().await.expect("finish");
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn respond_and_finish_rejects_request() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/private").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(response.headers()["www-authenticate"], "Basic");
+            assert!(request_stream.recv_data().await.unwrap().is_none());
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let unauthorized = || {
+            Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .header("www-authenticate", "Basic")
+                .body(())
+                .unwrap()
+        };
+        // An informational response would leave the request without a final one
+        let err = request_stream
+            .respond_and_finish(
+                Response::builder()
+                    .status(StatusCode::CONTINUE)
+                    .body(())
+                    .unwrap(),
+            )
+            .await
+            .unwrap_err();
+        assert_matches!(
+            err.kind(),
+            Kind::Application {
+                code: Code::H3_INTERNAL_ERROR,
+                ..
+            }
+        );
+
+        request_stream
+            .respond_and_finish(unauthorized())
+            .await
+            .expect("respond and finish");
+
+        let err = request_stream
+            .respond_and_finish(unauthorized())
+            .await
+            .unwrap_err();
+        assert_matches!(
+            err.kind(),
+            Kind::Application {
+                code: Code::H3_INTERNAL_ERROR,
+                ..
+            }
+        );
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn send_complete() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
+            let mut body = request_stream
+                .recv_data()
+                .await
+                .expect("recv data")
+                .expect("body");
+            assert_eq!(body.copy_to_bytes(body.remaining()), "hello");
+            assert!(request_stream.recv_data().await.unwrap().is_none());
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+
+        let no_content = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Bytes::from("body"))
+            .unwrap();
+        assert!(request_stream.send_complete(no_content).await.is_err());
+
+        request_stream
+            .send_complete(Response::new(Bytes::from("hello")))
+            .await
+            .expect("send complete");
+        assert!(request_stream
+            .send_complete(Response::new(Bytes::new()))
+            .await
+            .is_err());
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn send_data_before_response() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+
+            let response = request_stream.recv_response().await.expect("recv 100");
+            assert_eq!(response.status(), StatusCode::CONTINUE);
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
+            let mut body = request_stream
+                .recv_data()
+                .await
+                .expect("recv data")
+                .expect("body");
+            assert_eq!(body.copy_to_bytes(body.remaining()), "hello");
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+
+        let err = request_stream
+            .send_data(Bytes::from("early"))
+            .await
+            .unwrap_err();
+        assert!(err.is_response_not_sent());
+        assert_matches!(err.kind(), Kind::ResponseNotSent);
+        assert!(request_stream
+            .start_send_data(Bytes::from("early"))
+            .unwrap_err()
+            .is_response_not_sent());
+
+        // An informational response does not allow a body either
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(StatusCode::CONTINUE)
+                    .body(())
+                    .unwrap(),
+            )
+            .await
+            .expect("send informational");
+        assert!(request_stream
+            .send_data(Bytes::from("early"))
+            .await
+            .unwrap_err()
+            .is_response_not_sent());
+
+        request_stream
+            .send_response(Response::new(()))
+            .await
+            .expect("send response");
+        request_stream
+            .send_data(Bytes::from("hello"))
+            .await
+            .expect("send data");
+        request_stream.finish().await.expect("server finish");
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn drop_unfinished_response_resets() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+    let (received, wait_received) = tokio::sync::oneshot::channel();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+
+            request_stream.recv_response().await.expect("recv response");
+            received.send(()).unwrap();
+
+            //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1.1
+            //= type=test
+            //# Implementations SHOULD cancel requests by abruptly terminating any
+            //# directions of a stream that are still open.
+            let err = loop {
+                match request_stream.recv_data().await {
+                    Ok(Some(_)) => continue,
+                    Ok(None) => panic!("response finished"),
+                    Err(e) => break e,
+                }
+            };
+            assert_matches!(
+                err.kind(),
+                Kind::Application {
+                    code: Code::H3_REQUEST_CANCELLED,
+                    ..
+                }
+            );
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream
+            .send_response(Response::new(()))
+            .await
+            .expect("send_response");
+        wait_received.await.unwrap();
+        request_stream
+            .send_data(Bytes::from("partial"))
+            .await
+            .expect("send_data");
+        drop(request_stream);
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn drop_unfinished_request_resets() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+    let (accepted, wait_accepted) = tokio::sync::oneshot::channel();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::post("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            wait_accepted.await.unwrap();
+            request_stream
+                .send_data(Bytes::from("partial"))
+                .await
+                .expect("send_data");
+            drop(request_stream);
+            future::pending::<()>().await;
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        accepted.send(()).unwrap();
+        let err = loop {
+            match request_stream.recv_data().await {
+                Ok(Some(_)) => continue,
+                Ok(None) => panic!("request finished"),
+                Err(e) => break e,
+            }
+        };
+        assert_matches!(
+            err.kind(),
+            Kind::Application {
+                code: Code::H3_REQUEST_CANCELLED,
+                ..
+            }
+        );
+    };
+
+    tokio::select! { _ = server_fut => (), _ = client_fut => panic!("client resolved first") };
+}
+
+#[tokio::test]
+async fn on_stream_reset_hook() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+    let (accepted, wait_accepted) = tokio::sync::oneshot::channel();
+    let resets = Arc::new(Mutex::new(Vec::new()));
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let request_stream = client
+                .send_request(Request::post("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            wait_accepted.await.unwrap();
+            drop(request_stream);
+            future::pending::<()>().await;
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let hook_resets = resets.clone();
+        let mut incoming_req = server::builder()
+            .on_stream_reset(move |id, code| hook_resets.lock().unwrap().push((id, code)))
+            .build(conn)
+            .await
+            .unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        accepted.send(()).unwrap();
+        while request_stream.recv_data().await.is_ok() {}
+
+        assert_eq!(
+            *resets.lock().unwrap(),
+            [(StreamId::FIRST_REQUEST, Code::H3_REQUEST_CANCELLED)]
+        );
+    };
+
+    tokio::select! { _ = server_fut => (), _ = client_fut => panic!("client resolved first") };
+}
+
+#[tokio::test]
+async fn cancellation_on_client_reset() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+    let (accepted, wait_accepted) = tokio::sync::oneshot::channel();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::post("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            wait_accepted.await.unwrap();
+            request_stream.stop_sending(Code::H3_REQUEST_CANCELLED);
+            drop(request_stream);
+            future::pending::<()>().await;
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        accepted.send(()).unwrap();
+
+        let cancellation = request_stream.cancellation();
+        // Neither reading nor writing, as a handler busy elsewhere
+        let code = tokio::time::timeout(Duration::from_secs(2), request_stream.stopped())
+            .await
+            .expect("cancellation timed out")
+            .expect("cancellation");
+        assert_eq!(code, Code::H3_REQUEST_CANCELLED);
+        assert_eq!(cancellation.await, Code::H3_REQUEST_CANCELLED);
+    };
+
+    tokio::select! { _ = server_fut => (), _ = client_fut => panic!("client resolved first") };
+}
+
+#[tokio::test]
+async fn cancellation_races_send_data() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+    let (responded, wait_response) = tokio::sync::oneshot::channel();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+            request_stream.recv_response().await.expect("recv response");
+            wait_response.await.unwrap();
+            request_stream.stop_sending(Code::H3_REQUEST_CANCELLED);
+            drop(request_stream);
+            future::pending::<()>().await;
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream
+            .send_response(Response::builder().status(200).body(()).unwrap())
+            .await
+            .expect("send_response");
+
+        let cancellation = request_stream.cancellation();
+        let watcher = tokio::spawn(cancellation.clone());
+        responded.send(()).unwrap();
+
+        // The body never ends, only the cancellation can stop the handler
+        let send = async {
+            while request_stream
+                .send_data(Bytes::from(vec![0; 16 * 1024]))
+                .await
+                .is_ok()
+            {}
+            future::pending::<()>().await;
+        };
+        let code = tokio::time::timeout(Duration::from_secs(2), async {
+            tokio::select! { code = cancellation => code, _ = send => unreachable!() }
+        })
+        .await
+        .expect("cancellation timed out");
+        assert_eq!(code, Code::H3_REQUEST_CANCELLED);
+        assert_eq!(watcher.await.unwrap(), Code::H3_REQUEST_CANCELLED);
+    };
+
+    tokio::select! { _ = server_fut => (), _ = client_fut => panic!("client resolved first") };
+}
+
+#[tokio::test]
+async fn request_reset_before_headers_skipped() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+
+        // Only the type of a HEADERS frame, then cancel
+        let (mut req_send, _req_recv) = connection.open_bi().await.unwrap();
+        req_send.write_all(&[0x01]).await.unwrap();
+        req_send
+            .reset(quinn::VarInt::from_u64(Code::H3_REQUEST_CANCELLED.value()).unwrap())
+            .unwrap();
+
+        let (mut req_send, _req_recv) = connection.open_bi().await.unwrap();
+        let mut buf = BytesMut::new();
+        request_encode(
+            &mut buf,
+            Request::get("http://localhost/salut").body(()).unwrap(),
+        );
+        req_send.write_all(&buf[..]).await.unwrap();
+        req_send.finish().await.unwrap();
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        let (request, _) = incoming.accept().await.expect("accept").unwrap();
+        assert_eq!(request.uri().path(), "/salut");
+        assert_eq!(incoming.stats().requests, 2);
+    };
+
+    tokio::select! { _ = server_fut => (), _ = client_fut => panic!("client resolved first") };
+}
+
+#[tokio::test]
+async fn too_many_early_resets() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+
+        for _ in 0..3 {
+            let (mut req_send, _req_recv) = connection.open_bi().await.unwrap();
+            req_send.write_all(&[0x01]).await.unwrap();
+            req_send
+                .reset(quinn::VarInt::from_u64(Code::H3_REQUEST_CANCELLED.value()).unwrap())
+                .unwrap();
+        }
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .max_early_resets(2)
+            .build(conn)
+            .await
+            .unwrap();
+        assert_matches!(
+            incoming.accept().await.map(|_| ()).unwrap_err().kind(),
+            Kind::Application {
+                code: Code::H3_EXCESSIVE_LOAD,
+                ..
+            }
+        );
+    };
+
+    tokio::select! { _ = server_fut => (), _ = client_fut => panic!("client resolved first") };
+}
+
+#[tokio::test]
+async fn early_resets_outside_window() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+
+        for _ in 0..3 {
+            let (mut req_send, _req_recv) = connection.open_bi().await.unwrap();
+            req_send.write_all(&[0x01]).await.unwrap();
+            req_send
+                .reset(quinn::VarInt::from_u64(Code::H3_REQUEST_CANCELLED.value()).unwrap())
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+
+        let (mut req_send, _req_recv) = connection.open_bi().await.unwrap();
+        let mut buf = BytesMut::new();
+        request_encode(
+            &mut buf,
+            Request::get("http://localhost/salut").body(()).unwrap(),
+        );
+        req_send.write_all(&buf[..]).await.unwrap();
+        req_send.finish().await.unwrap();
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .max_early_resets(1)
+            .early_reset_window(Duration::from_millis(100))
+            .build(conn)
+            .await
+            .unwrap();
+        let (request, _) = incoming.accept().await.expect("accept").unwrap();
+        assert_eq!(request.uri().path(), "/salut");
+    };
+
+    tokio::select! { _ = server_fut => (), _ = client_fut => panic!("client resolved first") };
+}
+
+#[tokio::test]
+async fn get_with_trailers_unknown_content_type() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.recv_response().await.expect("recv response");
+            request_stream
+                .recv_data()
+                .await
+                .expect("recv data")
+                .expect("body");
+
+            assert!(request_stream.recv_data().await.unwrap().is_none());
+            let trailers = request_stream
+                .recv_trailers()
+                .await
+                .expect("recv trailers")
+                .expect("trailers none");
+            assert_eq!(trailers.get("trailer").unwrap(), &"value");
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(200)
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .expect("send_response");
+        request_stream
+            .send_data("wonderful hypertext".into())
+            .await
+            .expect("send_data");
+        let mut trailers = HeaderMap::new();
+        trailers.insert("trailer", "value".parse().unwrap());
+        request_stream
+            .send_trailers(trailers)
+            .await
+            .expect("send_trailers");
+        request_stream.finish().await.expect("finish");
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn get_with_trailers_known_content_type() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.recv_response().await.expect("recv response");
+            request_stream
+                .recv_data()
+                .await
+                .expect("recv data")
+                .expect("body");
+
+            let trailers = request_stream
+                .recv_trailers()
+                .await
+                .expect("recv trailers")
+                .expect("trailers none");
+            assert_eq!(trailers.get("trailer").unwrap(), &"value");
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(200)
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .expect("send_response");
+        request_stream
+            .send_data("wonderful hypertext".into())
+            .await
+            .expect("send_data");
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("trailer", "value".parse().unwrap());
+        request_stream
+            .send_trailers(trailers)
+            .await
+            .expect("send_trailers");
+        request_stream.finish().await.expect("finish");
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn trailers_only_response_finishes_stream() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::post("http://localhost/grpc").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("finish");
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(request_stream
+                .recv_data()
+                .await
+                .expect("recv data")
+                .is_none());
+
+            let trailers = request_stream
+                .recv_trailers()
+                .await
+                .expect("recv trailers")
+                .expect("trailers none");
+            assert_eq!(trailers.get("grpc-status").unwrap(), &"13");
+            assert_eq!(trailers.get("grpc-message").unwrap(), &"internal");
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(200)
+                    .header("content-type", "application/grpc")
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .expect("send_response");
+
+        // No body and no call to finish(), as gRPC does on an error
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", "13".parse().unwrap());
+        trailers.insert("grpc-message", "internal".parse().unwrap());
+        request_stream
+            .send_trailers(trailers)
+            .await
+            .expect("send_trailers");
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn enforce_declared_trailers() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("finish");
+            request_stream.recv_response().await.expect("recv response");
+            assert!(request_stream
+                .recv_data()
+                .await
+                .expect("recv data")
+                .is_none());
+
+            let trailers = request_stream
+                .recv_trailers()
+                .await
+                .expect("recv trailers")
+                .expect("trailers none");
+            assert_eq!(trailers.get("grpc-status").unwrap(), &"0");
+            assert!(trailers.get("undeclared").is_none());
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::builder()
+            .enforce_declared_trailers(true)
+            .build(conn)
+            .await
+            .unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(200)
+                    .header("trailer", "Grpc-Status, grpc-message")
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .expect("send_response");
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", "0".parse().unwrap());
+        trailers.insert("undeclared", "value".parse().unwrap());
+        assert!(request_stream
+            .send_trailers(trailers.clone())
+            .await
+            .is_err());
+
+        trailers.remove("undeclared");
+        request_stream
+            .send_trailers(trailers)
+            .await
+            .expect("send_trailers");
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn default_response_headers() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            for expected_server in ["h3", "custom"] {
+                let mut request_stream = client
+                    .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                    .await
+                    .expect("request");
+                request_stream.finish().await.expect("finish");
+                let response = request_stream.recv_response().await.expect("recv response");
+                assert_eq!(response.headers().get("server").unwrap(), expected_server);
+                assert_eq!(response.headers().get_all("server").iter().count(), 1);
+                assert_eq!(response.headers().get("alt-svc").unwrap(), "h3=\":443\"");
+            }
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut defaults = HeaderMap::new();
+        defaults.insert("server", "h3".parse().unwrap());
+        defaults.insert("alt-svc", "h3=\":443\"".parse().unwrap());
+        let mut incoming_req = server::builder()
+            .default_response_headers(defaults)
+            .build(conn)
+            .await
+            .unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream
+            .send_response(Response::builder().status(200).body(()).unwrap())
+            .await
+            .expect("send_response");
+        request_stream.finish().await.expect("finish");
+
+        // The response's own fields take precedence over the defaults
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(200)
+                    .header("server", "custom")
+                    .body(())
+                    .unwrap(),
+            )
+            .await
+            .expect("send_response");
+        request_stream.finish().await.expect("finish");
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn post() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+
+            request_stream
+                .send_data("wonderful json".into())
+                .await
+                .expect("send_data");
+            request_stream.finish().await.expect("client finish");
+
+            request_stream.recv_response().await.expect("recv response");
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(200)
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .expect("send_response");
+
+        let request_body = request_stream
+            .recv_data()
+            .await
+            .expect("recv data")
+            .expect("server recv body");
+        assert_eq!(request_body.chunk(), b"wonderful json");
+        request_stream.finish().await.expect("client finish");
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn post_peek_frames() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::post("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream
+                .send_data("wonderful json".into())
+                .await
+                .expect("send_data");
+            request_stream.finish().await.expect("client finish");
+            request_stream.recv_response().await.expect("recv response");
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        // Peeking does not consume the header
+        for _ in 0..2 {
+            assert_matches!(request_stream.peek_frame().await, Ok(Some((0x0, 14))));
+        }
+        let request_body = request_stream
+            .recv_data()
+            .await
+            .expect("recv data")
+            .expect("server recv body");
+        assert_eq!(request_body.chunk(), b"wonderful json");
+        // The grease frame the client sends before finishing is reported too
+        let (ty, _) = request_stream.peek_frame().await.unwrap().unwrap();
+        assert_eq!((ty - 0x21) % 0x1f, 0);
+        assert!(request_stream.recv_data().await.unwrap().is_none());
+        assert_matches!(request_stream.peek_frame().await, Ok(None));
+
+        request_stream
+            .send_response(Response::builder().status(200).body(()).unwrap())
+            .await
+            .expect("send_response");
+        request_stream.finish().await.expect("server finish");
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn post_drain_body() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::post("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+
+            for _ in 0..256 {
+                request_stream
+                    .send_data(Bytes::from(vec![0x42; 4096]))
+                    .await
+                    .expect("send_data");
+            }
+            let mut trailers = HeaderMap::new();
+            trailers.insert("trailer", "value".parse().unwrap());
+            request_stream
+                .send_trailers(trailers)
+                .await
+                .expect("send_trailers");
+            request_stream.finish().await.expect("client finish");
+
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream.drain_body().await.expect("drain body");
+        assert!(request_stream.recv_data().await.unwrap().is_none());
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .expect("send_response");
+        request_stream.finish().await.expect("server finish");
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn stream_stats() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::post("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            for len in [10, 20, 30] {
+                request_stream
+                    .send_data(Bytes::from(vec![0x42; len]))
+                    .await
+                    .expect("send_data");
+            }
+            request_stream.finish().await.expect("client finish");
+
+            request_stream.recv_response().await.expect("recv response");
+            while request_stream
+                .recv_data()
+                .await
+                .expect("recv data")
+                .is_some()
+            {}
+
+            let stats = request_stream.stats();
+            assert_eq!(stats.bytes_sent, 60);
+            assert_eq!(stats.data_frames_sent, 3);
+            assert_eq!(stats.bytes_recv, 5);
+            assert_eq!(stats.data_frames_recv, 1);
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        while request_stream
+            .recv_data()
+            .await
+            .expect("recv data")
+            .is_some()
+        {}
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(200)
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .expect("send_response");
+        request_stream
+            .send_data(Bytes::from("hello"))
+            .await
+            .expect("send_data");
+
+        let stats = request_stream.stats();
+        assert_eq!(stats.bytes_recv, 60);
+        assert_eq!(stats.data_frames_recv, 3);
+        assert_eq!(stats.bytes_sent, 5);
+        assert_eq!(stats.data_frames_sent, 1);
+        request_stream.finish().await.expect("server finish");
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn headers_size_stats() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let mut headers = HeaderMap::new();
+    headers.insert("server", HeaderValue::from_static("h3-test"));
+    headers.insert("content-type", HeaderValue::from_static("text/plain"));
+    let mut trailers = HeaderMap::new();
+    trailers.insert("x-checksum", HeaderValue::from_static("c0ffee"));
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+            let stats = request_stream.stats();
+            assert!(stats.headers_encoded_sent > 0);
+            assert!(stats.headers_size_sent > stats.headers_encoded_sent);
+
+            request_stream.recv_response().await.expect("recv response");
+            request_stream.recv_trailers().await.expect("recv trailers");
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let mut response = Response::new(());
+        *response.headers_mut() = headers.clone();
+        request_stream
+            .send_response(response)
+            .await
+            .expect("send_response");
+        request_stream
+            .send_trailers(trailers.clone())
+            .await
+            .expect("send_trailers");
+
+        let mut block = BytesMut::new();
+        let response_size = qpack::encode_stateless(
+            &mut block,
+            Header::response(StatusCode::OK, headers.clone()).unwrap(),
+        )
+        .unwrap();
+        let trailers_size =
+            qpack::encode_stateless(&mut block, Header::trailer(trailers.clone()).unwrap())
+                .unwrap();
+
+        let stats = request_stream.stats();
+        assert_eq!(stats.headers_encoded_sent, block.len() as u64);
+        assert_eq!(stats.headers_size_sent, response_size + trailers_size);
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn post_body_too_big() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::post("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+
+            // The server stops reading the body, so sending may fail
+            for _ in 0..3 {
+                let _ = request_stream
+                    .send_data(Bytes::from(vec![0x42; 1000]))
+                    .await;
+            }
+            let _ = request_stream.finish().await;
+
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream.limit_body(1500);
+
+        let mut received = 0;
+        let err = loop {
+            match request_stream.recv_data().await {
+                Ok(Some(data)) => received += data.remaining(),
+                Ok(None) => panic!("body fully received"),
+                Err(e) => break e,
+            }
+        };
+        assert!(err.is_body_too_big());
+        assert_matches!(err.get_error_level(), ErrorLevel::StreamError);
+        assert!(received <= 1500);
+
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(StatusCode::PAYLOAD_TOO_LARGE)
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .expect("send_response");
+        request_stream.finish().await.expect("server finish");
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+/// Send two requests in a row, and tell which responses end with a grease frame
+async fn responses_with_grease(grease_frames: GreaseFrames) -> Vec<bool> {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+        let mut greased = Vec::new();
+        for _ in 0..2 {
+            let (mut req_send, mut req_recv) = connection.open_bi().await.unwrap();
+            let mut buf = BytesMut::new();
+            request_encode(
+                &mut buf,
+                Request::get("http://localhost/salut").body(()).unwrap(),
+            );
+            req_send.write_all(&buf).await.unwrap();
+            req_send.finish().await.unwrap();
+
+            let response = req_recv.read_to_end(1024).await.unwrap();
+            greased.push(response.windows(6).any(|w| w == b"grease"));
+        }
+        greased
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .grease_frames(grease_frames)
+            .build(conn)
+            .await
+            .unwrap();
+        for _ in 0..2 {
+            let (_, mut request_stream) = incoming.accept().await.expect("accept").unwrap();
+            request_stream
+                .send_response(Response::builder().status(200).body(()).unwrap())
+                .await
+                .expect("send_response");
+            request_stream.finish().await.expect("server finish");
+        }
+        incoming
+    };
+
+    let (_incoming, greased) = tokio::join!(server_fut, client_fut);
+    greased
+}
+
+#[tokio::test]
+async fn grease_frames() {
+    assert_eq!(
+        responses_with_grease(GreaseFrames::Never).await,
+        [false, false]
+    );
+    assert_eq!(
+        responses_with_grease(GreaseFrames::Once).await,
+        [true, false]
+    );
+    assert_eq!(
+        responses_with_grease(GreaseFrames::EveryRequest).await,
+        [true, true]
+    );
+}
+
+#[tokio::test]
+async fn poll_accept_resumes_pending_request() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+        let (mut req_send, mut req_recv) = connection.open_bi().await.unwrap();
+
+        let mut buf = BytesMut::new();
+        request_encode(
+            &mut buf,
+            Request::get("http://localhost/salut").body(()).unwrap(),
+        );
+        // Send the headers in two parts, so the server gets the stream first
+        let (first, second) = buf.split_at(2);
+        req_send.write_all(first).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        req_send.write_all(second).await.unwrap();
+        req_send.finish().await.unwrap();
+
+        let mut resp = [0; 64];
+        let _ = req_recv.read(&mut resp).await;
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+
+        // Cancel while the request stream is accepted but its headers are incomplete
+        tokio::select! {
+            res = future::poll_fn(|cx| incoming.poll_accept(cx)) => {
+                panic!("accept resolved: {:?}", res.map(|_| ()))
+            }
+            _ = tokio::time::sleep(Duration::from_millis(50)) => (),
+        };
+
+        let (request, mut request_stream) = future::poll_fn(|cx| incoming.poll_accept(cx))
+            .await
+            .expect("accept")
+            .expect("request");
+        assert_eq!(request.uri().path(), "/salut");
+
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(200)
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .expect("send_response");
+        request_stream.finish().await.expect("server finish");
+    };
+
+    tokio::select! { _ = server_fut => (), _ = client_fut => panic!("client resolved first") };
+}
+
+#[tokio::test]
+async fn header_too_big_response_from_server() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        // Do not poll driver so client doesn't know about server's max_field section size setting
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+            let response = request_stream.recv_response().await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+            );
+        };
+        tokio::select! {biased; _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
+        //= type=test
+        //# An HTTP/3 implementation MAY impose a limit on the maximum size of
+        //# the message header it will accept on an individual HTTP message.
+        let mut incoming_req = server::builder()
+            .max_field_section_size(12)
+            .build(conn)
+            .await
+            .unwrap();
+
+        let err_kind = incoming_req.accept().await.map(|_| ()).unwrap_err().kind();
+        assert_matches!(
+            err_kind,
+            Kind::HeaderTooBig {
+                actual_size: 42,
+                max_size: 12,
+                ..
+            }
+        );
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn too_many_header_fields() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request = Request::get("http://localhost/salut").body(()).unwrap();
+            for _ in 0..5000 {
+                request
+                    .headers_mut()
+                    .append("a", HeaderValue::from_static("b"));
+            }
+            let mut request_stream = client.send_request(request).await.expect("request");
+            request_stream.finish().await.expect("client finish");
+            let response = request_stream.recv_response().await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+            );
+        };
+        tokio::select! {biased; _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::builder()
+            .max_header_fields(100)
+            .build(conn)
+            .await
+            .unwrap();
+
+        let err = incoming_req.accept().await.map(|_| ()).unwrap_err();
+        assert_matches!(
+            err.kind(),
+            Kind::Application {
+                code: Code::H3_EXCESSIVE_LOAD,
+                level: ErrorLevel::StreamError,
+                ..
+            }
+        );
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn header_too_big_response_from_server_trailers() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        // Do not poll driver so client doesn't know about server's max_field_section_size setting
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream
+                .send_data("wonderful json".into())
+                .await
+                .expect("send_data");
+
+            let mut trailers = HeaderMap::new();
+            trailers.insert("trailer", "A".repeat(200).parse().unwrap());
+            request_stream
+                .send_trailers(trailers)
+                .await
+                .expect("send trailers");
+            request_stream.finish().await.expect("client finish");
+        };
+        tokio::select! {biased; _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
+        //= type=test
+        //# An HTTP/3 implementation MAY impose a limit on the maximum size of
+        //# the message header it will accept on an individual HTTP message.
+        let mut incoming_req = server::builder()
+            .max_field_section_size(207)
+            .build(conn)
+            .await
+            .unwrap();
+
+        let (_request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let _ = request_stream
+            .recv_data()
+            .await
+            .expect("recv data")
+            .expect("body");
+        let err_kind = request_stream.recv_trailers().await.unwrap_err().kind();
+        assert_matches!(
+            err_kind,
+            Kind::HeaderTooBig {
+                actual_size: 239,
+                max_size: 207,
+                ..
+            }
+        );
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn max_request_field_section_size() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        // Do not poll driver so client doesn't know about server's max_field_section_size setting
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            let mut trailers = HeaderMap::new();
+            trailers.insert("trailer", "A".repeat(200).parse().unwrap());
+            request_stream
+                .send_trailers(trailers)
+                .await
+                .expect("send trailers");
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.headers()["large"], "A".repeat(200));
+        };
+        tokio::select! {biased; _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::builder()
+            .max_request_field_section_size(207)
+            .build(conn)
+            .await
+            .unwrap();
+
+        let (_request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let err_kind = request_stream.recv_trailers().await.unwrap_err().kind();
+        assert_matches!(
+            err_kind,
+            Kind::HeaderTooBig {
+                actual_size: 239,
+                max_size: 207,
+                ..
+            }
+        );
+
+        // Responses are only limited by the client
+        let response = Response::builder()
+            .header("large", "A".repeat(200))
+            .body(())
+            .unwrap();
+        request_stream
+            .send_response(response)
+            .await
+            .expect("send response");
+        request_stream.finish().await.expect("server finish");
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn max_response_field_section_size() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request = Request::get("http://localhost/salut").body(()).unwrap();
+            request
+                .headers_mut()
+                .insert("large", "A".repeat(200).parse().unwrap());
+            let mut request_stream = client.send_request(request).await.expect("request");
+            request_stream.finish().await.expect("client finish");
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(request_stream.recv_data().await.unwrap().is_none());
+        };
+        tokio::select! {biased; _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::builder()
+            .max_response_field_section_size(100)
+            .build(conn)
+            .await
+            .unwrap();
+
+        // Requests are only limited by the default
+        let (request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        assert_eq!(request.headers()["large"], "A".repeat(200));
+
+        let response = Response::builder()
+            .header("large", "A".repeat(200))
+            .body(())
+            .unwrap();
+        let err_kind = request_stream
+            .send_response(response)
+            .await
+            .unwrap_err()
+            .kind();
+        assert_matches!(
+            err_kind,
+            Kind::HeaderTooBig {
+                actual_size: 279,
+                max_size: 100,
+                ..
+            }
+        );
+        request_stream
+            .send_response(Response::new(()))
+            .await
+            .expect("send response");
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("trailer", "A".repeat(200).parse().unwrap());
+        let err_kind = request_stream
+            .send_trailers(trailers)
+            .await
+            .unwrap_err()
+            .kind();
+        assert_matches!(
+            err_kind,
+            Kind::HeaderTooBig {
+                actual_size: 239,
+                max_size: 100,
+                ..
+            }
+        );
+        request_stream.finish().await.expect("server finish");
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn header_too_big_client_error() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            // pretend client already received server's settings
+            client
+                .shared_state()
+                .write("client")
+                .peer_max_field_section_size = 12;
+
+            let req = Request::get("http://localhost/salut").body(()).unwrap();
+            let err_kind = client
+                .send_request(req)
+                .await
+                .map(|_| ())
+                .unwrap_err()
+                .kind();
+            assert_matches!(
+                err_kind,
+                Kind::HeaderTooBig {
+                    actual_size: 179,
+                    max_size: 12,
+                    ..
+                }
+            );
+        };
+        tokio::select! {biased; _ = req_fut => (),_ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
+        //= type=test
+        //# An HTTP/3 implementation MAY impose a limit on the maximum size of
+        //# the message header it will accept on an individual HTTP message.
+        server::builder()
+            .max_field_section_size(12)
+            .build(conn)
+            .await
+            .unwrap();
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn header_too_big_client_error_trailer() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        // Do not poll driver so client doesn't know about server's max_field_section_size setting
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            client
+                .shared_state()
+                .write("client")
+                .peer_max_field_section_size = 200;
+
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream
+                .send_data("wonderful json".into())
+                .await
+                .expect("send_data");
+
+            let mut trailers = HeaderMap::new();
+            trailers.insert("trailer", "A".repeat(200).parse().unwrap());
+
+            let err_kind = request_stream
+                .send_trailers(trailers)
+                .await
+                .unwrap_err()
+                .kind();
+            assert_matches!(
+                err_kind,
+                Kind::HeaderTooBig {
+                    actual_size: 239,
+                    max_size: 200,
+                    ..
+                }
+            );
+
+            request_stream.finish().await.expect("client finish");
+        };
+        tokio::select! {biased; _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
+        //= type=test
+        //# An HTTP/3 implementation MAY impose a limit on the maximum size of
+        //# the message header it will accept on an individual HTTP message.
+        let mut incoming_req = server::builder()
+            .max_field_section_size(207)
+            .build(conn)
+            .await
+            .unwrap();
+
+        let (_request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let _ = request_stream
+            .recv_data()
+            .await
+            .expect("recv data")
+            .expect("body");
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn header_too_big_discard_from_client() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
+        //= type=test
+        //# An implementation that
+        //# has received this parameter SHOULD NOT send an HTTP message header
+        //# that exceeds the indicated size, as the peer will likely refuse to
+        //# process it.
+
+        // Do not poll driver so client doesn't know about server's max_field section size setting
+        let (_conn, mut client) = client::builder()
+            .max_field_section_size(12)
+            .build::<_, _, Bytes>(pair.client().await)
+            .await
+            .expect("client init");
+        let mut request_stream = client
+            .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+            .await
+            .expect("request");
+        request_stream.finish().await.expect("client finish");
+        let err_kind = request_stream.recv_response().await.unwrap_err().kind();
+        assert_matches!(
+            err_kind,
+            Kind::HeaderTooBig {
+                actual_size: 42,
+                max_size: 12,
+                ..
+            }
+        );
+
+        let mut request_stream = client
+            .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+            .await
+            .expect("request");
+        request_stream.finish().await.expect("client finish");
+        let _ = request_stream.recv_response().await.unwrap_err();
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        // pretend server didn't receive settings
+        incoming_req
+            .shared_state()
+            .write("client")
+            .peer_max_field_section_size = u64::MAX;
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(200)
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .expect("send_response");
+
+        // Keep sending: wait for the stream to be cancelled by the client
+        let mut err = None;
+        for _ in 0..100 {
+            if let Err(e) = request_stream.send_data("some data".into()).await {
+                err = Some(e);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        assert_matches!(
+            err.as_ref().unwrap().kind(),
+            Kind::Application {
+                code: Code::H3_REQUEST_CANCELLED,
+                ..
+            }
+        );
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn header_too_big_discard_from_client_trailers() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
+        //= type=test
+        //# An implementation that
+        //# has received this parameter SHOULD NOT send an HTTP message header
+        //# that exceeds the indicated size, as the peer will likely refuse to
+        //# process it.
+
+        // Do not poll driver so client doesn't know about server's max_field section size setting
+        let (mut driver, mut client) = client::builder()
+            .max_field_section_size(200)
+            .build::<_, _, Bytes>(pair.client().await)
+            .await
+            .expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.recv_response().await.expect("recv response");
+            request_stream.recv_data().await.expect("recv data");
+
+            let err_kind = request_stream.recv_trailers().await.unwrap_err().kind();
+            assert_matches!(
+                err_kind,
+                Kind::HeaderTooBig {
+                    actual_size: 539,
+                    max_size: 200,
+                    ..
+                }
+            );
+            request_stream.finish().await.expect("client finish");
+        };
+        tokio::select! {biased; _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+
+        // pretend server didn't receive settings
+        incoming_req
+            .shared_state()
+            .write("server")
+            .peer_max_field_section_size = u64::MAX;
+
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(200)
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .expect("send_response");
+
+        request_stream
+            .send_data("wonderful hypertext".into())
+            .await
+            .expect("send_data");
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("trailer", "value".repeat(100).parse().unwrap());
+        request_stream
+            .send_trailers(trailers)
+            .await
+            .expect("send_trailers");
+        request_stream.finish().await.expect("finish");
+
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn header_too_big_server_error() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await) // header size limit faked for brevity
+            .await
+            .expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let req = Request::get("http://localhost/salut").body(()).unwrap();
+            let _ = client
+                .send_request(req)
+                .await
+                .unwrap()
+                .recv_response()
+                .await;
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
+        //= type=test
+        //# An implementation that
+        //# has received this parameter SHOULD NOT send an HTTP message header
+        //# that exceeds the indicated size, as the peer will likely refuse to
+        //# process it.
+
+        // pretend the server received a smaller max_field_section_size
+        incoming_req
+            .shared_state()
+            .write("server")
+            .peer_max_field_section_size = 12;
+
+        let err_kind = request_stream
+            .send_response(
+                Response::builder()
+                    .status(200)
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .map(|_| ())
+            .unwrap_err()
+            .kind();
+
+        assert_matches!(
+            err_kind,
+            Kind::HeaderTooBig {
+                actual_size: 42,
+                max_size: 12,
+                ..
+            }
+        );
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn header_too_big_server_error_trailers() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await) // header size limit faked for brevity
+            .await
+            .expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let req = Request::get("http://localhost/salut").body(()).unwrap();
+            let _ = client
+                .send_request(req)
+                .await
+                .unwrap()
+                .recv_response()
+                .await;
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(200)
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .unwrap();
+        request_stream
+            .send_data("wonderful hypertext".into())
+            .await
+            .expect("send_data");
+
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
+        //= type=test
+        //# An implementation that
+        //# has received this parameter SHOULD NOT send an HTTP message header
+        //# that exceeds the indicated size, as the peer will likely refuse to
+        //# process it.
+
+        // pretend the server already received client's settings
+        incoming_req
+            .shared_state()
+            .write("write")
+            .peer_max_field_section_size = 200;
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("trailer", "value".repeat(100).parse().unwrap());
+        let err_kind = request_stream
+            .send_trailers(trailers)
+            .await
+            .unwrap_err()
+            .kind();
+        assert_matches!(
+            err_kind,
+            Kind::HeaderTooBig {
+                actual_size: 539,
+                max_size: 200,
+                ..
+            }
+        );
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn get_timeout_client_recv_response() {
+    init_tracing();
+    let mut pair = Pair::default();
+    pair.with_timeout(Duration::from_millis(100));
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut conn, mut client) = client::new(pair.client().await).await.expect("client init");
+        let request_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+
+            let response = request_stream.recv_response().await;
+            assert_matches!(response.unwrap_err().kind(), Kind::Timeout);
+        };
+
+        let drive_fut = async move {
+            let result = future::poll_fn(|cx| conn.poll_close(cx)).await;
+            assert_matches!(result.unwrap_err().kind(), Kind::Timeout);
+        };
+
+        tokio::join!(drive_fut, request_fut);
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        // _req must not be dropped, else the connection will be closed and the timeout
+        // wont be triggered
+        let _req = incoming_req.accept().await.expect("accept").unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn get_timeout_client_recv_data() {
+    init_tracing();
+    let mut pair = Pair::default();
+    pair.with_timeout(Duration::from_millis(200));
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut conn, mut client) = client::new(pair.client().await).await.expect("client init");
+        let request_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+
+            let _ = request_stream.recv_response().await.unwrap();
+            let data = request_stream.recv_data().await;
+            assert_matches!(data.map(|_| ()).unwrap_err().kind(), Kind::Timeout);
+        };
+
+        let drive_fut = async move {
+            let result = future::poll_fn(|cx| conn.poll_close(cx)).await;
+            assert_matches!(result.unwrap_err().kind(), Kind::Timeout);
+        };
+
+        tokio::join!(drive_fut, request_fut);
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(200)
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .expect("send_response");
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn get_timeout_server_accept() {
+    init_tracing();
+    let mut pair = Pair::default();
+    pair.with_timeout(Duration::from_millis(200));
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut conn, _client) = client::new(pair.client().await).await.expect("client init");
+        let request_fut = async {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        };
+
+        let drive_fut = async move {
+            let result = future::poll_fn(|cx| conn.poll_close(cx)).await;
+            assert_matches!(result.unwrap_err().kind(), Kind::Timeout);
+        };
+
+        tokio::join!(drive_fut, request_fut);
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        assert_matches!(
+            incoming_req.accept().await.map(|_| ()).unwrap_err().kind(),
+            Kind::Timeout
+        );
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn post_timeout_server_recv_data() {
+    init_tracing();
+    let mut pair = Pair::default();
+    pair.with_timeout(Duration::from_millis(100));
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (_conn, mut client) = client::new(pair.client().await).await.expect("client init");
+        let _request_stream = client
+            .send_request(Request::post("http://localhost/salut").body(()).unwrap())
+            .await
+            .expect("request");
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
 
         let (_, mut req_stream) = incoming_req.accept().await.expect("accept").unwrap();
         assert_matches!(
             req_stream.recv_data().await.map(|_| ()).unwrap_err().kind(),
             Kind::Timeout
         );
-    };
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+// 4.1. HTTP Message Exchanges
+
+// An HTTP message (request or response) consists of:
+// * the header section, sent as a single HEADERS frame (see Section 7.2.2),
+// * optionally, the content, if present, sent as a series of DATA frames (see Section 7.2.1),
+// * and optionally, the trailer section, if present, sent as a single HEADERS frame.
+
+#[tokio::test]
+async fn request_valid_one_header() {
+    request_sequence_ok(|mut buf| {
+        request_encode(
+            &mut buf,
+            Request::post("http://localhost/salut").body(()).unwrap(),
+        );
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn request_valid_header_data() {
+    request_sequence_ok(|mut buf| {
+        request_encode(
+            &mut buf,
+            Request::post("http://localhost/salut").body(()).unwrap(),
+        );
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+    })
+    .await;
+}
 
-    tokio::join!(server_fut, client_fut);
+#[tokio::test]
+async fn request_valid_header_data_trailer() {
+    request_sequence_ok(|mut buf| {
+        request_encode(
+            &mut buf,
+            Request::post("http://localhost/salut").body(()).unwrap(),
+        );
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+        let mut trailers = HeaderMap::new();
+        trailers.insert("trailer", "value".parse().unwrap());
+        trailers_encode(buf, trailers);
+    })
+    .await;
 }
 
-// 4.1. HTTP Message Exchanges
+#[tokio::test]
+async fn request_valid_header_multiple_data_trailer() {
+    request_sequence_ok(|mut buf| {
+        request_encode(
+            &mut buf,
+            Request::post("http://localhost/salut").body(()).unwrap(),
+        );
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+        let mut trailers = HeaderMap::new();
+        trailers.insert("trailer", "value".parse().unwrap());
+        trailers_encode(buf, trailers);
+    })
+    .await;
+}
 
-// An HTTP message (request or response) consists of:
-// * the header section, sent as a single HEADERS frame (see Section 7.2.2),
-// * optionally, the content, if present, sent as a series of DATA frames (see Section 7.2.1),
-// * and optionally, the trailer section, if present, sent as a single HEADERS frame.
+#[tokio::test]
+async fn request_valid_header_trailer() {
+    request_sequence_ok(|mut buf| {
+        request_encode(
+            &mut buf,
+            Request::post("http://localhost/salut").body(()).unwrap(),
+        );
+        let mut trailers = HeaderMap::new();
+        trailers.insert("trailer", "value".parse().unwrap());
+        trailers_encode(buf, trailers);
+    })
+    .await;
+}
+
+// Frames of unknown types (Section 9), including reserved frames (Section
+// 7.2.8) MAY be sent on a request or push stream before, after, or interleaved
+// with other frames described in this section.
 
 #[tokio::test]
-async fn request_valid_one_header() {
+async fn request_valid_unkown_frame_before() {
     request_sequence_ok(|mut buf| {
+        unknown_frame_encode(buf);
         request_encode(
             &mut buf,
             Request::post("http://localhost/salut").body(()).unwrap(),
@@ -971,19 +3615,76 @@ async fn request_valid_one_header() {
 }
 
 #[tokio::test]
-async fn request_valid_header_data() {
+async fn request_valid_unkown_frame_after_one_header() {
+    request_sequence_ok(|mut buf| {
+        request_encode(
+            &mut buf,
+            Request::post("http://localhost/salut").body(()).unwrap(),
+        );
+        unknown_frame_encode(buf);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn request_valid_unkown_frame_interleaved_after_header() {
     request_sequence_ok(|mut buf| {
         request_encode(
             &mut buf,
             Request::post("http://localhost/salut").body(()).unwrap(),
         );
+        unknown_frame_encode(buf);
         Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
     })
     .await;
 }
 
 #[tokio::test]
-async fn request_valid_header_data_trailer() {
+async fn request_valid_unkown_frame_interleaved_between_data() {
+    request_sequence_ok(|mut buf| {
+        request_encode(
+            &mut buf,
+            Request::post("http://localhost/salut").body(()).unwrap(),
+        );
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+        unknown_frame_encode(buf);
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn request_valid_unkown_frame_interleaved_after_data() {
+    request_sequence_ok(|mut buf| {
+        request_encode(
+            &mut buf,
+            Request::post("http://localhost/salut").body(()).unwrap(),
+        );
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+        unknown_frame_encode(buf);
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn request_valid_unkown_frame_interleaved_before_trailers() {
+    request_sequence_ok(|mut buf| {
+        request_encode(
+            &mut buf,
+            Request::post("http://localhost/salut").body(()).unwrap(),
+        );
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+        unknown_frame_encode(buf);
+        let mut trailers = HeaderMap::new();
+        trailers.insert("trailer", "value".parse().unwrap());
+        trailers_encode(buf, trailers);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn request_valid_unkown_frame_after_trailers() {
     request_sequence_ok(|mut buf| {
         request_encode(
             &mut buf,
@@ -993,34 +3694,178 @@ async fn request_valid_header_data_trailer() {
         let mut trailers = HeaderMap::new();
         trailers.insert("trailer", "value".parse().unwrap());
         trailers_encode(buf, trailers);
+        unknown_frame_encode(buf);
+    })
+    .await;
+}
+
+//= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
+//= type=test
+//# Receipt of an invalid sequence of frames MUST be treated as a
+//# connection error of type H3_FRAME_UNEXPECTED.
+fn invalid_request_frames() -> Vec<Frame<Bytes>> {
+    vec![
+        Frame::CancelPush(PushId(0)),
+        Frame::Settings(frame::Settings::default()),
+        Frame::Goaway(VarInt(1)),
+        Frame::MaxPushId(PushId(1)),
+    ]
+}
+
+#[tokio::test]
+async fn request_invalid_frame_first() {
+    //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.3
+    //= type=test
+    //# Receiving a
+    //# CANCEL_PUSH frame on a stream other than the control stream MUST be
+    //# treated as a connection error of type H3_FRAME_UNEXPECTED.
+
+    //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.4
+    //= type=test
+    //# If an endpoint receives a SETTINGS frame on a different
+    //# stream, the endpoint MUST respond with a connection error of type
+    //# H3_FRAME_UNEXPECTED.
+
+    //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.6
+    //= type=test
+    //# A client MUST treat a GOAWAY frame on a stream other than
+    //# the control stream as a connection error of type H3_FRAME_UNEXPECTED.
+
+    //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.7
+    //= type=test
+    //# The MAX_PUSH_ID frame is always sent on the control stream.  Receipt
+    //# of a MAX_PUSH_ID frame on any other stream MUST be treated as a
+    //# connection error of type H3_FRAME_UNEXPECTED.
+    for frame in invalid_request_frames() {
+        request_sequence_unexpected(|mut buf| frame.encode(&mut buf)).await;
+    }
+}
+
+#[tokio::test]
+async fn request_invalid_frame_after_header() {
+    for frame in invalid_request_frames() {
+        request_sequence_unexpected(|mut buf| {
+            request_encode(
+                &mut buf,
+                Request::post("http://localhost/salut").body(()).unwrap(),
+            );
+            frame.encode(&mut buf);
+        })
+        .await;
+    }
+}
+
+#[tokio::test]
+async fn request_invalid_frame_after_data() {
+    for frame in invalid_request_frames() {
+        request_sequence_unexpected(|mut buf| {
+            request_encode(
+                &mut buf,
+                Request::post("http://localhost/salut").body(()).unwrap(),
+            );
+            Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+            frame.encode(&mut buf);
+        })
+        .await;
+    }
+}
+
+#[tokio::test]
+async fn request_invalid_frame_after_trailers() {
+    for frame in invalid_request_frames() {
+        request_sequence_unexpected(|mut buf| {
+            request_encode(
+                &mut buf,
+                Request::post("http://localhost/salut").body(()).unwrap(),
+            );
+            Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+            let mut trailers = HeaderMap::new();
+            trailers.insert("trailer", "value".parse().unwrap());
+            trailers_encode(buf, trailers);
+            frame.encode(&mut buf);
+        })
+        .await;
+    }
+}
+
+#[tokio::test]
+async fn request_invalid_data_after_trailers() {
+    request_sequence_unexpected(|mut buf| {
+        request_encode(
+            &mut buf,
+            Request::post("http://localhost/salut").body(()).unwrap(),
+        );
+        let mut trailers = HeaderMap::new();
+        trailers.insert("trailer", "value".parse().unwrap());
+        trailers_encode(buf, trailers);
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn request_invalid_data_first() {
+    request_sequence_unexpected(|mut buf| {
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn request_invalid_two_trailers() {
+    request_sequence_unexpected(|mut buf| {
+        request_encode(
+            &mut buf,
+            Request::post("http://localhost/salut").body(()).unwrap(),
+        );
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+        let mut trailers = HeaderMap::new();
+        trailers.insert("trailer", "value".parse().unwrap());
+        trailers_encode(buf, trailers.clone());
+        trailers_encode(buf, trailers);
     })
     .await;
 }
 
 #[tokio::test]
-async fn request_valid_header_multiple_data_trailer() {
-    request_sequence_ok(|mut buf| {
+async fn request_invalid_trailing_byte() {
+    request_sequence_frame_error(|mut buf| {
         request_encode(
             &mut buf,
             Request::post("http://localhost/salut").body(()).unwrap(),
         );
         Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
-        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
-        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
         let mut trailers = HeaderMap::new();
         trailers.insert("trailer", "value".parse().unwrap());
         trailers_encode(buf, trailers);
+
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-7.1
+        //= type=test
+        //# A frame payload that contains additional bytes
+        //# after the identified fields or a frame payload that terminates before
+        //# the end of the identified fields MUST be treated as a connection
+        //# error of type H3_FRAME_ERROR.
+        buf.put_u8(255);
     })
     .await;
 }
 
 #[tokio::test]
-async fn request_valid_header_trailer() {
-    request_sequence_ok(|mut buf| {
+async fn request_invalid_data_frame_length_too_large() {
+    request_sequence_frame_error(|mut buf| {
         request_encode(
             &mut buf,
             Request::post("http://localhost/salut").body(()).unwrap(),
         );
+        FrameType::DATA.encode(&mut buf);
+
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-7.1
+        //= type=test
+        //# In particular, redundant length
+        //# encodings MUST be verified to be self-consistent; see Section 10.8.
+        VarInt::from(5u32).encode(&mut buf);
+        buf.put_slice(b"fada");
+
         let mut trailers = HeaderMap::new();
         trailers.insert("trailer", "value".parse().unwrap());
         trailers_encode(buf, trailers);
@@ -1028,203 +3873,519 @@ async fn request_valid_header_trailer() {
     .await;
 }
 
-// Frames of unknown types (Section 9), including reserved frames (Section
-// 7.2.8) MAY be sent on a request or push stream before, after, or interleaved
-// with other frames described in this section.
-
 #[tokio::test]
-async fn request_valid_unkown_frame_before() {
-    request_sequence_ok(|mut buf| {
-        unknown_frame_encode(buf);
+async fn request_invalid_data_frame_length_too_short() {
+    request_sequence_frame_error(|mut buf| {
         request_encode(
             &mut buf,
             Request::post("http://localhost/salut").body(()).unwrap(),
         );
+        FrameType::DATA.encode(&mut buf);
+
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-7.1
+        //= type=test
+        //# In particular, redundant length
+        //# encodings MUST be verified to be self-consistent; see Section 10.8.
+        VarInt::from(3u32).encode(&mut buf);
+        buf.put_slice(b"fada");
     })
     .await;
 }
 
 #[tokio::test]
-async fn request_valid_unkown_frame_after_one_header() {
-    request_sequence_ok(|mut buf| {
-        request_encode(
-            &mut buf,
-            Request::post("http://localhost/salut").body(()).unwrap(),
-        );
-        unknown_frame_encode(buf);
-    })
-    .await;
+async fn request_headers_one_byte_at_a_time() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+    let mut headers = HeaderMap::new();
+    headers.insert("x-padding", "a".repeat(200).parse().unwrap());
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+        let (mut req_send, mut req_recv) = connection.open_bi().await.unwrap();
+
+        let request = Request::get("http://localhost/salut").body(()).unwrap();
+        let (parts, _) = request.into_parts();
+        let mut block = BytesMut::new();
+        qpack::encode_stateless(
+            &mut block,
+            Header::request(parts.method, parts.uri, headers.clone()).unwrap(),
+        )
+        .unwrap();
+        let mut buf = BytesMut::new();
+        Frame::headers(block.freeze()).encode_with_payload(&mut buf);
+        for byte in buf.chunks(1) {
+            req_send.write_all(byte).await.unwrap();
+            // Let each byte go out in its own packet
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        req_send.finish().await.unwrap();
+
+        let mut response = [0; 1];
+        req_recv.read(&mut response).await.unwrap();
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+        let (request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        assert_eq!(request.uri().path(), "/salut");
+        assert_eq!(request.headers()["x-padding"], headers["x-padding"]);
+        assert!(request_stream
+            .recv_data()
+            .await
+            .expect("recv data")
+            .is_none());
+        request_stream
+            .send_response(Response::builder().status(200).body(()).unwrap())
+            .await
+            .expect("send_response");
+        request_stream.finish().await.expect("finish");
+    };
+
+    tokio::join!(server_fut, client_fut);
 }
 
 #[tokio::test]
-async fn request_valid_unkown_frame_interleaved_after_header() {
-    request_sequence_ok(|mut buf| {
-        request_encode(
-            &mut buf,
-            Request::post("http://localhost/salut").body(()).unwrap(),
-        );
-        unknown_frame_encode(buf);
-        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+async fn request_invalid_qpack_static_index() {
+    request_sequence_decompression_failed(|mut buf| {
+        // Required Insert Count and Base of 0, then an indexed field line
+        // referencing static entry 99, past the end of the table.
+        Frame::headers(&[0x00, 0x00, 0xff, 0x24][..]).encode_with_payload(&mut buf);
     })
     .await;
 }
 
 #[tokio::test]
-async fn request_valid_unkown_frame_interleaved_between_data() {
-    request_sequence_ok(|mut buf| {
-        request_encode(
-            &mut buf,
-            Request::post("http://localhost/salut").body(()).unwrap(),
-        );
-        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
-        unknown_frame_encode(buf);
-        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+async fn request_empty_headers_frame() {
+    request_sequence_decompression_failed(|mut buf| {
+        // Not even the Required Insert Count and Base prefix
+        Frame::headers(&[][..]).encode_with_payload(&mut buf);
     })
     .await;
 }
 
 #[tokio::test]
-async fn request_valid_unkown_frame_interleaved_after_data() {
-    request_sequence_ok(|mut buf| {
-        request_encode(
-            &mut buf,
-            Request::post("http://localhost/salut").body(()).unwrap(),
-        );
-        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
-        unknown_frame_encode(buf);
-        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+async fn request_invalid_qpack_dynamic_reference() {
+    request_sequence_decompression_failed(|mut buf| {
+        // Required Insert Count and Base of 0, then an indexed field line
+        // referencing dynamic entry 0.
+
+        //= https://www.rfc-editor.org/rfc/rfc9204#section-2.2.3
+        //= type=test
+        //# If the decoder encounters a reference in a field line representation
+        //# to a dynamic table entry that has already been evicted or that has an
+        //# absolute index greater than or equal to the declared Required Insert
+        //# Count (Section 4.5.1), it MUST treat this as a connection error of
+        //# type QPACK_DECOMPRESSION_FAILED.
+        Frame::headers(&[0x00, 0x00, 0x80][..]).encode_with_payload(&mut buf);
     })
     .await;
 }
 
+//= https://www.rfc-editor.org/rfc/rfc9114#section-4.1.2
+//= type=test
+//# Malformed requests or responses that are
+//# detected MUST be treated as a stream error of type H3_MESSAGE_ERROR.
 #[tokio::test]
-async fn request_valid_unkown_frame_interleaved_before_trailers() {
-    request_sequence_ok(|mut buf| {
-        request_encode(
-            &mut buf,
-            Request::post("http://localhost/salut").body(()).unwrap(),
-        );
-        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
-        unknown_frame_encode(buf);
-        let mut trailers = HeaderMap::new();
-        trailers.insert("trailer", "value".parse().unwrap());
-        trailers_encode(buf, trailers);
-    })
-    .await;
+async fn response_status_0() {
+    response_status_malformed("0").await;
 }
 
 #[tokio::test]
-async fn request_valid_unkown_frame_after_trailers() {
-    request_sequence_ok(|mut buf| {
-        request_encode(
-            &mut buf,
-            Request::post("http://localhost/salut").body(()).unwrap(),
+async fn response_status_99() {
+    response_status_malformed("99").await;
+}
+
+#[tokio::test]
+async fn response_status_1000() {
+    response_status_malformed("1000").await;
+}
+
+#[tokio::test]
+async fn push_promise_interleaved() {
+    let promises = push_promise_response(Some(PushId(1)))
+        .await
+        .expect("response");
+    let promised: Vec<_> = promises
+        .iter()
+        .map(|(id, req)| (*id, req.uri().path().to_string()))
+        .collect();
+    assert_eq!(
+        promised,
+        [
+            (PushId(0), "/style.css".to_string()),
+            (PushId(1), "/script.js".to_string())
+        ]
+    );
+    assert_eq!(promises[0].1.method(), Method::GET);
+}
+
+#[tokio::test]
+async fn concurrent_pushes() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let server = pair.server_inner();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        driver.allow_push(PushId(1)).await.expect("allow push");
+
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+            request_stream.recv_response().await.expect("recv response");
+            while request_stream
+                .recv_data()
+                .await
+                .expect("recv data")
+                .is_some()
+            {}
+            request_stream.take_push_promises()
+        };
+        let push_fut = async {
+            let mut pushes = Vec::new();
+            for _ in 0..2 {
+                pushes.push(driver.accept_push().await.expect("accept").expect("push"));
+            }
+            // Both pushed responses are read at the same time
+            future::join_all(pushes.into_iter().map(|(push_id, mut stream)| async move {
+                stream.recv_response().await.expect("recv pushed response");
+                let mut body = BytesMut::new();
+                while let Some(mut chunk) = stream.recv_data().await.expect("recv pushed data") {
+                    body.put(chunk.copy_to_bytes(chunk.remaining()));
+                }
+                (push_id, body.freeze())
+            }))
+            .await
+        };
+        let (promises, mut pushed) = tokio::join!(req_fut, push_fut);
+
+        pushed.sort_by_key(|(push_id, _)| *push_id);
+        let promised: Vec<_> = promises
+            .iter()
+            .map(|(push_id, req)| (*push_id, req.uri().path().to_string()))
+            .collect();
+        assert_eq!(
+            promised,
+            [
+                (PushId(0), "/style.css".to_string()),
+                (PushId(1), "/script.js".to_string())
+            ]
         );
-        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
-        let mut trailers = HeaderMap::new();
-        trailers.insert("trailer", "value".parse().unwrap());
-        trailers_encode(buf, trailers);
-        unknown_frame_encode(buf);
-    })
-    .await;
+        assert_eq!(
+            pushed,
+            [
+                (PushId(0), Bytes::from("style")),
+                (PushId(1), Bytes::from("script"))
+            ]
+        );
+    };
+
+    let server_fut = async {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let mut control_stream = conn.open_uni().await.unwrap();
+        let mut buf = BytesMut::new();
+        StreamType::CONTROL.encode(&mut buf);
+        Frame::<Bytes>::Settings(Settings::default()).encode(&mut buf);
+        control_stream.write_all(&buf[..]).await.unwrap();
+
+        let (mut send, _recv) = conn.accept_bi().await.unwrap();
+        let mut buf = BytesMut::new();
+        response_encode(&mut buf, StatusCode::OK);
+        push_promise_encode(&mut buf, PushId(0), "/style.css");
+        push_promise_encode(&mut buf, PushId(1), "/script.js");
+        send.write_all(&buf[..]).await.unwrap();
+        send.finish().await.unwrap();
+
+        // Interleave the pushed responses
+        let mut pushes = Vec::new();
+        for push_id in [PushId(0), PushId(1)] {
+            let mut push_stream = conn.open_uni().await.unwrap();
+            let mut buf = BytesMut::new();
+            StreamType::PUSH.encode(&mut buf);
+            VarInt::from(push_id).encode(&mut buf);
+            response_encode(&mut buf, StatusCode::OK);
+            push_stream.write_all(&buf[..]).await.unwrap();
+            pushes.push(push_stream);
+        }
+        for (push_stream, body) in pushes.iter_mut().rev().zip(["script", "style"]) {
+            let mut buf = BytesMut::new();
+            Frame::Data(Bytes::from(body)).encode_with_payload(&mut buf);
+            push_stream.write_all(&buf[..]).await.unwrap();
+            push_stream.finish().await.unwrap();
+        }
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
 }
 
-//= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
-//= type=test
-//# Receipt of an invalid sequence of frames MUST be treated as a
-//# connection error of type H3_FRAME_UNEXPECTED.
-fn invalid_request_frames() -> Vec<Frame<Bytes>> {
-    vec![
-        Frame::CancelPush(PushId(0)),
-        Frame::Settings(frame::Settings::default()),
-        Frame::Goaway(VarInt(1)),
-        Frame::MaxPushId(PushId(1)),
-    ]
+#[tokio::test]
+async fn reject_push() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let server = pair.server_inner();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        driver.allow_push(PushId(1)).await.expect("allow push");
+
+        let mut request_stream = client
+            .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+            .await
+            .expect("request");
+        request_stream.finish().await.expect("client finish");
+        request_stream.recv_response().await.expect("recv response");
+        while request_stream
+            .recv_data()
+            .await
+            .expect("recv data")
+            .is_some()
+        {}
+        assert_eq!(request_stream.take_push_promises().len(), 1);
+
+        assert!(driver
+            .reject_push(PushId(1), Code::H3_REQUEST_CANCELLED)
+            .await
+            .is_err());
+        driver
+            .reject_push(PushId(0), Code::H3_REQUEST_CANCELLED)
+            .await
+            .expect("reject push");
+        future::poll_fn(|cx| driver.poll_close(cx)).await
+    };
+
+    let server_fut = async {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let mut control_stream = conn.open_uni().await.unwrap();
+        let mut buf = BytesMut::new();
+        StreamType::CONTROL.encode(&mut buf);
+        Frame::<Bytes>::Settings(Settings::default()).encode(&mut buf);
+        control_stream.write_all(&buf[..]).await.unwrap();
+
+        let (mut send, _recv) = conn.accept_bi().await.unwrap();
+        let mut buf = BytesMut::new();
+        response_encode(&mut buf, StatusCode::OK);
+        push_promise_encode(&mut buf, PushId(0), "/style.css");
+        send.write_all(&buf[..]).await.unwrap();
+        send.finish().await.unwrap();
+
+        let mut cancel_push = BytesMut::new();
+        Frame::<Bytes>::CancelPush(PushId(0)).encode(&mut cancel_push);
+        loop {
+            let mut recv = conn.accept_uni().await.unwrap();
+            let mut received = BytesMut::new();
+            while let Some(chunk) = recv.read_chunk(usize::MAX, true).await.unwrap() {
+                received.put(chunk.bytes);
+                if received[0] != StreamType::CONTROL.value() as u8 {
+                    break;
+                }
+                if received
+                    .windows(cancel_push.len())
+                    .any(|w| w == cancel_push)
+                {
+                    return;
+                }
+            }
+        }
+    };
+
+    tokio::select! { _ = server_fut => (), res = client_fut => panic!("client resolved first: {:?}", res) };
 }
 
 #[tokio::test]
-async fn request_invalid_frame_first() {
-    //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.3
-    //= type=test
-    //# Receiving a
-    //# CANCEL_PUSH frame on a stream other than the control stream MUST be
-    //# treated as a connection error of type H3_FRAME_UNEXPECTED.
+async fn initial_max_push_id() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let server = pair.server_inner();
+    let (id_send, id_recv) = tokio::sync::oneshot::channel();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::builder()
+            .initial_max_push_id(PushId(1))
+            .build::<_, _, Bytes>(pair.client().await)
+            .await
+            .expect("client init");
+
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+            request_stream.recv_response().await.expect("recv response");
+            while request_stream
+                .recv_data()
+                .await
+                .expect("recv data")
+                .is_some()
+            {}
+            request_stream.take_push_promises()
+        };
+        let push_fut = async {
+            let (push_id, mut stream) = driver.accept_push().await.expect("accept").expect("push");
+            let resp = stream.recv_response().await.expect("recv pushed response");
+            (push_id, stream.id(), resp.status())
+        };
+        let (promises, pushed) = tokio::join!(req_fut, push_fut);
+
+        assert_eq!(promises.len(), 1);
+        assert_eq!(promises[0].0, PushId(1));
+        // The ID given by the transport, as the server opened it
+        let push_stream_id = StreamId::from(VarInt::from_u64(id_recv.await.unwrap()).unwrap());
+        assert_eq!(pushed, (PushId(1), push_stream_id, StatusCode::OK));
+    };
+
+    let server_fut = async {
+        let conn = server.accept().await.unwrap().await.unwrap();
+
+        // MAX_PUSH_ID follows the client SETTINGS, before any request
+        let mut max_push_id = BytesMut::new();
+        Frame::<Bytes>::MaxPushId(PushId(1)).encode(&mut max_push_id);
+        'control: loop {
+            let mut recv = conn.accept_uni().await.unwrap();
+            let mut received = BytesMut::new();
+            while let Some(chunk) = recv.read_chunk(usize::MAX, true).await.unwrap() {
+                received.put(chunk.bytes);
+                if received[0] != StreamType::CONTROL.value() as u8 {
+                    break;
+                }
+                let mut control = &received[1..];
+                if let Ok(Frame::Settings(_)) = Frame::<PayloadLen>::decode(&mut control) {
+                    if control.starts_with(&max_push_id) {
+                        break 'control;
+                    }
+                }
+            }
+        }
+
+        let mut control_stream = conn.open_uni().await.unwrap();
+        let mut buf = BytesMut::new();
+        StreamType::CONTROL.encode(&mut buf);
+        Frame::<Bytes>::Settings(Settings::default()).encode(&mut buf);
+        control_stream.write_all(&buf[..]).await.unwrap();
 
-    //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.4
-    //= type=test
-    //# If an endpoint receives a SETTINGS frame on a different
-    //# stream, the endpoint MUST respond with a connection error of type
-    //# H3_FRAME_UNEXPECTED.
+        let (mut send, _recv) = conn.accept_bi().await.unwrap();
+        let mut buf = BytesMut::new();
+        response_encode(&mut buf, StatusCode::OK);
+        push_promise_encode(&mut buf, PushId(1), "/style.css");
+        send.write_all(&buf[..]).await.unwrap();
+        send.finish().await.unwrap();
 
-    //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.6
-    //= type=test
-    //# A client MUST treat a GOAWAY frame on a stream other than
-    //# the control stream as a connection error of type H3_FRAME_UNEXPECTED.
+        let mut push_stream = conn.open_uni().await.unwrap();
+        id_send.send(push_stream.id().0).unwrap();
+        let mut buf = BytesMut::new();
+        StreamType::PUSH.encode(&mut buf);
+        VarInt::from(PushId(1)).encode(&mut buf);
+        response_encode(&mut buf, StatusCode::OK);
+        push_stream.write_all(&buf[..]).await.unwrap();
+        push_stream.finish().await.unwrap();
 
-    //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.7
-    //= type=test
-    //# The MAX_PUSH_ID frame is always sent on the control stream.  Receipt
-    //# of a MAX_PUSH_ID frame on any other stream MUST be treated as a
-    //# connection error of type H3_FRAME_UNEXPECTED.
-    for frame in invalid_request_frames() {
-        request_sequence_unexpected(|mut buf| frame.encode(&mut buf)).await;
-    }
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
 }
 
 #[tokio::test]
-async fn request_invalid_frame_after_header() {
-    for frame in invalid_request_frames() {
-        request_sequence_unexpected(|mut buf| {
-            request_encode(
-                &mut buf,
-                Request::post("http://localhost/salut").body(()).unwrap(),
-            );
-            frame.encode(&mut buf);
-        })
-        .await;
-    }
+async fn push_promise_above_max_push_id() {
+    let err = push_promise_response(Some(PushId(0))).await.unwrap_err();
+    assert_matches!(
+        err.kind(),
+        Kind::Application {
+            code: Code::H3_ID_ERROR,
+            ..
+        }
+    );
 }
 
 #[tokio::test]
-async fn request_invalid_frame_after_data() {
-    for frame in invalid_request_frames() {
-        request_sequence_unexpected(|mut buf| {
-            request_encode(
-                &mut buf,
-                Request::post("http://localhost/salut").body(()).unwrap(),
+async fn push_promise_without_max_push_id() {
+    let err = push_promise_response(None).await.unwrap_err();
+    assert_matches!(
+        err.kind(),
+        Kind::Application {
+            code: Code::H3_ID_ERROR,
+            ..
+        }
+    );
+}
+
+// Helpers
+
+async fn response_status_malformed(status: &'static str) {
+    init_tracing();
+    let mut pair = Pair::default();
+    let server = pair.server_inner();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+            let err = request_stream.recv_response().await.unwrap_err();
+            assert_matches!(
+                err.kind(),
+                Kind::Application {
+                    code: Code::H3_MESSAGE_ERROR,
+                    ..
+                }
             );
-            Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
-            frame.encode(&mut buf);
-        })
-        .await;
-    }
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") };
+    };
+
+    let server_fut = async {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let mut control_stream = conn.open_uni().await.unwrap();
+        let mut buf = BytesMut::new();
+        StreamType::CONTROL.encode(&mut buf);
+        Frame::<Bytes>::Settings(Settings::default()).encode(&mut buf);
+        control_stream.write_all(&buf[..]).await.unwrap();
+
+        let (mut send, _recv) = conn.accept_bi().await.unwrap();
+        let mut block = BytesMut::new();
+        qpack::encode_stateless(&mut block, [qpack::HeaderField::new(":status", status)]).unwrap();
+        let mut buf = BytesMut::new();
+        Frame::headers(block).encode_with_payload(&mut buf);
+        send.write_all(&buf[..]).await.unwrap();
+        send.finish().await.unwrap();
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
 }
 
 #[tokio::test]
-async fn request_invalid_frame_after_trailers() {
-    for frame in invalid_request_frames() {
-        request_sequence_unexpected(|mut buf| {
-            request_encode(
-                &mut buf,
-                Request::post("http://localhost/salut").body(()).unwrap(),
-            );
+async fn response_valid_header_data_trailer() {
+    response_sequence_check(
+        |mut buf| {
+            response_encode(&mut buf, StatusCode::OK);
             Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
             let mut trailers = HeaderMap::new();
             trailers.insert("trailer", "value".parse().unwrap());
             trailers_encode(buf, trailers);
-            frame.encode(&mut buf);
-        })
-        .await;
-    }
+        },
+        |trailers| assert_eq!(trailers.unwrap().unwrap()["trailer"], "value"),
+    )
+    .await;
 }
 
 #[tokio::test]
-async fn request_invalid_data_after_trailers() {
-    request_sequence_unexpected(|mut buf| {
-        request_encode(
-            &mut buf,
-            Request::post("http://localhost/salut").body(()).unwrap(),
-        );
+async fn response_invalid_data_after_trailers() {
+    response_sequence_unexpected(|mut buf| {
+        response_encode(&mut buf, StatusCode::OK);
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
         let mut trailers = HeaderMap::new();
         trailers.insert("trailer", "value".parse().unwrap());
         trailers_encode(buf, trailers);
@@ -1234,95 +4395,204 @@ async fn request_invalid_data_after_trailers() {
 }
 
 #[tokio::test]
-async fn request_invalid_data_first() {
-    request_sequence_unexpected(|mut buf| {
+async fn response_invalid_two_trailers() {
+    response_sequence_unexpected(|mut buf| {
+        response_encode(&mut buf, StatusCode::OK);
         Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+        let mut trailers = HeaderMap::new();
+        trailers.insert("trailer", "value".parse().unwrap());
+        trailers_encode(buf, trailers.clone());
+        trailers_encode(buf, trailers);
     })
     .await;
 }
 
 #[tokio::test]
-async fn request_invalid_two_trailers() {
-    request_sequence_unexpected(|mut buf| {
-        request_encode(
-            &mut buf,
-            Request::post("http://localhost/salut").body(()).unwrap(),
-        );
+async fn response_informational_then_final() {
+    response_sequence_check(
+        |mut buf| {
+            response_encode(&mut buf, StatusCode::CONTINUE);
+            response_encode(&mut buf, StatusCode::OK);
+            Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+        },
+        |trailers| assert_matches!(trailers, Ok(None)),
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn response_invalid_data_before_headers() {
+    response_sequence_unexpected(|mut buf| {
         Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
-        let mut trailers = HeaderMap::new();
-        trailers.insert("trailer", "value".parse().unwrap());
-        trailers_encode(buf, trailers.clone());
-        trailers_encode(buf, trailers);
+        response_encode(&mut buf, StatusCode::OK);
     })
     .await;
 }
 
 #[tokio::test]
-async fn request_invalid_trailing_byte() {
-    request_sequence_frame_error(|mut buf| {
-        request_encode(
-            &mut buf,
-            Request::post("http://localhost/salut").body(()).unwrap(),
-        );
+async fn response_invalid_data_after_informational() {
+    response_sequence_unexpected(|mut buf| {
+        response_encode(&mut buf, StatusCode::CONTINUE);
         Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
-        let mut trailers = HeaderMap::new();
-        trailers.insert("trailer", "value".parse().unwrap());
-        trailers_encode(buf, trailers);
+        response_encode(&mut buf, StatusCode::OK);
+    })
+    .await;
+}
 
-        //= https://www.rfc-editor.org/rfc/rfc9114#section-7.1
+async fn response_sequence_unexpected<F>(response: F)
+where
+    F: Fn(&mut BytesMut),
+{
+    response_sequence_check(response, |trailers| {
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
         //= type=test
-        //# A frame payload that contains additional bytes
-        //# after the identified fields or a frame payload that terminates before
-        //# the end of the identified fields MUST be treated as a connection
-        //# error of type H3_FRAME_ERROR.
-        buf.put_u8(255);
+        //# Receipt of an invalid sequence of frames MUST be treated as a
+        //# connection error of type H3_FRAME_UNEXPECTED.
+        assert_matches!(
+            trailers.unwrap_err().kind(),
+            Kind::Application {
+                code: Code::H3_FRAME_UNEXPECTED,
+                ..
+            }
+        )
     })
     .await;
 }
 
-#[tokio::test]
-async fn request_invalid_data_frame_length_too_large() {
-    request_sequence_frame_error(|mut buf| {
-        request_encode(
-            &mut buf,
-            Request::post("http://localhost/salut").body(()).unwrap(),
-        );
-        FrameType::DATA.encode(&mut buf);
+/// Send the frames written by `response` on a request stream, and check the
+/// result of reading the responses, the body then the trailers with the client
+async fn response_sequence_check<F, FC>(response: F, check: FC)
+where
+    F: Fn(&mut BytesMut),
+    FC: Fn(Result<Option<HeaderMap>, Error>),
+{
+    init_tracing();
+    let mut pair = Pair::default();
+    let server = pair.server_inner();
 
-        //= https://www.rfc-editor.org/rfc/rfc9114#section-7.1
-        //= type=test
-        //# In particular, redundant length
-        //# encodings MUST be verified to be self-consistent; see Section 10.8.
-        VarInt::from(5u32).encode(&mut buf);
-        buf.put_slice(b"fada");
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
 
-        let mut trailers = HeaderMap::new();
-        trailers.insert("trailer", "value".parse().unwrap());
-        trailers_encode(buf, trailers);
-    })
-    .await;
+            // The body cannot be read ahead of the response
+            let err = request_stream.recv_data().await.map(|_| ()).unwrap_err();
+            assert_matches!(
+                err.kind(),
+                Kind::Application {
+                    code: Code::H3_INTERNAL_ERROR,
+                    level: ErrorLevel::StreamError,
+                    ..
+                }
+            );
+
+            while request_stream
+                .recv_response()
+                .await?
+                .status()
+                .is_informational()
+            {}
+            while request_stream.recv_data().await?.is_some() {}
+            request_stream.recv_trailers().await
+        };
+        tokio::select! { res = req_fut => check(res), _ = drive_fut => panic!("driver resolved first") };
+    };
+
+    let server_fut = async {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let mut control_stream = conn.open_uni().await.unwrap();
+        let mut buf = BytesMut::new();
+        StreamType::CONTROL.encode(&mut buf);
+        Frame::<Bytes>::Settings(Settings::default()).encode(&mut buf);
+        control_stream.write_all(&buf[..]).await.unwrap();
+
+        let (mut send, _recv) = conn.accept_bi().await.unwrap();
+        let mut buf = BytesMut::new();
+        response(&mut buf);
+        send.write_all(&buf[..]).await.unwrap();
+        send.finish().await.unwrap();
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
 }
 
-#[tokio::test]
-async fn request_invalid_data_frame_length_too_short() {
-    request_sequence_frame_error(|mut buf| {
-        request_encode(
-            &mut buf,
-            Request::post("http://localhost/salut").body(()).unwrap(),
-        );
-        FrameType::DATA.encode(&mut buf);
+/// Read a response carrying push promises for IDs 0 and 1, before and after
+/// its body, and return the promises received
+async fn push_promise_response(
+    max_push_id: Option<PushId>,
+) -> Result<Vec<(PushId, Request<()>)>, Error> {
+    init_tracing();
+    let mut pair = Pair::default();
+    let server = pair.server_inner();
 
-        //= https://www.rfc-editor.org/rfc/rfc9114#section-7.1
-        //= type=test
-        //# In particular, redundant length
-        //# encodings MUST be verified to be self-consistent; see Section 10.8.
-        VarInt::from(3u32).encode(&mut buf);
-        buf.put_slice(b"fada");
-    })
-    .await;
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        if let Some(max_push_id) = max_push_id {
+            driver.allow_push(max_push_id).await.expect("allow push");
+        }
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+            request_stream.recv_response().await?;
+            let mut body = BytesMut::new();
+            while let Some(mut chunk) = request_stream.recv_data().await? {
+                body.put(chunk.copy_to_bytes(chunk.remaining()));
+            }
+            assert_eq!(&body[..], b"fada");
+            request_stream.recv_trailers().await?;
+            Ok(request_stream.take_push_promises())
+        };
+        tokio::select! { res = req_fut => res, _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let mut control_stream = conn.open_uni().await.unwrap();
+        let mut buf = BytesMut::new();
+        StreamType::CONTROL.encode(&mut buf);
+        Frame::<Bytes>::Settings(Settings::default()).encode(&mut buf);
+        control_stream.write_all(&buf[..]).await.unwrap();
+
+        let (mut send, _recv) = conn.accept_bi().await.unwrap();
+        let mut buf = BytesMut::new();
+        push_promise_encode(&mut buf, PushId(0), "/style.css");
+        response_encode(&mut buf, StatusCode::OK);
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+        push_promise_encode(&mut buf, PushId(1), "/script.js");
+        send.write_all(&buf[..]).await.unwrap();
+        send.finish().await.unwrap();
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), res = client_fut => res }
 }
 
-// Helpers
+fn response_encode<B: BufMut>(buf: &mut B, status: StatusCode) {
+    let headers = Header::response(status, HeaderMap::new()).unwrap();
+    let mut block = BytesMut::new();
+    qpack::encode_stateless(&mut block, headers).unwrap();
+    Frame::headers(block).encode_with_payload(buf);
+}
+
+fn push_promise_encode<B: BufMut>(buf: &mut B, id: PushId, path: &str) {
+    let uri = format!("https://localhost{}", path).parse().unwrap();
+    let headers = Header::request(Method::GET, uri, HeaderMap::new()).unwrap();
+    let mut block = BytesMut::new();
+    qpack::encode_stateless(&mut block, headers).unwrap();
+    Frame::push_promise(id, block).encode_with_payload(buf);
+}
 
 fn request_encode<B: BufMut>(buf: &mut B, req: http::Request<()>) {
     let (parts, _) = req.into_parts();
@@ -1339,7 +4609,7 @@ fn request_encode<B: BufMut>(buf: &mut B, req: http::Request<()>) {
 }
 
 fn trailers_encode<B: BufMut>(buf: &mut B, fields: HeaderMap) {
-    let headers = Header::trailer(fields);
+    let headers = Header::trailer(fields).unwrap();
     let mut block = BytesMut::new();
     qpack::encode_stateless(&mut block, headers).unwrap();
     Frame::headers(block).encode_with_payload(buf);
@@ -1392,6 +4662,22 @@ where
     .await;
 }
 
+async fn request_sequence_decompression_failed<F>(request: F)
+where
+    F: Fn(&mut BytesMut),
+{
+    request_sequence_check(request, |err| {
+        assert_matches!(
+            err.unwrap_err().kind(),
+            Kind::Application {
+                code: Code::QPACK_DECOMPRESSION_FAILED,
+                ..
+            }
+        )
+    })
+    .await;
+}
+
 async fn request_sequence_check<F, FC>(request: F, check: FC)
 where
     F: Fn(&mut BytesMut),